@@ -0,0 +1,67 @@
+//! Claiming a session with proof of the key, see `crypto::claim`
+
+use crate::client::ShareLink;
+use crate::crypto::claim;
+use crate::crypto::types::EncryptionKey;
+use crate::server::auth::CLAIM_COOKIE;
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose, Engine};
+use reqwest::{header, StatusCode};
+use serde_json::json;
+
+#[derive(serde::Deserialize)]
+struct ChallengeResponse {
+    challenge: String,
+}
+
+/// Answer the server's challenge, returns the `Cookie` header for later requests
+pub(crate) async fn claim(
+    http: &reqwest::Client,
+    link: &ShareLink,
+    key: &EncryptionKey,
+) -> Result<String> {
+    let b64 = general_purpose::URL_SAFE_NO_PAD;
+
+    let challenge: ChallengeResponse = http
+        .post(link.endpoint("challenge"))
+        .send()
+        .await
+        .context("Failed to reach server")?
+        .error_for_status()
+        .context("Server refused a claim challenge (link expired?)")?
+        .json()
+        .await
+        .context("Invalid challenge response")?;
+
+    let challenge = b64
+        .decode(&challenge.challenge)
+        .context("Invalid challenge encoding")?;
+    let proof = claim::claim_proof(key, &link.token, &challenge);
+
+    let response = http
+        .post(link.endpoint("claim"))
+        .json(&json!({
+            "challenge": b64.encode(&challenge),
+            "proof": b64.encode(proof),
+        }))
+        .send()
+        .await
+        .context("Failed to reach server")?;
+    if response.status() == StatusCode::GONE {
+        bail!("This transfer was already downloaded and is no longer available");
+    }
+    let response = response
+        .error_for_status()
+        .context("Server rejected claim (session already claimed?)")?;
+
+    // No cookie jar, the one cookie we need is kept by hand
+    response
+        .headers()
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .filter_map(|value| value.split(';').next())
+        .find(|cookie| cookie.starts_with(&format!("{}=", CLAIM_COOKIE)))
+        .map(str::to_string)
+        .context("Server sent no claim cookie")
+}
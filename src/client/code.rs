@@ -0,0 +1,106 @@
+//! Find the session behind a transfer code
+
+use crate::client::{http, ShareLink};
+use crate::config::MDNS_SERVICE_TYPE;
+use crate::crypto::code::TransferCode;
+use crate::crypto::CipherSuite;
+use anyhow::{bail, ensure, Context, Result};
+use mdns_sd::{ServiceDaemon, ServiceEvent};
+use std::time::Duration;
+
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(serde::Deserialize)]
+struct CodeResponse {
+    path: String,
+    #[serde(default)]
+    suite: CipherSuite,
+}
+
+/// Share link for `code`, asked from `server` or looked up with mDNS
+/// The link has no key, unlock it with the code's passphrase
+/// `insecure` skips certificate checks for servers that aren't pinned
+pub async fn resolve_code(
+    code: &TransferCode,
+    service: &str,
+    server: Option<&str>,
+    insecure: bool,
+) -> Result<ShareLink> {
+    let url = match server {
+        Some(server) => ask_server(code, server, insecure).await?,
+        None => browse(code, service).await?,
+    };
+
+    let link = ShareLink::parse(&url)?.with_insecure(insecure);
+    ensure!(
+        link.service == service,
+        "Code {} belongs to a {} session",
+        code,
+        link.service
+    );
+    Ok(link)
+}
+
+// Tunnels and named hosts, same lookup the code page does
+async fn ask_server(code: &TransferCode, server: &str, insecure: bool) -> Result<String> {
+    let base = server.trim_end_matches('/');
+    let base = if base.contains("://") {
+        base.to_string()
+    } else {
+        format!("https://{}", base)
+    };
+
+    // No pin to check against, the server needs a trusted certificate
+    let response: CodeResponse = http::build_client(None, insecure)?
+        .get(format!("{}/code/{}", base, code.nameplate()))
+        .send()
+        .await
+        .context("Failed to reach server")?
+        .error_for_status()
+        .context(format!("No session with code {} on {}", code, base))?
+        .json()
+        .await
+        .context("Invalid code response")?;
+
+    Ok(format!(
+        "{}{}#pake=1&suite={}",
+        base, response.path, response.suite
+    ))
+}
+
+// Local mode servers advertise their share URL under the nameplate
+async fn browse(code: &TransferCode, service: &str) -> Result<String> {
+    let daemon = ServiceDaemon::new().context("Failed to start mDNS")?;
+    let receiver = daemon
+        .browse(MDNS_SERVICE_TYPE)
+        .context("Failed to browse for sessions")?;
+
+    let found = tokio::time::timeout(DISCOVERY_TIMEOUT, async {
+        while let Ok(event) = receiver.recv_async().await {
+            let ServiceEvent::ServiceResolved(info) = event else {
+                continue;
+            };
+            if info.get_property_val_str("code") == Some(code.nameplate())
+                && info.get_property_val_str("service") == Some(service)
+            {
+                if let Some(url) = info.get_property_val_str("url") {
+                    return Some(url.to_string());
+                }
+            }
+        }
+        None
+    })
+    .await;
+
+    if let Err(e) = daemon.shutdown() {
+        tracing::debug!("mDNS shutdown failed: {}", e);
+    }
+
+    match found {
+        Ok(Some(url)) => Ok(url),
+        _ => bail!(
+            "No session with code {} on the local network, pass --server with its address",
+            code
+        ),
+    }
+}
@@ -0,0 +1,261 @@
+//! Client for send servers (`archdrop get`)
+
+use crate::client::claim;
+use crate::client::http;
+use crate::client::pake;
+use crate::client::progress::{self, ProgressEvent, ProgressFn};
+use crate::client::ShareLink;
+use crate::config::CHUNK_SIZE;
+use crate::crypto::{
+    self,
+    kdf::{self, Direction},
+    types::{EncryptionKey, Nonce},
+};
+use crate::transfer::manifest::{FileEntry, Manifest};
+use crate::transfer::security;
+use anyhow::{bail, ensure, Context, Result};
+use base64::{engine::general_purpose, Engine};
+use futures::{StreamExt, TryStreamExt};
+use reqwest::header;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use tokio::io::AsyncWriteExt;
+
+const MAX_CONCURRENT_CHUNKS: usize = 8;
+
+// Encrypted with the session's metadata key, see `crypto::kdf`
+#[derive(serde::Deserialize)]
+struct SealedManifest {
+    manifest: String,
+}
+
+#[derive(serde::Deserialize)]
+struct HashResponse {
+    sha256: String,
+}
+
+fn check_service(link: &ShareLink) -> Result<()> {
+    ensure!(
+        link.service == "send",
+        "Not a send URL (expected /send/<token>)"
+    );
+    Ok(())
+}
+
+pub struct DownloadClient {
+    http: reqwest::Client,
+    link: ShareLink,
+    key: EncryptionKey,
+    /// `Cookie` header from the claim, sent with every later request
+    cookie: OnceLock<String>,
+    progress: ProgressFn,
+}
+
+impl DownloadClient {
+    pub fn new(link: ShareLink) -> Result<Self> {
+        check_service(&link)?;
+        let key = link.key.clone().context("This link needs a passphrase")?;
+        let http = http::build_client(link.fingerprint.as_ref(), link.insecure)?;
+        Ok(Self::build(http, link, key))
+    }
+
+    /// For links without a key, the passphrase exchange fetches it
+    pub async fn with_passphrase(link: ShareLink, passphrase: &str) -> Result<Self> {
+        check_service(&link)?;
+        let http = http::build_client(link.fingerprint.as_ref(), link.insecure)?;
+        let key = pake::unlock(&http, &link, passphrase).await?;
+        Ok(Self::build(http, link, key))
+    }
+
+    fn build(http: reqwest::Client, link: ShareLink, key: EncryptionKey) -> Self {
+        Self {
+            http,
+            link,
+            key,
+            cookie: OnceLock::new(),
+            progress: progress::noop(),
+        }
+    }
+
+    pub fn on_progress(mut self, f: impl Fn(ProgressEvent) + Send + Sync + 'static) -> Self {
+        self.progress = Arc::new(f);
+        self
+    }
+
+    // Empty before the claim, the server answers 403
+    fn cookie(&self) -> &str {
+        self.cookie.get().map(String::as_str).unwrap_or_default()
+    }
+
+    /// Prove we hold the key, then fetch the manifest
+    pub async fn claim(&self) -> Result<Manifest> {
+        let cookie = claim::claim(&self.http, &self.link, &self.key).await?;
+        // Claiming again gets the same client back, keep the first cookie
+        let _ = self.cookie.set(cookie);
+
+        let response = self
+            .http
+            .get(self.link.endpoint("manifest"))
+            .header(header::COOKIE, self.cookie())
+            .send()
+            .await
+            .context("Failed to reach server")?;
+        if response.status() == reqwest::StatusCode::GONE {
+            bail!("This transfer was already downloaded and is no longer available");
+        }
+        let sealed: SealedManifest = response
+            .error_for_status()
+            .context("Server refused the manifest")?
+            .json()
+            .await
+            .context("Invalid manifest")?;
+
+        let sealed = general_purpose::URL_SAFE_NO_PAD
+            .decode(&sealed.manifest)
+            .context("Invalid manifest encoding")?;
+        let cipher = kdf::metadata_cipher(&self.key, self.link.suite, Direction::Send);
+        let manifest: Manifest = serde_json::from_slice(
+            &crypto::open(&cipher, &sealed).context("Failed to decrypt the manifest")?,
+        )
+        .context("Invalid manifest")?;
+        ensure!(
+            manifest.suite == self.link.suite,
+            "Manifest uses {}, the link says {}",
+            manifest.suite,
+            self.link.suite
+        );
+
+        (self.progress)(ProgressEvent::Claimed {
+            files: manifest.files.len(),
+            total_bytes: manifest.files.iter().map(|f| f.size).sum(),
+        });
+
+        Ok(manifest)
+    }
+
+    /// Fetch and decrypt a single chunk, retried on failure
+    pub async fn fetch_chunk(&self, file: &FileEntry, chunk_index: u64) -> Result<Vec<u8>> {
+        let url = self
+            .link
+            .endpoint(&format!("{}/chunk/{}", file.index, chunk_index));
+
+        let encrypted = http::with_retry(&format!("chunk {}", chunk_index), || async {
+            let res = self
+                .http
+                .get(&url)
+                .header(header::COOKIE, self.cookie())
+                .send()
+                .await?
+                .error_for_status()
+                .context(format!("Failed to fetch chunk {}", chunk_index))?;
+            Ok(res.bytes().await?)
+        })
+        .await?;
+
+        let suite = self.link.suite;
+        let nonce = Nonce::from_base64(&file.nonce, suite).context("Invalid file nonce")?;
+        let cipher = kdf::file_cipher(&self.key, suite, Direction::Send, &file.index.to_string());
+        let last = chunk_index + 1 == file.size.div_ceil(CHUNK_SIZE);
+        crypto::decrypt_chunk_at_position(&cipher, &nonce, &encrypted, chunk_index as u32, last)
+    }
+
+    /// SHA-256 of the source file as computed by the server
+    pub async fn file_hash(&self, file: &FileEntry) -> Result<String> {
+        let res: HashResponse = self
+            .http
+            .get(self.link.endpoint(&format!("{}/hash", file.index)))
+            .header(header::COOKIE, self.cookie())
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(res.sha256)
+    }
+
+    /// Download one file under destination, keeping its relative path
+    pub async fn download_file(&self, file: &FileEntry, destination: &Path) -> Result<PathBuf> {
+        // Manifest comes from the network, same rules as receive mode
+        security::validate_path(&file.relative_path).context("Invalid file path")?;
+        let dest_path = destination.join(&file.relative_path);
+
+        if let Some(parent) = dest_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        (self.progress)(ProgressEvent::FileStarted {
+            relative_path: file.relative_path.clone(),
+            size: file.size,
+        });
+
+        let mut out = tokio::fs::File::create(&dest_path)
+            .await
+            .context(format!("Failed to create {}", dest_path.display()))?;
+        let mut hasher = Sha256::new();
+
+        // Fetch in parallel, buffered() keeps chunks in order for writing
+        let mut chunks = futures::stream::iter(0..file.size.div_ceil(CHUNK_SIZE))
+            .map(|chunk_index| self.fetch_chunk(file, chunk_index))
+            .buffered(MAX_CONCURRENT_CHUNKS);
+
+        while let Some(decrypted) = chunks.try_next().await? {
+            hasher.update(&decrypted);
+            out.write_all(&decrypted).await?;
+
+            (self.progress)(ProgressEvent::ChunkDone {
+                relative_path: file.relative_path.clone(),
+                bytes: decrypted.len() as u64,
+            });
+        }
+        out.flush().await?;
+
+        // Compare against the servers hash of the source file
+        let expected = self.file_hash(file).await?;
+        let computed = hex::encode(hasher.finalize());
+        if computed != expected {
+            let _ = tokio::fs::remove_file(&dest_path).await;
+            bail!(
+                "Integrity check failed: expected {}, got {}",
+                expected,
+                computed
+            );
+        }
+
+        (self.progress)(ProgressEvent::FileVerified {
+            relative_path: file.relative_path.clone(),
+            sha256: computed,
+        });
+
+        Ok(dest_path)
+    }
+
+    /// Tell the server we are done, shuts down the sender
+    pub async fn complete(&self) -> Result<()> {
+        self.http
+            .post(self.link.endpoint("complete"))
+            .header(header::COOKIE, self.cookie())
+            .send()
+            .await?
+            .error_for_status()
+            .context("Failed to mark download complete")?;
+        Ok(())
+    }
+
+    /// Claim, download every file and complete
+    pub async fn download_all(&self, destination: &Path) -> Result<Vec<PathBuf>> {
+        let manifest = self.claim().await?;
+
+        let mut written = Vec::new();
+        for file in &manifest.files {
+            let path = self
+                .download_file(file, destination)
+                .await
+                .context(format!("Failed to download {}", file.relative_path))?;
+            written.push(path);
+        }
+
+        self.complete().await?;
+        Ok(written)
+    }
+}
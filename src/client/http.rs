@@ -0,0 +1,184 @@
+use crate::crypto::types::CertFingerprint;
+use anyhow::{Context, Result};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+const MAX_RETRIES: u32 = 3;
+
+pub(crate) fn build_client(
+    pin: Option<&CertFingerprint>,
+    insecure: bool,
+) -> Result<reqwest::Client> {
+    // reqwest's own TLS setup uses the process wide provider
+    let _ = rustls::crypto::aws_lc_rs::default_provider().install_default();
+
+    let builder = reqwest::Client::builder();
+    let builder = match pin {
+        // Only the certificate from the share link is accepted
+        Some(fingerprint) => builder.use_preconfigured_tls(pinned_tls(fingerprint.clone())?),
+        // Explicit opt out for self signed servers without a pin
+        None if insecure => builder.danger_accept_invalid_certs(true),
+        // Tunnels and --tls-cert servers, checked against the WebPKI roots
+        None => builder,
+    };
+    builder.build().context("Failed to create HTTP client")
+}
+
+fn pinned_tls(fingerprint: CertFingerprint) -> Result<rustls::ClientConfig> {
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    let config = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .context("Failed to configure TLS")?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(PinnedCert {
+            fingerprint,
+            provider,
+        }))
+        .with_no_client_auth();
+    Ok(config)
+}
+
+// Accepts exactly one leaf, chain and hostname don't matter
+// Handshake signatures are still checked so the peer must hold the key
+#[derive(Debug)]
+struct PinnedCert {
+    fingerprint: CertFingerprint,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCert {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let presented = CertFingerprint::from_der(end_entity);
+        if presented == self.fingerprint {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(format!(
+                "certificate fingerprint {} does not match the share link",
+                presented
+            )))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+// Retry with exponential backoff: 1s, 2s (matches retryWithExponentialBackoff in shared.js)
+// Only failures that may pass are retried, see `is_retryable`
+pub(crate) async fn with_retry<T, F, Fut>(context: &str, mut f: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempt = 0;
+    loop {
+        match f().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt + 1 >= MAX_RETRIES || !is_retryable(&e) => return Err(e),
+            Err(e) => {
+                let delay = Duration::from_secs(1 << attempt);
+                tracing::debug!("Retrying {} in {:?}: {:#}", context, delay, e);
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+// Transport failures and 5xx. Anything the server refused (403, 404, 410,
+// 429) would be refused again, and local errors won't change either
+fn is_retryable(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .filter_map(|e| e.downcast_ref::<reqwest::Error>())
+        .any(|e| match e.status() {
+            Some(status) => status.is_server_error(),
+            None => e.is_connect() || e.is_timeout() || e.is_request() || e.is_body(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn status_error(status: u16) -> anyhow::Error {
+        let response = axum::http::Response::builder()
+            .status(status)
+            .body("")
+            .unwrap();
+        let error = reqwest::Response::from(response)
+            .error_for_status()
+            .unwrap_err();
+        anyhow::Error::new(error).context("Failed to fetch chunk 0")
+    }
+
+    #[tokio::test]
+    async fn only_transient_errors_are_retried() {
+        assert!(is_retryable(&status_error(500)));
+        assert!(is_retryable(&status_error(503)));
+        for status in [403, 404, 410, 429] {
+            assert!(!is_retryable(&status_error(status)), "{}", status);
+        }
+        assert!(!is_retryable(&anyhow::anyhow!("Decryption failed")));
+
+        // Nothing listens on port 1
+        let refused = reqwest::get("http://127.0.0.1:1").await.unwrap_err();
+        assert!(is_retryable(&refused.into()));
+    }
+
+    #[tokio::test]
+    async fn refusals_are_not_retried() {
+        let attempts = AtomicU32::new(0);
+        let result: Result<()> = with_retry("chunk 0", || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err(status_error(410))
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,152 @@
+use crate::crypto::types::{CertFingerprint, EncryptionKey};
+use crate::crypto::CipherSuite;
+use anyhow::{bail, ensure, Context, Result};
+use reqwest::Url;
+
+/// Parsed share URL as printed by the server
+/// Format: {base}/{service}/{token}#key={key}&suite={suite}[&fp={fingerprint}]
+/// Passphrase sessions carry `pake=1` instead of the key, links without a
+/// suite are AES-256-GCM. Older links also carry a `nonce`, which is ignored,
+/// every file has its own in the manifest or chunk upload
+pub struct ShareLink {
+    pub base_url: String,
+    pub service: String,
+    pub token: String,
+    /// None until unlocked with the passphrase
+    pub key: Option<EncryptionKey>,
+    /// Local mode certificate pin, any other certificate is refused
+    pub fingerprint: Option<CertFingerprint>,
+    pub suite: CipherSuite,
+    /// Skip certificate checks on links without a pin (`--insecure`)
+    pub insecure: bool,
+}
+
+impl ShareLink {
+    pub fn new(
+        base_url: &str,
+        service: &str,
+        token: &str,
+        key: EncryptionKey,
+        suite: CipherSuite,
+    ) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            service: service.to_string(),
+            token: token.to_string(),
+            key: Some(key),
+            fingerprint: None,
+            suite,
+            insecure: false,
+        }
+    }
+
+    pub fn with_fingerprint(mut self, fingerprint: CertFingerprint) -> Self {
+        self.fingerprint = Some(fingerprint);
+        self
+    }
+
+    pub fn with_insecure(mut self, insecure: bool) -> Self {
+        self.insecure = insecure;
+        self
+    }
+
+    pub fn parse(url: &str) -> Result<Self> {
+        let parsed = Url::parse(url).context("Invalid URL")?;
+
+        // Path is /{service}/{token}
+        let segments: Vec<&str> = parsed
+            .path_segments()
+            .map(|s| s.filter(|seg| !seg.is_empty()).collect())
+            .unwrap_or_default();
+
+        let (service, token) = match segments.as_slice() {
+            [service, token] => (service.to_string(), token.to_string()),
+            _ => bail!("URL path must be /send/<token> or /receive/<token>"),
+        };
+
+        // Keys live in the fragment, never sent to the server
+        let fragment = parsed
+            .fragment()
+            .context("URL is missing the #key=... fragment")?;
+
+        let params: Vec<(&str, &str)> = fragment
+            .split('&')
+            .filter_map(|p| p.split_once('='))
+            .collect();
+
+        let mut key = None;
+        let mut suite = CipherSuite::default();
+        let mut fingerprint = None;
+        let mut pake = false;
+        for (name, value) in params {
+            match name {
+                "key" => key = Some(EncryptionKey::from_base64(value).context("Invalid key")?),
+                "suite" => suite = value.parse()?,
+                "fp" => {
+                    fingerprint =
+                        Some(CertFingerprint::from_base64(value).context("Invalid fingerprint")?)
+                }
+                "pake" => pake = value == "1",
+                _ => {}
+            }
+        }
+
+        // Everything before the path, keeps non default ports
+        let base_url = parsed.origin().ascii_serialization();
+
+        ensure!(pake || key.is_some(), "URL fragment is missing key");
+
+        Ok(Self {
+            base_url,
+            service,
+            token,
+            key,
+            fingerprint,
+            suite,
+            insecure: false,
+        })
+    }
+
+    pub fn requires_passphrase(&self) -> bool {
+        self.key.is_none()
+    }
+
+    // Build an endpoint URL under this session
+    pub fn endpoint(&self, path: &str) -> String {
+        format!("{}/{}/{}/{}", self.base_url, self.service, self.token, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_need_only_a_key_or_pake() {
+        let key = EncryptionKey::new().to_base64();
+
+        let link = ShareLink::parse(&format!(
+            "https://host:8443/send/abc#key={}&suite=xchacha20-poly1305",
+            key
+        ))
+        .unwrap();
+        assert_eq!(link.base_url, "https://host:8443");
+        assert_eq!(link.token, "abc");
+        assert_eq!(link.suite, CipherSuite::XChaCha20Poly1305);
+        assert!(!link.requires_passphrase());
+
+        // Older links still carry a nonce
+        let link = ShareLink::parse(&format!(
+            "https://host/receive/abc#key={}&nonce=AAAAAAAAAA&suite=aes-256-gcm",
+            key
+        ))
+        .unwrap();
+        assert_eq!(link.service, "receive");
+
+        let link = ShareLink::parse("https://host/send/abc#pake=1&suite=aes-256-gcm").unwrap();
+        assert!(link.requires_passphrase());
+
+        assert!(ShareLink::parse("https://host/send/abc#suite=aes-256-gcm").is_err());
+        assert!(ShareLink::parse("https://host/send/abc").is_err());
+    }
+}
@@ -0,0 +1,21 @@
+//! Native clients for talking to archdrop servers without a browser
+//!
+//! `DownloadClient` pulls files from a send server, `UploadClient` pushes
+//! files into a receive server. Both speak the same HTTP protocol as the
+//! browser pages in templates/. `resolve_code` turns a transfer code into
+//! a share link.
+
+mod claim;
+mod code;
+pub mod download;
+mod http;
+mod link;
+mod pake;
+pub mod progress;
+pub mod upload;
+
+pub use code::resolve_code;
+pub use download::DownloadClient;
+pub use link::ShareLink;
+pub use progress::{ProgressEvent, ProgressFn};
+pub use upload::{collect_files, UploadClient, UploadFile};
@@ -0,0 +1,68 @@
+//! Passphrase exchange for links without a key, see `crypto::pake`
+
+use crate::client::ShareLink;
+use crate::crypto::code::TransferCode;
+use crate::crypto::pake::{Password, Spake2};
+use crate::crypto::types::EncryptionKey;
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose, Engine};
+use serde_json::json;
+
+#[derive(serde::Deserialize)]
+struct PakeResponse {
+    #[serde(rename = "exchangeId")]
+    exchange_id: String,
+    message: String,
+}
+
+#[derive(serde::Deserialize)]
+struct ConfirmResponse {
+    key: String,
+}
+
+/// Run SPAKE2 with the server and unwrap the session key
+/// The key then claims the session like any other, see `client::claim`
+pub(crate) async fn unlock(
+    http: &reqwest::Client,
+    link: &ShareLink,
+    passphrase: &str,
+) -> Result<EncryptionKey> {
+    let b64 = general_purpose::URL_SAFE_NO_PAD;
+    let passphrase = TransferCode::normalize_passphrase(&link.token, passphrase);
+    let spake = Spake2::start_client(Password::new(&link.token, &passphrase));
+
+    let response: PakeResponse = http
+        .post(link.endpoint("pake"))
+        .json(&json!({ "message": b64.encode(spake.message()) }))
+        .send()
+        .await
+        .context("Failed to reach server")?
+        .error_for_status()
+        .context("Server rejected passphrase exchange")?
+        .json()
+        .await
+        .context("Invalid passphrase exchange response")?;
+
+    let server_message = b64
+        .decode(&response.message)
+        .context("Invalid PAKE message encoding")?;
+    let keys = spake.finish(&server_message, &response.exchange_id)?;
+
+    let confirm: ConfirmResponse = http
+        .post(link.endpoint("pake/confirm"))
+        .json(&json!({
+            "exchangeId": response.exchange_id,
+            "confirmation": b64.encode(keys.client_confirmation()),
+        }))
+        .send()
+        .await
+        .context("Failed to reach server")?
+        .error_for_status()
+        .context("Wrong passphrase, or too many failed attempts from this address")?
+        .json()
+        .await
+        .context("Invalid confirm response")?;
+
+    let wrapped = b64.decode(&confirm.key).context("Invalid wrapped key")?;
+    keys.unwrap(&wrapped)
+}
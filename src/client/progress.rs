@@ -0,0 +1,29 @@
+use std::sync::Arc;
+
+/// Transfer events reported to the progress callback
+#[derive(Debug, Clone)]
+pub enum ProgressEvent {
+    Claimed {
+        files: usize,
+        total_bytes: u64,
+    },
+    FileStarted {
+        relative_path: String,
+        size: u64,
+    },
+    ChunkDone {
+        relative_path: String,
+        bytes: u64,
+    },
+    FileVerified {
+        relative_path: String,
+        sha256: String,
+    },
+}
+
+pub type ProgressFn = Arc<dyn Fn(ProgressEvent) + Send + Sync>;
+
+// Default callback, ignores everything
+pub(crate) fn noop() -> ProgressFn {
+    Arc::new(|_| {})
+}
@@ -0,0 +1,338 @@
+//! Client for receive servers (`archdrop put`)
+
+use crate::client::claim;
+use crate::client::http;
+use crate::client::pake;
+use crate::client::progress::{self, ProgressEvent, ProgressFn};
+use crate::client::ShareLink;
+use crate::config::CHUNK_SIZE;
+use crate::crypto::{
+    self,
+    kdf::{self, Direction},
+    types::{EncryptionKey, Nonce},
+    Cipher,
+};
+use crate::transfer::io;
+use anyhow::{bail, ensure, Context, Result};
+use base64::{engine::general_purpose, Engine};
+use futures::{StreamExt, TryStreamExt};
+use reqwest::header;
+use reqwest::multipart::{Form, Part};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+use walkdir::WalkDir;
+
+const MAX_CONCURRENT_CHUNKS: usize = 8;
+
+/// Local file and the path it will have on the receiver
+#[derive(Debug, Clone)]
+pub struct UploadFile {
+    pub full_path: PathBuf,
+    pub relative_path: String,
+    pub size: u64,
+}
+
+impl UploadFile {
+    // Empty files still upload one (empty) chunk so the server creates them
+    pub fn total_chunks(&self) -> u64 {
+        self.size.div_ceil(CHUNK_SIZE).max(1)
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct ManifestResponse {
+    file_ids: Vec<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct FinalizeResponse {
+    sha256: String,
+}
+
+/// Expand paths into files, directories keep their own name as the root
+pub fn collect_files(paths: &[PathBuf]) -> Result<Vec<UploadFile>> {
+    let mut files = Vec::new();
+
+    for path in paths {
+        ensure!(path.exists(), "File not found: {}", path.display());
+
+        let base = path.parent().unwrap_or(path.as_path());
+
+        for entry in WalkDir::new(path)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_file())
+        {
+            let relative_path = entry
+                .path()
+                .strip_prefix(base)
+                .unwrap_or(entry.path())
+                .to_string_lossy()
+                .to_string();
+
+            files.push(UploadFile {
+                full_path: entry.path().to_path_buf(),
+                relative_path,
+                size: entry.metadata()?.len(),
+            });
+        }
+    }
+
+    Ok(files)
+}
+
+fn check_service(link: &ShareLink) -> Result<()> {
+    ensure!(
+        link.service == "receive",
+        "Not a receive URL (expected /receive/<token>)"
+    );
+    Ok(())
+}
+
+pub struct UploadClient {
+    http: reqwest::Client,
+    link: ShareLink,
+    key: EncryptionKey,
+    /// `Cookie` header from the claim, sent with every later request
+    cookie: OnceLock<String>,
+    /// Server assigned ids by relative path, from the manifest response
+    file_ids: Mutex<HashMap<String, String>>,
+    progress: ProgressFn,
+}
+
+impl UploadClient {
+    pub fn new(link: ShareLink) -> Result<Self> {
+        check_service(&link)?;
+        let key = link.key.clone().context("This link needs a passphrase")?;
+        let http = http::build_client(link.fingerprint.as_ref(), link.insecure)?;
+        Ok(Self::build(http, link, key))
+    }
+
+    /// For links without a key, the passphrase exchange fetches it
+    pub async fn with_passphrase(link: ShareLink, passphrase: &str) -> Result<Self> {
+        check_service(&link)?;
+        let http = http::build_client(link.fingerprint.as_ref(), link.insecure)?;
+        let key = pake::unlock(&http, &link, passphrase).await?;
+        Ok(Self::build(http, link, key))
+    }
+
+    fn build(http: reqwest::Client, link: ShareLink, key: EncryptionKey) -> Self {
+        Self {
+            http,
+            link,
+            key,
+            cookie: OnceLock::new(),
+            file_ids: Mutex::default(),
+            progress: progress::noop(),
+        }
+    }
+
+    pub fn on_progress(mut self, f: impl Fn(ProgressEvent) + Send + Sync + 'static) -> Self {
+        self.progress = Arc::new(f);
+        self
+    }
+
+    // Empty before the claim, the server answers 403
+    fn cookie(&self) -> &str {
+        self.cookie.get().map(String::as_str).unwrap_or_default()
+    }
+
+    /// Prove we hold the key, then send the file list
+    pub async fn claim(&self, files: &[UploadFile]) -> Result<()> {
+        let cookie = claim::claim(&self.http, &self.link, &self.key).await?;
+        // Claiming again gets the same client back, keep the first cookie
+        let _ = self.cookie.set(cookie);
+
+        let manifest = json!({
+            "files": files
+                .iter()
+                .map(|f| json!({ "relative_path": f.relative_path, "size": f.size }))
+                .collect::<Vec<_>>(),
+            "suite": self.link.suite,
+        });
+        // Paths and sizes are sealed, the server answers with opaque ids
+        let cipher = kdf::metadata_cipher(&self.key, self.link.suite, Direction::Receive);
+        let sealed = crypto::seal(&cipher, &serde_json::to_vec(&manifest)?)?;
+
+        let response: ManifestResponse = self
+            .http
+            .post(self.link.endpoint("manifest"))
+            .header(header::COOKIE, self.cookie())
+            .json(&json!({ "manifest": general_purpose::URL_SAFE_NO_PAD.encode(sealed) }))
+            .send()
+            .await
+            .context("Failed to reach server")?
+            .error_for_status()
+            .context("Server rejected the file list")?
+            .json()
+            .await
+            .context("Invalid manifest response")?;
+        ensure!(
+            response.file_ids.len() == files.len(),
+            "Server returned {} file ids for {} files",
+            response.file_ids.len(),
+            files.len()
+        );
+
+        self.file_ids.lock().unwrap().extend(
+            files
+                .iter()
+                .map(|f| f.relative_path.clone())
+                .zip(response.file_ids),
+        );
+
+        (self.progress)(ProgressEvent::Claimed {
+            files: files.len(),
+            total_bytes: files.iter().map(|f| f.size).sum(),
+        });
+
+        Ok(())
+    }
+
+    /// Encrypt and upload one file, returns the verified SHA-256
+    pub async fn upload_file(&self, file: &UploadFile) -> Result<String> {
+        let file_id = self
+            .file_ids
+            .lock()
+            .unwrap()
+            .get(&file.relative_path)
+            .cloned()
+            .context("File was not in the claimed file list")?;
+
+        // Each file gets its own nonce and key
+        let suite = self.link.suite;
+        let nonce = Nonce::new(suite);
+        let cipher = kdf::file_cipher(&self.key, suite, Direction::Receive, &file.relative_path);
+
+        (self.progress)(ProgressEvent::FileStarted {
+            relative_path: file.relative_path.clone(),
+            size: file.size,
+        });
+
+        let handle = Arc::new(
+            File::open(&file.full_path)
+                .context(format!("Failed to open {}", file.full_path.display()))?,
+        );
+
+        // Chunk 0 creates the file on the server, rest can go in parallel
+        self.upload_chunk(file, &file_id, &handle, &cipher, &nonce, 0)
+            .await?;
+        futures::stream::iter(1..file.total_chunks())
+            .map(|chunk_index| {
+                self.upload_chunk(file, &file_id, &handle, &cipher, &nonce, chunk_index)
+            })
+            .buffer_unordered(MAX_CONCURRENT_CHUNKS)
+            .try_collect::<()>()
+            .await?;
+
+        // Server decrypted and wrote the file, compare its hash with ours
+        let form = Form::new().text("fileId", file_id.clone());
+        let finalized: FinalizeResponse = self
+            .http
+            .post(self.link.endpoint("finalize"))
+            .header(header::COOKIE, self.cookie())
+            .multipart(form)
+            .send()
+            .await?
+            .error_for_status()
+            .context("Failed to finalize file")?
+            .json()
+            .await?;
+
+        let local_hash = io::hash_file(&file.full_path).await?;
+        if local_hash != finalized.sha256 {
+            bail!(
+                "Integrity check failed: expected {}, server wrote {}",
+                local_hash,
+                finalized.sha256
+            );
+        }
+
+        (self.progress)(ProgressEvent::FileVerified {
+            relative_path: file.relative_path.clone(),
+            sha256: local_hash.clone(),
+        });
+
+        Ok(local_hash)
+    }
+
+    async fn upload_chunk(
+        &self,
+        file: &UploadFile,
+        file_id: &str,
+        handle: &Arc<File>,
+        cipher: &Cipher,
+        nonce: &Nonce,
+        chunk_index: u64,
+    ) -> Result<()> {
+        let start = chunk_index * CHUNK_SIZE;
+        let len = CHUNK_SIZE.min(file.size - start) as usize;
+        let last = chunk_index + 1 == file.total_chunks();
+
+        let handle = handle.clone();
+        let plaintext =
+            tokio::task::spawn_blocking(move || io::read_chunk_at_position(&handle, start, len))
+                .await
+                .context("File read task panicked")??;
+
+        let encrypted =
+            crypto::encrypt_chunk_at_position(cipher, nonce, &plaintext, chunk_index as u32, last)?;
+
+        http::with_retry(&format!("chunk {}", chunk_index), || async {
+            // Field names match ChunkUploadRequest
+            let form = Form::new()
+                .part("chunk", Part::bytes(encrypted.clone()))
+                .text("fileId", file_id.to_string())
+                .text("chunkIndex", chunk_index.to_string())
+                .text("nonce", nonce.to_base64());
+
+            self.http
+                .post(self.link.endpoint("chunk"))
+                .header(header::COOKIE, self.cookie())
+                .multipart(form)
+                .send()
+                .await?
+                .error_for_status()
+                .context(format!("Failed to upload chunk {}", chunk_index))?;
+            Ok(())
+        })
+        .await?;
+
+        (self.progress)(ProgressEvent::ChunkDone {
+            relative_path: file.relative_path.clone(),
+            bytes: len as u64,
+        });
+
+        Ok(())
+    }
+
+    /// Tell the server we are done, shuts down the receiver
+    pub async fn complete(&self) -> Result<()> {
+        self.http
+            .post(self.link.endpoint("complete"))
+            .header(header::COOKIE, self.cookie())
+            .send()
+            .await?
+            .error_for_status()
+            .context("Failed to mark upload complete")?;
+        Ok(())
+    }
+
+    /// Claim, upload every file and complete
+    pub async fn upload_all(&self, files: &[UploadFile]) -> Result<()> {
+        ensure!(!files.is_empty(), "No files to send");
+
+        self.claim(files).await?;
+
+        for file in files {
+            self.upload_file(file)
+                .await
+                .context(format!("Failed to upload {}", file.relative_path))?;
+        }
+
+        self.complete().await
+    }
+}
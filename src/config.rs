@@ -0,0 +1,85 @@
+//! Shared constants and the optional config file
+//!
+//! $XDG_CONFIG_HOME/archdrop/config.toml, every entry is optional and
+//! command line flags win over it.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::PathBuf;
+
+pub const CHUNK_SIZE: u64 = 1024 * 1024; // 1MB
+
+// mDNS service local mode code sessions are advertised under
+pub const MDNS_SERVICE_TYPE: &str = "_archdrop._tcp.local.";
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub tls: TlsSection,
+    pub cloudflared: CloudflaredSection,
+    pub limits: LimitsSection,
+}
+
+/// [tls] certificate used instead of the local CA
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsSection {
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+}
+
+/// [cloudflared] named tunnel with a stable hostname
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct CloudflaredSection {
+    pub tunnel: Option<String>,
+    pub hostname: Option<String>,
+    pub credentials_file: Option<PathBuf>,
+}
+
+/// [limits] per IP rate limit and lockout on the token endpoints
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsSection {
+    /// Sustained requests per second, a chunk is one request
+    pub requests_per_second: u32,
+    /// Extra requests allowed in a short burst
+    pub burst: u32,
+    /// Rejected tokens, claims or passphrases before the IP is banned
+    pub max_failures: u32,
+    /// How long a ban lasts, failures older than this are forgotten
+    pub ban_secs: u64,
+}
+
+impl Default for LimitsSection {
+    fn default() -> Self {
+        // Roomy enough for a full speed LAN transfer with 1MB chunks
+        Self {
+            requests_per_second: 200,
+            burst: 400,
+            max_failures: 10,
+            ban_secs: 600,
+        }
+    }
+}
+
+impl Config {
+    /// Load the config file, a missing file is an empty config
+    pub fn load() -> Result<Self> {
+        let Some(path) = config_path() else {
+            return Ok(Self::default());
+        };
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents =
+            std::fs::read_to_string(&path).context(format!("Failed to read {}", path.display()))?;
+        toml::from_str(&contents).context(format!("Invalid config file {}", path.display()))
+    }
+}
+
+/// $XDG_CONFIG_HOME/archdrop/config.toml (~/.config/archdrop/config.toml)
+pub fn config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|d| d.join("archdrop").join("config.toml"))
+}
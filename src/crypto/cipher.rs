@@ -0,0 +1,194 @@
+//! Cipher suites for file contents and metadata
+//!
+//! A session picks one suite and names it in the share link and the
+//! manifest. AES-256-GCM is the default, XChaCha20-Poly1305 is much faster
+//! on CPUs without AES instructions (low end ARM boards and phones).
+//! Both take the same 32 byte keys from `kdf`. AES-GCM's 12 byte nonce
+//! carries the STREAM counter, XChaCha's 24 bytes are drawn at random for
+//! every chunk, see `encryption`. The browser gets XChaCha from
+//! templates/shared.js, WebCrypto has no ChaCha.
+
+use crate::crypto::types::EncryptionKey;
+use aes_gcm::{
+    aead::{Aead, Payload},
+    Aes256Gcm, KeyInit,
+};
+use anyhow::{anyhow, bail, ensure, Result};
+use chacha20poly1305::XChaCha20Poly1305;
+use serde::{Deserialize, Serialize};
+use sha2::digest::generic_array::GenericArray;
+use std::fmt;
+use std::str::FromStr;
+
+// STREAM counter and last chunk flag at the end of every chunk nonce
+const STREAM_SUFFIX_LEN: usize = 5;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CipherSuite {
+    #[default]
+    #[serde(rename = "aes-256-gcm")]
+    Aes256Gcm,
+    #[serde(rename = "xchacha20-poly1305")]
+    XChaCha20Poly1305,
+}
+
+impl CipherSuite {
+    /// Name used in links, manifests and `--cipher`
+    pub fn name(self) -> &'static str {
+        match self {
+            CipherSuite::Aes256Gcm => "aes-256-gcm",
+            CipherSuite::XChaCha20Poly1305 => "xchacha20-poly1305",
+        }
+    }
+
+    pub fn nonce_len(self) -> usize {
+        match self {
+            CipherSuite::Aes256Gcm => 12,
+            CipherSuite::XChaCha20Poly1305 => 24,
+        }
+    }
+
+    /// Random per file part of a chunk nonce, see `types::Nonce`
+    /// 7 bytes for AES-GCM, none for XChaCha20 whose chunks draw a whole nonce
+    pub fn nonce_prefix_len(self) -> usize {
+        match self {
+            CipherSuite::Aes256Gcm => self.nonce_len() - STREAM_SUFFIX_LEN,
+            CipherSuite::XChaCha20Poly1305 => 0,
+        }
+    }
+}
+
+impl fmt::Display for CipherSuite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for CipherSuite {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "aes-256-gcm" => Ok(CipherSuite::Aes256Gcm),
+            "xchacha20-poly1305" => Ok(CipherSuite::XChaCha20Poly1305),
+            _ => bail!(
+                "Unknown cipher suite {}, expected aes-256-gcm or xchacha20-poly1305",
+                s
+            ),
+        }
+    }
+}
+
+/// AEAD under one key, whichever suite the session uses
+/// AES's expanded key schedule is large, boxed to keep the enum small
+pub enum Cipher {
+    Aes256Gcm(Box<Aes256Gcm>),
+    XChaCha20Poly1305(Box<XChaCha20Poly1305>),
+}
+
+impl Cipher {
+    pub fn new(suite: CipherSuite, key: &EncryptionKey) -> Self {
+        match suite {
+            CipherSuite::Aes256Gcm => Cipher::Aes256Gcm(Box::new(Aes256Gcm::new(
+                GenericArray::from_slice(key.as_bytes()),
+            ))),
+            CipherSuite::XChaCha20Poly1305 => Cipher::XChaCha20Poly1305(Box::new(
+                XChaCha20Poly1305::new(GenericArray::from_slice(key.as_bytes())),
+            )),
+        }
+    }
+
+    pub fn suite(&self) -> CipherSuite {
+        match self {
+            Cipher::Aes256Gcm(_) => CipherSuite::Aes256Gcm,
+            Cipher::XChaCha20Poly1305(_) => CipherSuite::XChaCha20Poly1305,
+        }
+    }
+
+    /// Returns ciphertext || tag, the nonce must be `suite().nonce_len()` bytes
+    pub fn encrypt(&self, nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+        ensure!(
+            nonce.len() == self.suite().nonce_len(),
+            "Invalid nonce length"
+        );
+
+        let payload = Payload {
+            msg: plaintext,
+            aad,
+        };
+        match self {
+            Cipher::Aes256Gcm(cipher) => cipher.encrypt(GenericArray::from_slice(nonce), payload),
+            Cipher::XChaCha20Poly1305(cipher) => {
+                cipher.encrypt(GenericArray::from_slice(nonce), payload)
+            }
+        }
+        .map_err(|e| anyhow!("Encryption failed: {:?}", e))
+    }
+
+    pub fn decrypt(&self, nonce: &[u8], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+        ensure!(
+            nonce.len() == self.suite().nonce_len(),
+            "Invalid nonce length"
+        );
+
+        let payload = Payload {
+            msg: ciphertext,
+            aad,
+        };
+        match self {
+            Cipher::Aes256Gcm(cipher) => cipher.decrypt(GenericArray::from_slice(nonce), payload),
+            Cipher::XChaCha20Poly1305(cipher) => {
+                cipher.decrypt(GenericArray::from_slice(nonce), payload)
+            }
+        }
+        .map_err(|e| anyhow!("Decryption failed: {:?}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chacha20poly1305::ChaCha20Poly1305;
+    use serde_json::Value;
+
+    // Shared with tests/browser_crypto.rs, which runs shared.js on them
+    const VECTORS: &str = include_str!("../../tests/vectors/aead.json");
+
+    fn vector(name: &str, field: &str) -> Vec<u8> {
+        let vectors: Value = serde_json::from_str(VECTORS).unwrap();
+        hex::decode(vectors[name][field].as_str().unwrap()).unwrap()
+    }
+
+    // The IETF construction XChaCha builds on, and shared.js with it
+    #[test]
+    fn chacha20_poly1305_rfc8439_vector() {
+        let name = "chacha20-poly1305";
+        let cipher = ChaCha20Poly1305::new(GenericArray::from_slice(&vector(name, "key")));
+        let payload = Payload {
+            msg: &vector(name, "plaintext"),
+            aad: &vector(name, "aad"),
+        };
+        let sealed = cipher
+            .encrypt(GenericArray::from_slice(&vector(name, "nonce")), payload)
+            .unwrap();
+        assert_eq!(sealed, vector(name, "ciphertext"));
+    }
+
+    #[test]
+    fn xchacha20_poly1305_draft_vector() {
+        let name = "xchacha20-poly1305";
+        let key = EncryptionKey::from_bytes(&vector(name, "key")).unwrap();
+        let cipher = Cipher::new(CipherSuite::XChaCha20Poly1305, &key);
+        let (nonce, aad) = (vector(name, "nonce"), vector(name, "aad"));
+
+        let sealed = cipher
+            .encrypt(&nonce, &aad, &vector(name, "plaintext"))
+            .unwrap();
+        assert_eq!(sealed, vector(name, "ciphertext"));
+        assert_eq!(
+            cipher.decrypt(&nonce, &aad, &sealed).unwrap(),
+            vector(name, "plaintext")
+        );
+        assert!(cipher.decrypt(&nonce, b"other aad", &sealed).is_err());
+    }
+}
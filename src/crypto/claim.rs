@@ -0,0 +1,92 @@
+//! Proof that a client holds the session key
+//!
+//! The server hands out a random challenge, the client answers with
+//! HMAC-SHA256 under the session key over the token and the challenge.
+//! The token alone, as it shows up in URLs, logs and proxies, can't claim.
+//! The claim is then bound to a cookie the server signs with a secret that
+//! never leaves the process. templates/shared.js computes the same proof.
+
+use crate::crypto::types::EncryptionKey;
+use base64::{engine::general_purpose, Engine};
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::Sha256;
+
+pub const CHALLENGE_LEN: usize = 32;
+
+const PROOF_LABEL: &[u8] = b"archdrop claim";
+
+pub fn new_challenge() -> [u8; CHALLENGE_LEN] {
+    let mut challenge = [0u8; CHALLENGE_LEN];
+    OsRng.fill_bytes(&mut challenge);
+    challenge
+}
+
+// label || u64 length || token || challenge, the same framing as the PAKE transcript
+fn proof_mac(key: &EncryptionKey, token: &str, challenge: &[u8]) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key.as_bytes()).expect("any key length");
+    mac.update(PROOF_LABEL);
+    mac.update(&(token.len() as u64).to_be_bytes());
+    mac.update(token.as_bytes());
+    mac.update(challenge);
+    mac
+}
+
+/// Sent by the client with the challenge it was given
+pub fn claim_proof(key: &EncryptionKey, token: &str, challenge: &[u8]) -> [u8; 32] {
+    proof_mac(key, token, challenge)
+        .finalize()
+        .into_bytes()
+        .into()
+}
+
+pub fn verify_claim_proof(
+    key: &EncryptionKey,
+    token: &str,
+    challenge: &[u8],
+    proof: &[u8],
+) -> bool {
+    proof_mac(key, token, challenge).verify_slice(proof).is_ok()
+}
+
+/// Server side secret for claim cookies, `{client_id}.{mac}`
+#[derive(Clone)]
+pub struct CookieKey([u8; 32]);
+
+impl CookieKey {
+    pub fn new() -> Self {
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        Self(key)
+    }
+
+    fn mac(&self, client_id: &str) -> Hmac<Sha256> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.0).expect("any key length");
+        mac.update(client_id.as_bytes());
+        mac
+    }
+
+    pub fn sign(&self, client_id: &str) -> String {
+        let tag = self.mac(client_id).finalize().into_bytes();
+        format!(
+            "{}.{}",
+            client_id,
+            general_purpose::URL_SAFE_NO_PAD.encode(tag)
+        )
+    }
+
+    /// Client id of a cookie we signed, None for anything else
+    pub fn verify(&self, cookie: &str) -> Option<String> {
+        let (client_id, tag) = cookie.rsplit_once('.')?;
+        let tag = general_purpose::URL_SAFE_NO_PAD.decode(tag).ok()?;
+        self.mac(client_id).verify_slice(&tag).ok()?;
+        Some(client_id.to_string())
+    }
+}
+
+impl Default for CookieKey {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,193 @@
+//! Short transfer codes like `7-guitar-orbit`
+//!
+//! The number is the nameplate, it becomes the session token and finds the
+//! server. The words are the passphrase for the PAKE exchange, so the code
+//! is the only secret and never needs to be in a URL.
+//!
+//! 99 nameplates and 65536 word pairs are guessable in principle, guesses
+//! are online only and each wrong one counts towards the per IP ban in
+//! `server::limits`.
+
+use anyhow::{ensure, Context, Result};
+use rand::rngs::OsRng;
+use rand::Rng;
+use std::fmt;
+use std::str::FromStr;
+
+const MAX_NAMEPLATE: u32 = 99;
+const WORD_COUNT: usize = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferCode {
+    nameplate: String,
+    words: Vec<String>,
+}
+
+impl TransferCode {
+    pub fn generate() -> Self {
+        let nameplate = OsRng.gen_range(1..=MAX_NAMEPLATE).to_string();
+        let words = (0..WORD_COUNT)
+            .map(|_| WORDS[OsRng.gen_range(0..WORDS.len())].to_string())
+            .collect();
+        Self { nameplate, words }
+    }
+
+    /// Session token, not secret
+    pub fn nameplate(&self) -> &str {
+        &self.nameplate
+    }
+
+    /// PAKE passphrase, e.g. "guitar-orbit"
+    pub fn passphrase(&self) -> String {
+        self.words.join("-")
+    }
+
+    /// Passphrase as typed for the session behind `token`. Code sessions
+    /// take the words in any case and spacing, with or without the nameplate
+    /// in front, other passphrases are used as is.
+    /// Mirrors normalizePassphrase in shared.js
+    pub fn normalize_passphrase(token: &str, input: &str) -> String {
+        if !is_number(token) {
+            return input.to_string();
+        }
+
+        let mut parts: Vec<String> = code_parts(input).collect();
+        if parts.first().is_some_and(|first| {
+            is_number(first) && first.parse::<u32>().ok() == token.parse().ok()
+        }) {
+            parts.remove(0);
+        }
+        parts.join("-")
+    }
+}
+
+// Lowercased words split on dashes and whitespace
+fn code_parts(s: &str) -> impl Iterator<Item = String> + '_ {
+    s.split(|c: char| c == '-' || c.is_whitespace())
+        .filter(|p| !p.is_empty())
+        .map(|p| p.to_lowercase())
+}
+
+// Nameplates are plain decimal numbers, other tokens are UUIDs
+fn is_number(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+impl fmt::Display for TransferCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.nameplate, self.passphrase())
+    }
+}
+
+// Forgiving about case and separators, "7 Guitar Orbit" works too
+impl FromStr for TransferCode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = code_parts(s);
+
+        let nameplate = parts.next().context("Empty code")?;
+        ensure!(
+            is_number(&nameplate),
+            "Code must start with a number, e.g. 7-guitar-orbit"
+        );
+        let number: u32 = nameplate.parse().context("Invalid code number")?;
+        ensure!(number >= 1, "Invalid code number");
+
+        let words: Vec<String> = parts.collect();
+        ensure!(
+            !words.is_empty(),
+            "Code is missing its words, e.g. 7-guitar-orbit"
+        );
+
+        Ok(Self {
+            nameplate: number.to_string(),
+            words,
+        })
+    }
+}
+
+// 256 words, one byte each
+const WORDS: [&str; 256] = [
+    "acid", "acorn", "actor", "adobe", "agent", "alarm", "album", "alley", "alpha", "amber",
+    "angle", "ankle", "apple", "apron", "arena", "arrow", "atlas", "attic", "award", "bacon",
+    "badge", "bagel", "baker", "bamboo", "banjo", "barn", "basil", "basin", "beach", "beard",
+    "beetle", "bell", "berry", "bison", "blade", "bloom", "board", "boat", "bonus", "boot",
+    "bottle", "bounce", "brick", "bridge", "brook", "broom", "bubble", "bucket", "bugle", "cabin",
+    "cactus", "camel", "candle", "canoe", "canyon", "carbon", "cargo", "carpet", "castle", "cedar",
+    "cello", "chalk", "cheese", "cherry", "chess", "cider", "cinema", "circle", "citrus", "clock",
+    "cloud", "clover", "cobalt", "cocoa", "comet", "copper", "coral", "cotton", "cougar", "crayon",
+    "credit", "cricket", "crown", "cube", "daisy", "dancer", "delta", "denim", "desert", "diesel",
+    "dinner", "disco", "dolphin", "domino", "donkey", "dragon", "dream", "drum", "eagle", "easel",
+    "echo", "eclipse", "elbow", "ember", "engine", "falcon", "fabric", "feather", "fiddle", "fig",
+    "flame", "flute", "forest", "fossil", "fox", "galaxy", "garden", "garlic", "gazelle", "geyser",
+    "ginger", "glove", "goblet", "gold", "granite", "grape", "gravel", "guitar", "hammer",
+    "harbor", "harp", "hazel", "helmet", "hermit", "honey", "hornet", "husky", "igloo", "indigo",
+    "iris", "island", "ivory", "jacket", "jaguar", "jelly", "jigsaw", "jungle", "kayak", "kettle",
+    "kiwi", "koala", "ladder", "lagoon", "laser", "lemon", "lilac", "lizard", "llama", "locket",
+    "lotus", "lunar", "magnet", "mango", "maple", "marble", "meadow", "melon", "meteor", "mint",
+    "mirror", "mitten", "monkey", "mosaic", "muffin", "nectar", "needle", "nickel", "noodle",
+    "nugget", "oasis", "ocean", "olive", "onion", "opal", "orbit", "orchid", "otter", "oven",
+    "owl", "paddle", "panda", "paper", "parrot", "peach", "pebble", "pepper", "piano", "pickle",
+    "pillow", "pilot", "pine", "pirate", "planet", "plum", "pocket", "polka", "pony", "poppy",
+    "potato", "prism", "puzzle", "quartz", "quill", "rabbit", "radar", "radio", "raven", "ribbon",
+    "river", "robin", "rocket", "ruby", "saddle", "salmon", "sandal", "satin", "scarf", "shadow",
+    "silver", "sketch", "sock", "sonar", "spider", "sponge", "squash", "statue", "stereo",
+    "summit", "sunset", "swan", "tango", "teapot", "tiger", "toast", "tomato", "topaz", "tulip",
+    "tunnel", "turtle", "velvet", "violin", "walnut", "walrus", "wizard", "yogurt", "zebra",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_is_forgiving() {
+        for input in ["7-guitar-orbit", "7 Guitar Orbit", " 07--GUITAR orbit "] {
+            let code: TransferCode = input.parse().unwrap();
+            assert_eq!(code.to_string(), "7-guitar-orbit");
+        }
+        for input in ["", "guitar-orbit", "7", "0-guitar-orbit", "+7-guitar-orbit"] {
+            assert!(input.parse::<TransferCode>().is_err(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn generated_codes_roundtrip() {
+        for _ in 0..100 {
+            let code = TransferCode::generate();
+            assert_eq!(code.to_string().parse::<TransferCode>().unwrap(), code);
+        }
+    }
+
+    #[test]
+    fn code_passphrases_are_normalized() {
+        for input in [
+            "guitar-orbit",
+            "Guitar Orbit",
+            "7-guitar-orbit",
+            "7 GUITAR-orbit ",
+        ] {
+            assert_eq!(
+                TransferCode::normalize_passphrase("7", input),
+                "guitar-orbit",
+                "{:?}",
+                input
+            );
+        }
+        // Another session's nameplate stays part of the passphrase
+        assert_eq!(
+            TransferCode::normalize_passphrase("7", "8-guitar-orbit"),
+            "8-guitar-orbit"
+        );
+    }
+
+    #[test]
+    fn other_passphrases_are_left_alone() {
+        let token = "5f0c6f7e-93a4-4c3e-9d8a-0b1e2c3d4e5f";
+        assert_eq!(
+            TransferCode::normalize_passphrase(token, "Correct Horse"),
+            "Correct Horse"
+        );
+    }
+}
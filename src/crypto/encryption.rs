@@ -1,36 +1,155 @@
+use crate::crypto::cipher::{Cipher, CipherSuite};
 use crate::crypto::types::Nonce;
-use aes_gcm::{aead::Aead, Aes256Gcm};
-use anyhow::Result;
-use sha2::digest::generic_array::GenericArray;
+use anyhow::{ensure, Result};
+use rand::rngs::OsRng;
+use rand::RngCore;
 
-/// Decrypt chunk using AES-256-GCM
+// Chunks follow the STREAM construction (same layout as aes-gcm's StreamBE32):
+// every chunk is bound to its index and a last chunk flag. AES-GCM puts them
+// in the nonce after the file's random prefix. XChaCha20 draws a fresh 24
+// byte nonce per chunk, sent in front of the ciphertext, and authenticates
+// index and flag as associated data. Either way moving a chunk to another
+// position, or dropping the tail and flagging an earlier chunk as last,
+// fails authentication. Each file has its own key (see `kdf`), so chunks
+// can't be swapped between files either.
+
+/// Decrypt chunk with the session's suite, `last` must match what the sender used
 pub fn decrypt_chunk_at_position(
-    cipher: &Aes256Gcm,
+    cipher: &Cipher,
     nonce_base: &Nonce,
     encrypted_data: &[u8],
     counter: u32,
+    last: bool,
 ) -> Result<Vec<u8>> {
-    // Consctruct full 12 byte nonce
-    let full_nonce = nonce_base.with_counter(counter);
-    let nonce_array = GenericArray::from_slice(&full_nonce);
+    // Prefix, counter and flag, the prefix is empty for XChaCha
+    let position = nonce_base.with_counter(counter, last);
 
-    cipher
-        .decrypt(nonce_array, encrypted_data)
-        .map_err(|e| anyhow::anyhow!("Decryption failed: {:?}", e))
+    match cipher.suite() {
+        CipherSuite::Aes256Gcm => cipher.decrypt(&position, &[], encrypted_data),
+        CipherSuite::XChaCha20Poly1305 => {
+            let (nonce, ciphertext) = split_nonce(cipher, encrypted_data)?;
+            cipher.decrypt(nonce, &position, ciphertext)
+        }
+    }
 }
 
-/// Encrypt chunk using AES-256-GCM
+/// Encrypt chunk with the session's suite, `last` is set for the file's final chunk
 pub fn encrypt_chunk_at_position(
-    cipher: &Aes256Gcm,
+    cipher: &Cipher,
     nonce_base: &Nonce,
     plaintext: &[u8],
     counter: u32,
+    last: bool,
 ) -> Result<Vec<u8>> {
-    // Construct Nonce
-    let full_nonce = nonce_base.with_counter(counter);
-    let nonce_array = GenericArray::from_slice(&full_nonce);
+    let position = nonce_base.with_counter(counter, last);
+
+    match cipher.suite() {
+        CipherSuite::Aes256Gcm => cipher.encrypt(&position, &[], plaintext),
+        CipherSuite::XChaCha20Poly1305 => seal_with(cipher, &position, plaintext),
+    }
+}
+
+/// Encrypt a standalone blob under a fresh random nonce, returns nonce || ciphertext
+pub fn seal(cipher: &Cipher, plaintext: &[u8]) -> Result<Vec<u8>> {
+    seal_with(cipher, &[], plaintext)
+}
+
+/// Decrypt a blob from `seal`
+pub fn open(cipher: &Cipher, sealed: &[u8]) -> Result<Vec<u8>> {
+    let (nonce, ciphertext) = split_nonce(cipher, sealed)?;
+
+    cipher.decrypt(nonce, &[], ciphertext)
+}
+
+fn seal_with(cipher: &Cipher, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let mut nonce = vec![0u8; cipher.suite().nonce_len()];
+    OsRng.fill_bytes(&mut nonce);
+    let ciphertext = cipher.encrypt(&nonce, aad, plaintext)?;
+
+    Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+fn split_nonce<'a>(cipher: &Cipher, sealed: &'a [u8]) -> Result<(&'a [u8], &'a [u8])> {
+    let nonce_len = cipher.suite().nonce_len();
+    ensure!(sealed.len() > nonce_len, "Sealed data too short");
+
+    Ok(sealed.split_at(nonce_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::types::EncryptionKey;
+    use crate::crypto::CipherSuite;
+
+    const SUITES: [CipherSuite; 2] = [CipherSuite::Aes256Gcm, CipherSuite::XChaCha20Poly1305];
+
+    // Three chunk file, only the last one flagged
+    fn encrypt_file(cipher: &Cipher, nonce: &Nonce) -> Vec<Vec<u8>> {
+        (0..3u32)
+            .map(|i| encrypt_chunk_at_position(cipher, nonce, &[i as u8; 64], i, i == 2).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn chunks_roundtrip() {
+        for suite in SUITES {
+            let cipher = Cipher::new(suite, &EncryptionKey::new());
+            let nonce = Nonce::new(suite);
+            for (i, chunk) in encrypt_file(&cipher, &nonce).iter().enumerate() {
+                let plaintext =
+                    decrypt_chunk_at_position(&cipher, &nonce, chunk, i as u32, i == 2).unwrap();
+                assert_eq!(plaintext, [i as u8; 64]);
+            }
+        }
+    }
+
+    #[test]
+    fn dropping_the_final_chunk_fails() {
+        for suite in SUITES {
+            let cipher = Cipher::new(suite, &EncryptionKey::new());
+            let nonce = Nonce::new(suite);
+            let chunks = encrypt_file(&cipher, &nonce);
+
+            // A truncated file ends on chunk 1, which wasn't sealed as last
+            assert!(decrypt_chunk_at_position(&cipher, &nonce, &chunks[1], 1, true).is_err());
+            // Nor does the real last chunk pass as a middle one
+            assert!(decrypt_chunk_at_position(&cipher, &nonce, &chunks[2], 2, false).is_err());
+        }
+    }
+
+    #[test]
+    fn reordered_chunks_fail() {
+        for suite in SUITES {
+            let cipher = Cipher::new(suite, &EncryptionKey::new());
+            let nonce = Nonce::new(suite);
+            let chunks = encrypt_file(&cipher, &nonce);
+
+            assert!(decrypt_chunk_at_position(&cipher, &nonce, &chunks[0], 1, false).is_err());
+        }
+    }
+
+    #[test]
+    fn xchacha_chunks_carry_their_own_nonce() {
+        let suite = CipherSuite::XChaCha20Poly1305;
+        let cipher = Cipher::new(suite, &EncryptionKey::new());
+        let nonce = Nonce::new(suite);
+        let chunks = encrypt_file(&cipher, &nonce);
+
+        assert_eq!(chunks[0].len(), suite.nonce_len() + 64 + 16);
+        assert_ne!(chunks[0][..24], chunks[1][..24]);
+    }
+
+    #[test]
+    fn seal_roundtrip_and_tamper() {
+        for suite in SUITES {
+            let cipher = Cipher::new(suite, &EncryptionKey::new());
+            let mut sealed = seal(&cipher, b"manifest").unwrap();
+            assert_eq!(open(&cipher, &sealed).unwrap(), b"manifest");
 
-    cipher
-        .encrypt(nonce_array, plaintext)
-        .map_err(|e| anyhow::anyhow!("Encryption failed: {:?}", e))
+            let last = sealed.len() - 1;
+            sealed[last] ^= 1;
+            assert!(open(&cipher, &sealed).is_err());
+        }
+    }
 }
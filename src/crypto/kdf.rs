@@ -0,0 +1,139 @@
+//! Per file keys, derived with HKDF-SHA256 (RFC 5869) from the session key
+//!
+//! Every file gets its own key, so two files that happen to draw the same
+//! nonce never reuse a (key, nonce) pair. Keys are the same for either
+//! cipher suite, the suite only picks the algorithm. The info string binds
+//! the direction and the file: its manifest index when sending, its
+//! relative path when receiving. Metadata (the manifest) has a key of its
+//! own per direction. templates/shared.js derives the same keys with
+//! WebCrypto's HKDF.
+
+use crate::crypto::cipher::{Cipher, CipherSuite};
+use crate::crypto::types::EncryptionKey;
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+const FILE_KEY_LABEL: &[u8] = b"archdrop file key";
+const METADATA_KEY_LABEL: &[u8] = b"archdrop metadata key";
+
+/// Which way a file travels, the same file id gets a different key each way
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Send,
+    Receive,
+}
+
+impl Direction {
+    fn label(self) -> &'static str {
+        match self {
+            Direction::Send => "send",
+            Direction::Receive => "receive",
+        }
+    }
+}
+
+// Empty salt, info is label || u64 length || part for every part
+fn derive(session_key: &EncryptionKey, label: &[u8], parts: &[&[u8]]) -> EncryptionKey {
+    let mut info = label.to_vec();
+    for part in parts {
+        info.extend_from_slice(&(part.len() as u64).to_be_bytes());
+        info.extend_from_slice(part);
+    }
+
+    let mut okm = [0u8; 32];
+    Hkdf::<Sha256>::new(None, session_key.as_bytes())
+        .expand(&info, &mut okm)
+        .expect("32 bytes is a valid HKDF-SHA256 length");
+    EncryptionKey::from_bytes(&okm).expect("HKDF output is 32 bytes")
+}
+
+/// Key for one file, see the module docs for what `file_id` is
+pub fn file_key(session_key: &EncryptionKey, direction: Direction, file_id: &str) -> EncryptionKey {
+    derive(
+        session_key,
+        FILE_KEY_LABEL,
+        &[direction.label().as_bytes(), file_id.as_bytes()],
+    )
+}
+
+/// Cipher under the file's key
+pub fn file_cipher(
+    session_key: &EncryptionKey,
+    suite: CipherSuite,
+    direction: Direction,
+    file_id: &str,
+) -> Cipher {
+    Cipher::new(suite, &file_key(session_key, direction, file_id))
+}
+
+/// Key for the direction's metadata
+pub fn metadata_key(session_key: &EncryptionKey, direction: Direction) -> EncryptionKey {
+    derive(
+        session_key,
+        METADATA_KEY_LABEL,
+        &[direction.label().as_bytes()],
+    )
+}
+
+/// Cipher for file names, paths and sizes, see `encryption::seal`
+pub fn metadata_cipher(
+    session_key: &EncryptionKey,
+    suite: CipherSuite,
+    direction: Direction,
+) -> Cipher {
+    Cipher::new(suite, &metadata_key(session_key, direction))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session_key() -> EncryptionKey {
+        EncryptionKey::from_bytes(&std::array::from_fn::<u8, 32, _>(|i| i as u8)).unwrap()
+    }
+
+    // Pinned so a change to the derivation can't go unnoticed, shared.js
+    // derives the same keys (tests/browser_crypto.rs)
+    #[test]
+    fn keys_match_pinned_vectors() {
+        let key = session_key();
+        for (derived, expected) in [
+            (
+                file_key(&key, Direction::Send, "0"),
+                "10489f29bddd2aadce61d90cebfaff30478ea0f1cd86ef5abb7b7f04301f2773",
+            ),
+            (
+                file_key(&key, Direction::Send, "1"),
+                "461bee42c830c9caf35b4de56c9b3ded38fe7879329f98cfd985db2234e9a06c",
+            ),
+            (
+                file_key(&key, Direction::Receive, "docs/a.txt"),
+                "0482fcb21c8ea1e6c3852ae3e14164f043514feece86dbb0feff3227c67e48b5",
+            ),
+            (
+                metadata_key(&key, Direction::Send),
+                "4bb16dfd454df031752f31b7341a53ca126c746eecaaf2e4571a9c98e2f60888",
+            ),
+            (
+                metadata_key(&key, Direction::Receive),
+                "03c4e6a741c539bd56139ec3d937310ebd5e58603b01876bf4adf053fed17f94",
+            ),
+        ] {
+            assert_eq!(hex::encode(derived.as_bytes()), expected);
+        }
+    }
+
+    #[test]
+    fn keys_differ_by_direction_and_file() {
+        let key = session_key();
+        let send = file_key(&key, Direction::Send, "docs/a.txt");
+        assert_ne!(
+            send.as_bytes(),
+            file_key(&key, Direction::Receive, "docs/a.txt").as_bytes()
+        );
+        assert_ne!(
+            send.as_bytes(),
+            file_key(&key, Direction::Send, "docs/b.txt").as_bytes()
+        );
+    }
+}
@@ -1,4 +1,10 @@
+pub mod cipher;
+pub mod claim;
+pub mod code;
 pub mod encryption;
+pub mod kdf;
+pub mod pake;
 pub mod types;
 
-pub use encryption::{decrypt_chunk_at_position, encrypt_chunk_at_position};
+pub use cipher::{Cipher, CipherSuite};
+pub use encryption::{decrypt_chunk_at_position, encrypt_chunk_at_position, open, seal};
@@ -0,0 +1,341 @@
+//! SPAKE2 over Ed25519, from the RustCrypto `spake2` crate
+//!
+//! Both sides prove they know the same passphrase without revealing it.
+//! Someone holding only the share URL can make one online guess per
+//! exchange, the messages give nothing to test guesses offline.
+//! The construction is Abdalla and Pointcheval's SPAKE2 as in python-spake2
+//! and magic-wormhole, the crate is tested against python-spake2's vectors
+//! and so is templates/shared.js, which implements the client side.
+//! The client is side A, the server side B identified by the session token.
+
+use crate::crypto::types::EncryptionKey;
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes256Gcm, KeyInit};
+use anyhow::{anyhow, ensure, Context, Result};
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::digest::generic_array::GenericArray;
+use sha2::{Digest, Sha256};
+use spake2::{Ed25519Group, Identity};
+
+/// Side byte followed by a compressed Edwards point
+pub const MESSAGE_LEN: usize = 33;
+const WRAP_NONCE_LEN: usize = 12;
+
+// Side A's identity, side B's is the session token
+const CLIENT_IDENTITY: &[u8] = b"archdrop client";
+
+//-----------
+// PASSWORD
+//-----------
+
+/// Passphrase for one session, the token goes into SPAKE2 as the server's identity
+#[derive(Clone)]
+pub struct Password {
+    token: String,
+    passphrase: spake2::Password,
+}
+
+impl Password {
+    pub fn new(token: &str, passphrase: &str) -> Self {
+        Self {
+            token: token.to_string(),
+            passphrase: spake2::Password::new(passphrase.trim()),
+        }
+    }
+}
+
+//-----------
+// EXCHANGE
+//-----------
+
+/// One side of a single exchange
+pub struct Spake2 {
+    inner: spake2::Spake2<Ed25519Group>,
+    message: Vec<u8>,
+}
+
+impl Spake2 {
+    pub fn start_client(password: Password) -> Self {
+        let (inner, message) = spake2::Spake2::start_a(
+            &password.passphrase,
+            &Identity::new(CLIENT_IDENTITY),
+            &Identity::new(password.token.as_bytes()),
+        );
+        Self { inner, message }
+    }
+
+    pub fn start_server(password: Password) -> Self {
+        let (inner, message) = spake2::Spake2::start_b(
+            &password.passphrase,
+            &Identity::new(CLIENT_IDENTITY),
+            &Identity::new(password.token.as_bytes()),
+        );
+        Self { inner, message }
+    }
+
+    /// Our message for the other side
+    pub fn message(&self) -> &[u8] {
+        &self.message
+    }
+
+    /// Combine with the peer's message, both sides end with the same keys
+    /// only if they used the same passphrase. `exchange_id` is assigned by
+    /// the server and ties the confirmation to this exchange
+    pub fn finish(self, peer_message: &[u8], exchange_id: &str) -> Result<PakeKeys> {
+        let key = self
+            .inner
+            .finish(peer_message)
+            .map_err(|e| anyhow!("Invalid PAKE message: {}", e))?;
+
+        let mut hasher = Sha256::new();
+        for part in [key.as_slice(), exchange_id.as_bytes()] {
+            hasher.update((part.len() as u64).to_be_bytes());
+            hasher.update(part);
+        }
+        Ok(PakeKeys(hasher.finalize().into()))
+    }
+}
+
+//-------
+// KEYS
+//-------
+
+/// SPAKE2 key hashed with the exchange id
+pub struct PakeKeys([u8; 32]);
+
+impl PakeKeys {
+    fn derive(&self, label: &[u8]) -> Hmac<Sha256> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.0).expect("any key length");
+        mac.update(label);
+        mac
+    }
+
+    /// Sent by the client to prove it used the right passphrase
+    pub fn client_confirmation(&self) -> [u8; 32] {
+        self.derive(b"archdrop client confirm")
+            .finalize()
+            .into_bytes()
+            .into()
+    }
+
+    pub fn verify_client_confirmation(&self, confirmation: &[u8]) -> bool {
+        self.derive(b"archdrop client confirm")
+            .verify_slice(confirmation)
+            .is_ok()
+    }
+
+    fn wrap_cipher(&self) -> Aes256Gcm {
+        let key = self.derive(b"archdrop wrap key").finalize().into_bytes();
+        Aes256Gcm::new(&key)
+    }
+
+    /// Encrypt the session key for the client, nonce || ciphertext
+    pub fn wrap(&self, key: &EncryptionKey) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; WRAP_NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+
+        let sealed = self
+            .wrap_cipher()
+            .encrypt(GenericArray::from_slice(&nonce), key.as_bytes().as_slice())
+            .map_err(|e| anyhow::anyhow!("Failed to wrap session key: {:?}", e))?;
+
+        Ok([nonce.as_slice(), &sealed].concat())
+    }
+
+    /// Also authenticates the server, only the right passphrase decrypts
+    pub fn unwrap(&self, wrapped: &[u8]) -> Result<EncryptionKey> {
+        ensure!(wrapped.len() > WRAP_NONCE_LEN, "Wrapped key too short");
+        let (nonce, sealed) = wrapped.split_at(WRAP_NONCE_LEN);
+
+        let key = self
+            .wrap_cipher()
+            .decrypt(GenericArray::from_slice(nonce), sealed)
+            .map_err(|_| anyhow::anyhow!("Server failed to prove the passphrase"))?;
+
+        EncryptionKey::from_bytes(&key).context("Invalid session key")
+    }
+}
+
+/// Random passphrase for `--passphrase` without a value, e.g. "k7rm-x2qp"
+/// Skips look-alike characters so it's easy to read out
+pub fn generate_passphrase() -> String {
+    const ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+    // Bytes past the last multiple of 31 are drawn again, a plain modulo
+    // would favour the first letters
+    const LIMIT: u8 = (256 - 256 % ALPHABET.len()) as u8;
+
+    let mut chars = String::new();
+    while chars.len() < 8 {
+        let mut byte = [0u8; 1];
+        OsRng.fill_bytes(&mut byte);
+        if byte[0] < LIMIT {
+            chars.push(ALPHABET[byte[0] as usize % ALPHABET.len()] as char);
+        }
+    }
+    format!("{}-{}", &chars[..4], &chars[4..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::CryptoRng;
+
+    const TOKEN: &str = "7";
+
+    // Runs both sides, returns (client keys, server keys)
+    fn exchange(client: Password, server: Password, client_id: &str) -> (PakeKeys, PakeKeys) {
+        let client = Spake2::start_client(client);
+        let server = Spake2::start_server(server);
+        let client_message = client.message().to_vec();
+        let server_message = server.message().to_vec();
+
+        let server_keys = server.finish(&client_message, "exchange").unwrap();
+        let client_keys = client.finish(&server_message, client_id).unwrap();
+        (client_keys, server_keys)
+    }
+
+    fn password(passphrase: &str) -> Password {
+        Password::new(TOKEN, passphrase)
+    }
+
+    #[test]
+    fn same_passphrase_unwraps_the_key() {
+        let (client, server) = exchange(
+            password("guitar-orbit"),
+            password("guitar-orbit"),
+            "exchange",
+        );
+        assert!(server.verify_client_confirmation(&client.client_confirmation()));
+
+        let key = EncryptionKey::new();
+        let unwrapped = client.unwrap(&server.wrap(&key).unwrap()).unwrap();
+        assert_eq!(unwrapped.as_bytes(), key.as_bytes());
+    }
+
+    #[test]
+    fn wrong_passphrase_is_rejected() {
+        let (client, server) = exchange(
+            password("guitar-orbot"),
+            password("guitar-orbit"),
+            "exchange",
+        );
+        assert!(!server.verify_client_confirmation(&client.client_confirmation()));
+        assert!(client
+            .unwrap(&server.wrap(&EncryptionKey::new()).unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn transcript_mismatch_is_rejected() {
+        // Confirmation replayed under another exchange id
+        let (client, server) = exchange(
+            password("guitar-orbit"),
+            password("guitar-orbit"),
+            "other exchange",
+        );
+        assert!(!server.verify_client_confirmation(&client.client_confirmation()));
+
+        // Or against another session
+        let (client, server) = exchange(
+            Password::new("8", "guitar-orbit"),
+            password("guitar-orbit"),
+            "exchange",
+        );
+        assert!(!server.verify_client_confirmation(&client.client_confirmation()));
+    }
+
+    #[test]
+    fn invalid_messages_are_rejected() {
+        let server = || Spake2::start_server(password("guitar-orbit"));
+        let from_server = server().message().to_vec();
+        // y = 2 isn't the y coordinate of any point
+        let mut off_curve = [0u8; MESSAGE_LEN];
+        off_curve[0] = b'A';
+        off_curve[1] = 2;
+
+        for message in [from_server.as_slice(), &off_curve, &[b'A'; MESSAGE_LEN - 1]] {
+            assert!(server().finish(message, "exchange").is_err());
+        }
+    }
+
+    #[test]
+    fn passphrase_is_trimmed() {
+        let (client, server) = exchange(
+            password(" guitar-orbit\n"),
+            password("guitar-orbit"),
+            "exchange",
+        );
+        assert!(server.verify_client_confirmation(&client.client_confirmation()));
+    }
+
+    // Hands out a fixed scalar, `Scalar::random` reads 64 little endian bytes
+    struct FixedScalar([u8; 64]);
+
+    impl RngCore for FixedScalar {
+        fn next_u32(&mut self) -> u32 {
+            unimplemented!()
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            unimplemented!()
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            dest.copy_from_slice(&self.0);
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl CryptoRng for FixedScalar {}
+
+    #[test]
+    fn matches_python_spake2_vectors() {
+        // From python-spake2, also checked against templates/shared.js in tests/browser_crypto.rs
+        let mut scalar_a = [0u8; 64];
+        hex::decode_to_slice(
+            "25184061a70b1142f1a9f043a52cf7033dc308b5a0a32e42b003ecd59c2ac605",
+            &mut scalar_a[..32],
+        )
+        .unwrap();
+
+        let (side_a, message) = spake2::Spake2::<Ed25519Group>::start_a_with_rng(
+            &spake2::Password::new(b"password"),
+            &Identity::new(b"idA"),
+            &Identity::new(b"idB"),
+            FixedScalar(scalar_a),
+        );
+        assert_eq!(
+            hex::encode(message),
+            "416fc960df73c9cf8ed7198b0c9534e2e96a5984bfc5edc023fd24dacf371f2af9"
+        );
+
+        let key = side_a
+            .finish(
+                &hex::decode("42354e97b88406922b1df4bea1d7870f17aed3dba7c720b313edae315b00959309")
+                    .unwrap(),
+            )
+            .unwrap();
+        assert_eq!(
+            hex::encode(key),
+            "712295de7219c675ddd31942184aa26e0a957cf216bc230d165b215047b520c1"
+        );
+    }
+
+    #[test]
+    fn generated_passphrases_use_the_whole_alphabet() {
+        let mut seen = std::collections::HashSet::new();
+        for _ in 0..200 {
+            let passphrase = generate_passphrase();
+            assert_eq!(passphrase.len(), 9);
+            assert_eq!(&passphrase[4..5], "-");
+            seen.extend(passphrase.chars().filter(|c| *c != '-'));
+        }
+        assert_eq!(seen.len(), 31);
+    }
+}
@@ -1,9 +1,12 @@
+use crate::crypto::cipher::CipherSuite;
 use base64::{engine::general_purpose, Engine};
 use rand::rngs::OsRng;
 use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::fmt;
 
 //---------------------------------------
-// AES-256-GCM encryption key (32 bytes)
+// 256-bit encryption key (32 bytes), either suite
 //---------------------------------------
 #[derive(Debug, Clone)]
 pub struct EncryptionKey([u8; 32]);
@@ -11,7 +14,7 @@ pub struct EncryptionKey([u8; 32]);
 impl EncryptionKey {
     pub fn new() -> Self {
         let mut key = [0u8; 32];
-        OsRng::default().fill_bytes(&mut key);
+        OsRng.fill_bytes(&mut key);
         Self(key)
     }
 
@@ -21,16 +24,20 @@ impl EncryptionKey {
 
     pub fn to_base64(&self) -> String {
         // url safe base64
-        general_purpose::URL_SAFE_NO_PAD.encode(&self.0)
+        general_purpose::URL_SAFE_NO_PAD.encode(self.0)
     }
 
     pub fn from_base64(b64: &str) -> anyhow::Result<Self> {
         let bytes = general_purpose::URL_SAFE_NO_PAD.decode(b64)?;
+        Self::from_bytes(&bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
         if bytes.len() != 32 {
             anyhow::bail!("Invalid key length");
         }
         let mut key = [0u8; 32];
-        key.copy_from_slice(&bytes);
+        key.copy_from_slice(bytes);
         Ok(Self(key))
     }
 }
@@ -41,23 +48,25 @@ impl Default for EncryptionKey {
     }
 }
 
-//---------------------------------------------------------------------
-// 7-byte nonce base for AES-GCM stream encryption
-// Combined with a 4-byte counter and 1-byte flag to form 12-byte nonce
-//---------------------------------------------------------------------
+//----------------------------------------------------------------------
+// Random per file nonce prefix for STREAM chunk encryption: 7 bytes for
+// AES-GCM, followed by a 4-byte counter and 1-byte flag to form the full
+// nonce. Empty for XChaCha20-Poly1305, which draws a random nonce for every
+// chunk and authenticates counter and flag as associated data
+//----------------------------------------------------------------------
 #[derive(Debug, Clone)]
-pub struct Nonce([u8; 7]);
+pub struct Nonce(Vec<u8>);
 
 impl Nonce {
     // Create new random nonce
-    pub fn new() -> Self {
-        let mut nonce = [0u8; 7];
-        OsRng::default().fill_bytes(&mut nonce);
+    pub fn new(suite: CipherSuite) -> Self {
+        let mut nonce = vec![0u8; suite.nonce_prefix_len()];
+        OsRng.fill_bytes(&mut nonce);
         Self(nonce)
     }
 
     // Get raw bytes (for creating stream encryptor/decryptor)
-    pub fn as_bytes(&self) -> &[u8; 7] {
+    pub fn as_bytes(&self) -> &[u8] {
         &self.0
     }
 
@@ -66,27 +75,93 @@ impl Nonce {
         general_purpose::URL_SAFE_NO_PAD.encode(&self.0)
     }
 
-    pub fn from_base64(b64: &str) -> anyhow::Result<Self> {
+    pub fn from_base64(b64: &str, suite: CipherSuite) -> anyhow::Result<Self> {
         let bytes = general_purpose::URL_SAFE_NO_PAD.decode(b64)?;
-        if bytes.len() != 7 {
+        if bytes.len() != suite.nonce_prefix_len() {
             anyhow::bail!("Invalid nonce length");
         }
-        let mut nonce = [0u8; 7];
-        nonce.copy_from_slice(&bytes);
-        Ok(Self(nonce))
+        Ok(Self(bytes))
     }
 
-    pub fn with_counter(&self, counter: u32) -> [u8; 12] {
-        let mut full_nonce = [0u8; 12];
-        full_nonce[..8].copy_from_slice(self.as_bytes());
-        full_nonce[7..12].copy_from_slice(&counter.to_be_bytes());
+    /// STREAM nonce for one chunk (the bare position for XChaCha), the flag
+    /// byte is 1 only on the final chunk so a stream cut short never
+    /// authenticates as complete
+    pub fn with_counter(&self, counter: u32, last: bool) -> Vec<u8> {
+        let mut full_nonce = self.0.clone();
+        full_nonce.extend_from_slice(&counter.to_be_bytes());
+        full_nonce.push(last as u8);
 
         full_nonce
     }
 }
 
-impl Default for Nonce {
-    fn default() -> Self {
-        Self::new()
+//---------------------------------------------------
+// SHA-256 of the server's leaf certificate (32 bytes)
+// Pinned through the URL fragment in local mode
+//---------------------------------------------------
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertFingerprint([u8; 32]);
+
+impl CertFingerprint {
+    pub fn from_der(der: &[u8]) -> Self {
+        Self(Sha256::digest(der).into())
+    }
+
+    pub fn to_base64(&self) -> String {
+        general_purpose::URL_SAFE_NO_PAD.encode(self.0)
+    }
+
+    pub fn from_base64(b64: &str) -> anyhow::Result<Self> {
+        let bytes = general_purpose::URL_SAFE_NO_PAD.decode(b64)?;
+        if bytes.len() != 32 {
+            anyhow::bail!("Invalid fingerprint length");
+        }
+        let mut fingerprint = [0u8; 32];
+        fingerprint.copy_from_slice(&bytes);
+        Ok(Self(fingerprint))
+    }
+}
+
+// Colon separated hex, the format browsers show in certificate details
+impl fmt::Display for CertFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hex: Vec<String> = self.0.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(f, "{}", hex.join(":"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nonce_with_counter_layout() {
+        let nonce = Nonce::from_base64(
+            &general_purpose::URL_SAFE_NO_PAD.encode([0xa0; 7]),
+            CipherSuite::Aes256Gcm,
+        )
+        .unwrap();
+
+        assert_eq!(
+            nonce.with_counter(0x01020304, false),
+            [0xa0, 0xa0, 0xa0, 0xa0, 0xa0, 0xa0, 0xa0, 1, 2, 3, 4, 0]
+        );
+        assert_eq!(
+            nonce.with_counter(0x01020304, true),
+            [0xa0, 0xa0, 0xa0, 0xa0, 0xa0, 0xa0, 0xa0, 1, 2, 3, 4, 1]
+        );
+    }
+
+    #[test]
+    fn nonce_length_follows_suite() {
+        let aes = Nonce::new(CipherSuite::Aes256Gcm);
+        assert_eq!(aes.with_counter(7, true).len(), 12);
+        assert!(Nonce::from_base64(&aes.to_base64(), CipherSuite::Aes256Gcm).is_ok());
+        assert!(Nonce::from_base64(&aes.to_base64(), CipherSuite::XChaCha20Poly1305).is_err());
+
+        // XChaCha chunks only need the position
+        let xchacha = Nonce::new(CipherSuite::XChaCha20Poly1305);
+        assert_eq!(xchacha.with_counter(7, true), [0, 0, 0, 7, 1]);
+        assert!(Nonce::from_base64(&xchacha.to_base64(), CipherSuite::XChaCha20Poly1305).is_ok());
     }
 }
@@ -2,13 +2,46 @@ use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
 };
-use tracing::error;
+use std::fmt;
+use tracing::{error, warn};
 
 /// Automatic conversion: any error -> HTTP 500 response
 pub struct AppError(anyhow::Error);
 
+/// The session's downloads are used up, answered with 410 instead of 500
+#[derive(Debug)]
+pub struct SessionGone;
+
+impl fmt::Display for SessionGone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Session has no downloads left")
+    }
+}
+
+impl std::error::Error for SessionGone {}
+
+/// Bad token, client id or passphrase, answered with 403 instead of 500
+/// The rate limiter counts these towards a ban
+#[derive(Debug)]
+pub struct Forbidden(pub &'static str);
+
+impl fmt::Display for Forbidden {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Forbidden {}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        if self.0.is::<SessionGone>() {
+            return StatusCode::GONE.into_response();
+        }
+        if self.0.is::<Forbidden>() {
+            warn!("Rejected request: {:#}", self.0);
+            return StatusCode::FORBIDDEN.into_response();
+        }
         error!(
             error = ?self.0,
             backtrace = ?self.0.backtrace(),
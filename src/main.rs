@@ -1,10 +1,24 @@
-use anyhow::{ensure, Context, Result};
+use anyhow::{bail, ensure, Context, Result};
 use archdrop::{
-    server::{start_receive_server, start_send_server, ServerMode},
+    client::{collect_files, resolve_code, DownloadClient, ProgressEvent, ShareLink, UploadClient},
+    config::{CloudflaredSection, Config},
+    crypto::{code::TransferCode, pake, CipherSuite},
+    server::{
+        ca::{self, LocalCa},
+        start_receive_server, start_send_server, LocalConfig, Recipients, ServerMode,
+        SessionOptions, TlsFiles,
+    },
     transfer::manifest::Manifest,
+    tunnel::{CloudflareTunnel, CommandTunnel, SshTunnel, TunnelProvider, DEFAULT_SSH_HOST},
+    ui::output,
 };
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use indicatif::ProgressBar;
+use regex::Regex;
+use std::io::Write;
+use std::net::IpAddr;
 use std::path::PathBuf;
+use std::time::Duration;
 use tracing_subscriber::EnvFilter;
 use walkdir::WalkDir;
 
@@ -24,18 +38,374 @@ enum Commands {
         #[arg(help = "Path to file to send")]
         paths: Vec<PathBuf>,
 
-        #[arg(long, help = "Use HTTPS with self-signed cert. (Faster)")]
-        local: bool,
+        #[arg(
+            long,
+            default_value_t = 1,
+            value_parser = clap::value_parser!(u32).range(1..),
+            conflicts_with = "unlimited",
+            help = "Number of people who can download before the session ends"
+        )]
+        recipients: u32,
+
+        #[arg(
+            long,
+            help = "Keep serving until Ctrl+C, any number of people can download"
+        )]
+        unlimited: bool,
+
+        #[arg(
+            long,
+            value_parser = clap::value_parser!(u64).range(1..),
+            conflicts_with_all = ["recipients", "unlimited"],
+            help = "Serve at most N clients, later ones are turned away with 410"
+        )]
+        max_downloads: Option<u64>,
+
+        #[arg(
+            long,
+            conflicts_with = "unlimited",
+            help = "Delete the sent files after the last verified download"
+        )]
+        burn: bool,
+
+        #[command(flatten)]
+        server: ServerArgs,
     },
     Receive {
         #[arg(default_value = ".", help = "Destination directory")]
         destination: PathBuf,
 
-        #[arg(long)]
-        local: bool,
+        #[command(flatten)]
+        server: ServerArgs,
+    },
+    Get {
+        #[arg(
+            help = "Full send URL including the #key=... fragment, or a code like 7-guitar-orbit"
+        )]
+        url: String,
+
+        #[arg(default_value = ".", help = "Destination directory")]
+        destination: PathBuf,
+
+        #[command(flatten)]
+        link: LinkArgs,
+    },
+    Put {
+        #[arg(
+            help = "Full receive URL including the #key=... fragment, or a code like 7-guitar-orbit"
+        )]
+        url: String,
+
+        #[arg(required = true, help = "Files or directories to upload")]
+        paths: Vec<PathBuf>,
+
+        #[command(flatten)]
+        link: LinkArgs,
+    },
+    /// Manage the local CA used to sign local mode certificates
+    Ca {
+        #[command(subcommand)]
+        command: CaCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum CaCommands {
+    /// Write the root certificate so it can be installed on other devices
+    Export {
+        #[arg(default_value = "archdrop-ca.pem", help = "Output file")]
+        output: PathBuf,
+
+        #[arg(long, value_enum, default_value_t = CertFormat::Pem)]
+        format: CertFormat,
     },
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+enum CertFormat {
+    Pem,
+    Der,
+}
+
+// Options shared by send and receive
+#[derive(Args)]
+struct ServerArgs {
+    #[arg(long, help = "Use HTTPS with self-signed cert. (Faster)")]
+    local: bool,
+
+    #[arg(
+        long,
+        alias = "interface",
+        help = "Interface (e.g. wlan0), IP or hostname to advertise in local mode"
+    )]
+    host: Option<String>,
+
+    // Tunnel mode listens on 127.0.0.1 only, reject these rather than ignore them
+    #[arg(
+        long,
+        requires = "local",
+        help = "Address to listen on in local mode, e.g. 192.168.1.5 or ::"
+    )]
+    bind: Option<IpAddr>,
+
+    #[arg(
+        long,
+        default_value_t = 0,
+        requires = "local",
+        help = "Port to listen on in local mode (0 = random)"
+    )]
+    port: u16,
+
+    #[arg(
+        long,
+        requires = "tls_key",
+        help = "PEM certificate chain to serve in local mode instead of the local CA"
+    )]
+    tls_cert: Option<PathBuf>,
+
+    #[arg(long, requires = "tls_cert", help = "PEM private key for --tls-cert")]
+    tls_key: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_parser = parse_duration,
+        help = "End the session after this long, e.g. 30m or 1h30m"
+    )]
+    expires: Option<Duration>,
+
+    #[arg(
+        long,
+        value_parser = parse_duration,
+        help = "End the session after this long without activity, e.g. 10m"
+    )]
+    idle_timeout: Option<Duration>,
+
+    #[arg(
+        long,
+        num_args = 0..=1,
+        help = "Require a passphrase before the key is handed out, generated if no value is given"
+    )]
+    passphrase: Option<Option<String>>,
+
+    #[arg(
+        long,
+        conflicts_with = "passphrase",
+        help = "Use a short code like 7-guitar-orbit instead of a link"
+    )]
+    code: bool,
+
+    #[arg(
+        long,
+        help = "Accept or reject each new client in the TUI before it can claim"
+    )]
+    approve: bool,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Append a JSON Lines record of claims, files and completion to this file"
+    )]
+    audit_log: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "SUITE",
+        default_value_t = CipherSuite::Aes256Gcm,
+        help = "aes-256-gcm or xchacha20-poly1305 (faster on CPUs without AES instructions)"
+    )]
+    cipher: CipherSuite,
+
+    #[command(flatten)]
+    tunnel: TunnelArgs,
+}
+
+// How get and put reach a session
+#[derive(Args)]
+struct LinkArgs {
+    #[arg(long, help = "Passphrase for links without a key, prompted if missing")]
+    passphrase: Option<String>,
+
+    #[arg(
+        long,
+        help = "Server to ask for a code, e.g. drop.example.com (default: search the LAN)"
+    )]
+    server: Option<String>,
+
+    #[arg(
+        long,
+        help = "Accept any certificate from servers the link doesn't pin (self signed, no CA)"
+    )]
+    insecure: bool,
+}
+
+impl LinkArgs {
+    /// Share link for a URL or code, with the passphrase it needs if any
+    async fn resolve(self, target: &str, service: &str) -> Result<(ShareLink, Option<String>)> {
+        if target.contains("://") {
+            let link = ShareLink::parse(target)?.with_insecure(self.insecure);
+            let passphrase = passphrase_for(&link, self.passphrase)?;
+            return Ok((link, passphrase));
+        }
+
+        let code: TransferCode = target.parse()?;
+        let link = resolve_code(&code, service, self.server.as_deref(), self.insecure).await?;
+        Ok((link, Some(code.passphrase())))
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum TunnelKind {
+    Cloudflared,
+    Ssh,
+    Command,
+}
+
+// Internet mode, ignored with --local
+#[derive(Args)]
+#[command(next_help_heading = "Tunnel")]
+struct TunnelArgs {
+    #[arg(long = "tunnel", value_enum, default_value_t = TunnelKind::Cloudflared)]
+    kind: TunnelKind,
+
+    #[arg(long, default_value = DEFAULT_SSH_HOST, help = "Destination for --tunnel ssh")]
+    ssh_host: String,
+
+    #[arg(
+        long,
+        default_value_t = 80,
+        help = "Port the ssh server listens on for --tunnel ssh"
+    )]
+    ssh_remote_port: u16,
+
+    #[arg(
+        long,
+        required_if_eq("kind", "command"),
+        help = "Tunnel client to run, {port} is replaced with the local port"
+    )]
+    tunnel_command: Option<String>,
+
+    #[arg(
+        long,
+        help = "Regex for the public URL in the tunnel's output (capture group 1 if present)"
+    )]
+    tunnel_url_regex: Option<Regex>,
+
+    #[arg(
+        long,
+        help = "Fixed public URL for --tunnel ssh to your own server, e.g. behind a reverse proxy"
+    )]
+    tunnel_url: Option<String>,
+
+    #[arg(
+        long,
+        requires = "tunnel_hostname",
+        help = "Named cloudflared tunnel to run instead of a quick tunnel"
+    )]
+    tunnel_name: Option<String>,
+
+    #[arg(
+        long,
+        help = "Hostname routed to the named tunnel, e.g. drop.example.com"
+    )]
+    tunnel_hostname: Option<String>,
+
+    #[arg(long, help = "Credentials file for the named tunnel")]
+    tunnel_credentials: Option<PathBuf>,
+}
+
+impl TunnelArgs {
+    fn provider(self, config: &CloudflaredSection) -> Result<Box<dyn TunnelProvider>> {
+        let tunnel: Box<dyn TunnelProvider> = match self.kind {
+            TunnelKind::Cloudflared => {
+                // Flags win over the config file
+                let name = self.tunnel_name.or_else(|| config.tunnel.clone());
+                let hostname = self.tunnel_hostname.or_else(|| config.hostname.clone());
+                let credentials = self
+                    .tunnel_credentials
+                    .or_else(|| config.credentials_file.clone());
+
+                match (name, hostname) {
+                    (Some(name), Some(hostname)) => {
+                        let mut tunnel = CloudflareTunnel::named(name, &hostname);
+                        if let Some(credentials) = credentials {
+                            tunnel = tunnel.credentials_file(credentials);
+                        }
+                        Box::new(tunnel)
+                    }
+                    (None, None) => Box::new(CloudflareTunnel::new()),
+                    _ => bail!("A named tunnel needs both a tunnel name and a hostname"),
+                }
+            }
+            TunnelKind::Ssh => {
+                let mut tunnel = SshTunnel::new(self.ssh_host).remote_port(self.ssh_remote_port);
+                if let Some(pattern) = self.tunnel_url_regex {
+                    tunnel = tunnel.url_pattern(pattern);
+                }
+                if let Some(url) = self.tunnel_url {
+                    tunnel = tunnel.public_url(url);
+                }
+                Box::new(tunnel)
+            }
+            TunnelKind::Command => {
+                // clap enforces the command for this kind
+                let mut tunnel = CommandTunnel::new(self.tunnel_command.unwrap_or_default());
+                if let Some(pattern) = self.tunnel_url_regex {
+                    tunnel = tunnel.url_pattern(pattern);
+                }
+                Box::new(tunnel)
+            }
+        };
+        Ok(tunnel)
+    }
+}
+
+impl ServerArgs {
+    fn options(&self, config: &Config) -> SessionOptions {
+        SessionOptions {
+            expires: self.expires,
+            idle_timeout: self.idle_timeout,
+            passphrase: self
+                .passphrase
+                .clone()
+                .map(|p| p.unwrap_or_else(pake::generate_passphrase)),
+            code: self.code.then(TransferCode::generate),
+            approve: self.approve,
+            limits: config.limits.clone(),
+            audit_log: self.audit_log.clone(),
+            cipher_suite: self.cipher,
+            ..Default::default()
+        }
+    }
+
+    fn mode(self, config: &Config) -> Result<ServerMode> {
+        if !self.local {
+            return Ok(ServerMode::Tunnel(
+                self.tunnel.provider(&config.cloudflared)?,
+            ));
+        }
+
+        // Flags win over the config file
+        let tls = match (self.tls_cert, self.tls_key) {
+            (Some(cert), Some(key)) => Some(TlsFiles { cert, key }),
+            _ => match (&config.tls.cert, &config.tls.key) {
+                (Some(cert), Some(key)) => Some(TlsFiles {
+                    cert: cert.clone(),
+                    key: key.clone(),
+                }),
+                (None, None) => None,
+                _ => bail!("Config [tls] needs both cert and key"),
+            },
+        };
+
+        Ok(ServerMode::Local(LocalConfig {
+            host: self.host,
+            bind: self.bind,
+            port: self.port,
+            tls,
+        }))
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     // log tracing, default to info
@@ -45,9 +415,17 @@ async fn main() -> Result<()> {
         )
         .init();
     let cli = Cli::parse();
+    let config = Config::load()?;
 
     match cli.command {
-        Commands::Send { paths, local } => {
+        Commands::Send {
+            paths,
+            recipients,
+            unlimited,
+            max_downloads,
+            burn,
+            server,
+        } => {
             // collect all files
             let mut files_to_send = Vec::new();
 
@@ -72,21 +450,30 @@ async fn main() -> Result<()> {
 
             ensure!(!files_to_send.is_empty(), "No files to send");
 
-            let manifest = Manifest::new(files_to_send, None)
+            let manifest = Manifest::new(files_to_send, None, server.cipher)
                 .await
                 .context("Failed to create manifest")?;
 
-            // handle local flag
-            let mode = if local {
-                ServerMode::Local
-            } else {
-                ServerMode::Tunnel
+            // Every download is a claim, later clients get 410
+            let recipients = match max_downloads {
+                Some(max) => Recipients::Limited(max as usize),
+                None if unlimited => Recipients::Unlimited,
+                None => Recipients::Limited(recipients as usize),
+            };
+            let options = SessionOptions {
+                recipients,
+                max_downloads,
+                burn,
+                ..server.options(&config)
             };
 
             //  Start server with mode
-            start_send_server(manifest, mode).await?;
+            start_send_server(manifest, server.mode(&config)?, options).await?;
         }
-        Commands::Receive { destination, local } => {
+        Commands::Receive {
+            destination,
+            server,
+        } => {
             // check dir location exits
             if !destination.exists() {
                 tokio::fs::create_dir_all(&destination)
@@ -101,18 +488,194 @@ async fn main() -> Result<()> {
                 destination.display()
             );
 
-            // handle local flag
-            let mode = if local {
-                ServerMode::Local
-            } else {
-                ServerMode::Tunnel
-            };
-
             //  Start server with mode
-            start_receive_server(destination, mode)
+            let options = server.options(&config);
+            start_receive_server(destination, server.mode(&config)?, options)
                 .await
                 .context("Failed to start file receiver")?;
         }
+        Commands::Get {
+            url,
+            destination,
+            link,
+        } => {
+            let (link, passphrase) = link.resolve(&url, "send").await?;
+            let client = match passphrase {
+                Some(passphrase) => DownloadClient::with_passphrase(link, &passphrase).await?,
+                None => DownloadClient::new(link)?,
+            };
+
+            let progress = output::progress_bar(0);
+            let client = client.on_progress(cli_progress(progress.clone()));
+
+            let files = client
+                .download_all(&destination)
+                .await
+                .context("Download failed")?;
+            progress.finish_and_clear();
+
+            println!(
+                "Downloaded {} file(s) to {}",
+                files.len(),
+                destination.display()
+            );
+        }
+        Commands::Put { url, paths, link } => {
+            let files = collect_files(&paths)?;
+
+            let (link, passphrase) = link.resolve(&url, "receive").await?;
+            let client = match passphrase {
+                Some(passphrase) => UploadClient::with_passphrase(link, &passphrase).await?,
+                None => UploadClient::new(link)?,
+            };
+
+            let progress = output::progress_bar(0);
+            let client = client.on_progress(cli_progress(progress.clone()));
+
+            client.upload_all(&files).await.context("Upload failed")?;
+            progress.finish_and_clear();
+
+            println!("Uploaded {} file(s)", files.len());
+        }
+        Commands::Ca {
+            command: CaCommands::Export { output, format },
+        } => {
+            let ca = LocalCa::load_or_create()?;
+
+            let contents = match format {
+                CertFormat::Pem => ca.cert_pem().as_bytes().to_vec(),
+                CertFormat::Der => ca.cert_der()?,
+            };
+            tokio::fs::write(&output, contents)
+                .await
+                .context(format!("Failed to write {}", output.display()))?;
+
+            println!(
+                "Exported root certificate to {} (stored in {})",
+                output.display(),
+                ca::ca_dir()?.display()
+            );
+            if ca.is_constrained()? {
+                println!("It only signs for private addresses and .local or .home.arpa names");
+            } else {
+                println!(
+                    "Warning: this root predates name constraints and can sign for any name. \
+                     Delete {} and export again to create a constrained one",
+                    ca::ca_dir()?.display()
+                );
+            }
+        }
     }
     Ok(())
 }
+
+// Drive a terminal progress bar from client events
+fn cli_progress(bar: ProgressBar) -> impl Fn(ProgressEvent) + Send + Sync + 'static {
+    move |event| match event {
+        ProgressEvent::Claimed { total_bytes, .. } => bar.set_length(total_bytes),
+        ProgressEvent::FileStarted { .. } => {}
+        ProgressEvent::ChunkDone { bytes, .. } => bar.inc(bytes),
+        ProgressEvent::FileVerified { relative_path, .. } => {
+            bar.println(format!("{} {}", output::check_mark(), relative_path));
+        }
+    }
+}
+
+// Links without a key need the passphrase, ask for it if not given
+fn passphrase_for(link: &ShareLink, passphrase: Option<String>) -> Result<Option<String>> {
+    if !link.requires_passphrase() {
+        return Ok(None);
+    }
+    if let Some(passphrase) = passphrase {
+        return Ok(Some(passphrase));
+    }
+
+    eprint!("Passphrase: ");
+    std::io::stderr().flush()?;
+    let mut line = String::new();
+    std::io::stdin()
+        .read_line(&mut line)
+        .context("Failed to read passphrase")?;
+    ensure!(
+        !line.trim().is_empty(),
+        "A passphrase is required for this link"
+    );
+    Ok(Some(line.trim().to_string()))
+}
+
+// "90s", "15m", "2h", "1d" or combined like "1h30m", a bare number is seconds
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    if let Ok(secs) = s.parse::<u64>() {
+        return match secs {
+            0 => Err("duration must be greater than zero".to_string()),
+            secs => Ok(Duration::from_secs(secs)),
+        };
+    }
+
+    let mut total = 0u64;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return Err(format!("unknown unit '{}', use s, m, h or d", c)),
+        };
+        let value: u64 = number
+            .parse()
+            .map_err(|_| format!("expected a number before '{}'", c))?;
+        total = value
+            .checked_mul(unit)
+            .and_then(|secs| total.checked_add(secs))
+            .ok_or_else(|| format!("duration '{}' is too long", s))?;
+        number.clear();
+    }
+
+    if !number.is_empty() {
+        return Err(format!("missing unit after {}, e.g. {}m", number, number));
+    }
+    if total == 0 {
+        return Err("duration must be greater than zero".to_string());
+    }
+    Ok(Duration::from_secs(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_parse_units_and_combinations() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("15m"), Ok(Duration::from_secs(15 * 60)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(2 * 3600)));
+        assert_eq!(parse_duration("1d"), Ok(Duration::from_secs(86400)));
+        assert_eq!(parse_duration(" 1h30m "), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("1d2h3m4s"), Ok(Duration::from_secs(93784)));
+    }
+
+    #[test]
+    fn invalid_durations_are_rejected() {
+        for input in ["0", "0m", "0h0s", "", "m", "10x", "1h30", "ten", "-5m"] {
+            assert!(parse_duration(input).is_err(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn overflowing_durations_are_rejected() {
+        assert!(parse_duration(&format!("{}d", u64::MAX / 86400 + 1)).is_err());
+        assert!(parse_duration(&format!("{}s1s", u64::MAX)).is_err());
+        assert!(parse_duration("99999999999999999999s").is_err());
+        assert_eq!(
+            parse_duration(&format!("{}s", u64::MAX)),
+            Ok(Duration::from_secs(u64::MAX))
+        );
+    }
+}
@@ -1,23 +1,96 @@
 //! Public API for starting send/receive servers
 
-use super::{runtime, session};
-use crate::crypto::types::{EncryptionKey, Nonce};
+use super::audit::AuditLog;
+use super::helpers;
+use super::runtime::{self, Protocol};
+use super::session::{self, Recipients};
+use crate::config::LimitsSection;
+use crate::crypto::code::TransferCode;
+use crate::crypto::types::EncryptionKey;
+use crate::crypto::CipherSuite;
 use crate::{
     server::{
         routes::{create_receive_router, create_send_router},
         AppState, Session,
     },
     transfer::manifest::Manifest,
+    tunnel::TunnelProvider,
 };
 use anyhow::Result;
 use axum::Router;
 use std::fmt;
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
+use std::time::Duration;
 use tokio::sync::watch;
 
 pub enum ServerMode {
-    Local,
-    Tunnel,
+    Local(LocalConfig),
+    Tunnel(Box<dyn TunnelProvider>),
+}
+
+/// Options for local (LAN) mode
+#[derive(Default, Clone)]
+pub struct LocalConfig {
+    /// Interface name, IP or hostname to advertise. Auto detected if None
+    pub host: Option<String>,
+    /// Address to listen on, all interfaces if None. IPv6 is fine
+    pub bind: Option<IpAddr>,
+    /// Port to listen on, 0 picks a random free port
+    pub port: u16,
+    /// Serve this certificate instead of one from the local CA
+    pub tls: Option<TlsFiles>,
+}
+
+/// PEM certificate chain and private key on disk
+#[derive(Clone)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// Limits for a send or receive session
+#[derive(Default, Clone)]
+pub struct SessionOptions {
+    /// Clients that may download, send only
+    pub recipients: Recipients,
+    /// End the session this long after it started
+    pub expires: Option<Duration>,
+    /// End the session after this long without client requests
+    pub idle_timeout: Option<Duration>,
+    /// Clients must enter this before claiming, the key stays out of the URL
+    pub passphrase: Option<String>,
+    /// Replaces the token and passphrase, typing the code is enough
+    pub code: Option<TransferCode>,
+    /// New clients wait for an accept in the TUI
+    pub approve: bool,
+    /// Completed downloads before the session ends, send only
+    pub max_downloads: Option<u64>,
+    /// Delete the sent files once the session is finished, send only
+    pub burn: bool,
+    /// Per IP rate limit and lockout on the token routes
+    pub limits: LimitsSection,
+    /// Append a JSON Lines record of the session here
+    pub audit_log: Option<PathBuf>,
+    /// Cipher for uploads, receive only, send sessions use the manifest's
+    pub cipher_suite: CipherSuite,
+}
+
+impl ServerMode {
+    /// Trusted header with the client's address, see `auth::client_ip`
+    pub fn client_ip_header(&self) -> Option<&'static str> {
+        match self {
+            ServerMode::Local(_) => None,
+            ServerMode::Tunnel(tunnel) => tunnel.client_ip_header(),
+        }
+    }
+}
+
+impl LocalConfig {
+    pub fn bind_addr(&self) -> SocketAddr {
+        let ip = self.bind.unwrap_or(IpAddr::from([0, 0, 0, 0]));
+        SocketAddr::new(ip, self.port)
+    }
 }
 
 pub enum ServerDirection {
@@ -40,6 +113,9 @@ pub struct ServerInstance {
     pub session: session::Session,
     pub display_name: String,
     pub progress_sender: watch::Sender<f64>,
+    /// Shown next to the QR code, the URL alone is not enough
+    pub passphrase: Option<String>,
+    pub code: Option<TransferCode>,
 }
 
 impl ServerInstance {
@@ -54,35 +130,75 @@ impl ServerInstance {
             session,
             display_name,
             progress_sender,
+            passphrase: None,
+            code: None,
         }
     }
 
+    pub fn with_passphrase(mut self, passphrase: Option<String>) -> Self {
+        self.passphrase = passphrase;
+        self
+    }
+
+    pub fn with_code(mut self, code: Option<TransferCode>) -> Self {
+        self.code = code;
+        self
+    }
+
     pub fn progress_receiver(&self) -> watch::Receiver<f64> {
         self.progress_sender.subscribe()
     }
 }
 
+// Passphrase or code and approval, checked before a client can claim
+fn protect(session: Session, options: &SessionOptions) -> Session {
+    let session = match (&options.code, &options.passphrase) {
+        (Some(code), _) => session.with_code(code),
+        (None, Some(passphrase)) => session.with_passphrase(passphrase),
+        (None, None) => session,
+    };
+    if options.approve {
+        session.with_approval()
+    } else {
+        session
+    }
+}
+
+// Opened before the server starts so a bad path fails right away
+fn audit_log(options: &SessionOptions) -> Result<AuditLog> {
+    match &options.audit_log {
+        Some(path) => AuditLog::open(path),
+        None => Ok(AuditLog::default()),
+    }
+}
+
 // Generic server helper function
 async fn start_server(
     server: ServerInstance,
     app_state: AppState,
     mode: ServerMode,
     direction: ServerDirection,
-    nonce: Nonce,
 ) -> Result<u16> {
     match mode {
-        ServerMode::Local => runtime::start_https(server, app_state, direction, nonce).await,
-        ServerMode::Tunnel => runtime::start_tunnel(server, app_state, direction, nonce).await,
+        ServerMode::Local(config) => {
+            runtime::start_https(server, app_state, direction, config).await
+        }
+        ServerMode::Tunnel(tunnel) => {
+            runtime::start_tunnel(server, app_state, direction, tunnel).await
+        }
     }
 }
 
 //----------------
 // SEND SERVER
 //---------------
-pub async fn start_send_server(manifest: Manifest, mode: ServerMode) -> Result<u16> {
+pub async fn start_send_server(
+    manifest: Manifest,
+    mode: ServerMode,
+    options: SessionOptions,
+) -> Result<u16> {
     // Generate crypto keys
     let session_key = EncryptionKey::new();
-    let nonce = Nonce::new();
 
     // TUI display
     let display_name = if manifest.files.len() == 1 {
@@ -93,27 +209,39 @@ pub async fn start_send_server(manifest: Manifest, mode: ServerMode) -> Result<u
 
     // Send specific session
     let total_chunks = manifest.total_chunks();
-    let session = session::Session::new_send(manifest.clone(), session_key, total_chunks);
+    let session = session::Session::new_send(manifest.clone(), session_key, total_chunks)
+        .with_recipients(options.recipients)
+        .with_max_downloads(options.max_downloads)
+        .with_burn(options.burn)
+        .with_expiry(options.expires, options.idle_timeout);
+    let session = protect(session, &options).with_audit_log(audit_log(&options)?);
     let (progress_sender, _) = tokio::sync::watch::channel(0.0);
 
-    let state = AppState::new_send(session.clone(), progress_sender.clone());
+    let state = AppState::new_send(session.clone(), progress_sender.clone())
+        .with_limits(options.limits)
+        .with_client_ip_header(mode.client_ip_header());
 
     // Create axium router
     // Note: More specific routes must come before less specific ones
     let app = create_send_router(&state);
 
-    let server = ServerInstance::new(app, session, display_name, progress_sender);
+    let server = ServerInstance::new(app, session, display_name, progress_sender)
+        .with_passphrase(options.passphrase)
+        .with_code(options.code);
 
-    start_server(server, state, mode, ServerDirection::Send, nonce).await
+    start_server(server, state, mode, ServerDirection::Send).await
 }
 
 //----------------
 // RECEIVE SERVER
 //----------------
-pub async fn start_receive_server(destination: PathBuf, mode: ServerMode) -> Result<u16> {
+pub async fn start_receive_server(
+    destination: PathBuf,
+    mode: ServerMode,
+    options: SessionOptions,
+) -> Result<u16> {
     // Generate crypto keys
     let session_key = EncryptionKey::new();
-    let nonce = Nonce::new();
 
     // TUI display name
     let display_name = destination
@@ -124,16 +252,23 @@ pub async fn start_receive_server(destination: PathBuf, mode: ServerMode) -> Res
 
     // Receive specific session
     // Start with 0, will be updated when manifest arrives from client
-    let session = session::Session::new_receive(destination.clone(), session_key, 0);
+    let session = session::Session::new_receive(destination.clone(), session_key, 0)
+        .with_cipher_suite(options.cipher_suite)
+        .with_expiry(options.expires, options.idle_timeout);
+    let session = protect(session, &options).with_audit_log(audit_log(&options)?);
     let (progress_sender, _) = tokio::sync::watch::channel(0.0);
 
-    let state = AppState::new_receive(session.clone(), progress_sender.clone());
+    let state = AppState::new_receive(session.clone(), progress_sender.clone())
+        .with_limits(options.limits)
+        .with_client_ip_header(mode.client_ip_header());
 
     let app = create_receive_router(&state);
 
-    let server = ServerInstance::new(app, session, display_name, progress_sender);
+    let server = ServerInstance::new(app, session, display_name, progress_sender)
+        .with_passphrase(options.passphrase)
+        .with_code(options.code);
 
-    start_server(server, state, mode, ServerDirection::Receive, nonce).await
+    start_server(server, state, mode, ServerDirection::Receive).await
 }
 
 //----------------
@@ -142,6 +277,23 @@ pub async fn start_receive_server(destination: PathBuf, mode: ServerMode) -> Res
 // Note: These functions are intended for testing only. They allow tests to:
 // 1. Provide their own encryption keys (for deterministic testing)
 // 2. Access the session token and key (needed by test clients)
+// 3. Drive the server with client::DownloadClient/UploadClient
+//
+// They return as soon as the server is ready, with no TUI or tunnel.
+// Tunnel mode serves plain HTTP, the same origin cloudflared would proxy.
+
+async fn test_protocol(mode: ServerMode) -> Result<(Protocol, SocketAddr)> {
+    match mode {
+        ServerMode::Local(config) => {
+            let tls_config = match &config.tls {
+                Some(files) => helpers::load_cert(files).await?,
+                None => helpers::generate_cert(&["127.0.0.1".to_string()]).await?.0,
+            };
+            Ok((Protocol::Https(tls_config), config.bind_addr()))
+        }
+        ServerMode::Tunnel(_) => Ok((Protocol::Http, runtime::TUNNEL_BIND_ADDR)),
+    }
+}
 
 /// Test helper: starts send server with provided key and returns session
 pub async fn start_send_server_for_test(
@@ -149,24 +301,25 @@ pub async fn start_send_server_for_test(
     session_key: EncryptionKey,
     mode: ServerMode,
 ) -> Result<(u16, Session)> {
-    let nonce = Nonce::new();
-
     // Create session with provided key
     let total_chunks = manifest.total_chunks();
-    let session = session::Session::new_send(manifest.clone(), session_key, total_chunks);
-
-    let display_name = if manifest.files.len() == 1 {
-        manifest.files[0].name.clone()
-    } else {
-        format!("{} files", manifest.files.len())
-    };
+    let session = session::Session::new_send(manifest, session_key, total_chunks);
+    start_send_session_for_test(session, mode).await
+}
 
+/// Test helper: serves an already configured send session, e.g. with a download limit
+pub async fn start_send_session_for_test(
+    session: Session,
+    mode: ServerMode,
+) -> Result<(u16, Session)> {
     let (progress_sender, _) = tokio::sync::watch::channel(0.0);
-    let state = AppState::new_send(session.clone(), progress_sender.clone());
+    let state = AppState::new_send(session.clone(), progress_sender.clone())
+        .with_client_ip_header(mode.client_ip_header());
     let app = create_send_router(&state);
-    let server = ServerInstance::new(app, session.clone(), display_name, progress_sender);
+    let server = ServerInstance::new(app, session.clone(), "test".to_string(), progress_sender);
 
-    let port = start_server(server, state, mode, ServerDirection::Send, nonce).await?;
+    let (protocol, addr) = test_protocol(mode).await?;
+    let (port, _handle) = runtime::start_local_server(server, protocol, addr).await?;
     Ok((port, session))
 }
 
@@ -176,23 +329,17 @@ pub async fn start_receive_server_for_test(
     session_key: EncryptionKey,
     mode: ServerMode,
 ) -> Result<(u16, Session)> {
-    let nonce = Nonce::new();
-
     // Create session with provided key
     // Start with 0, will be updated when manifest arrives
-    let session = session::Session::new_receive(destination.clone(), session_key, 0);
-
-    let display_name = destination
-        .file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or(".")
-        .to_string();
+    let session = session::Session::new_receive(destination, session_key, 0);
 
     let (progress_sender, _) = tokio::sync::watch::channel(0.0);
-    let state = AppState::new_receive(session.clone(), progress_sender.clone());
+    let state = AppState::new_receive(session.clone(), progress_sender.clone())
+        .with_client_ip_header(mode.client_ip_header());
     let app = create_receive_router(&state);
-    let server = ServerInstance::new(app, session.clone(), display_name, progress_sender);
+    let server = ServerInstance::new(app, session.clone(), "test".to_string(), progress_sender);
 
-    let port = start_server(server, state, mode, ServerDirection::Receive, nonce).await?;
+    let (protocol, addr) = test_protocol(mode).await?;
+    let (port, _handle) = runtime::start_local_server(server, protocol, addr).await?;
     Ok((port, session))
 }
@@ -0,0 +1,208 @@
+//! Manual approval of new claims (`--approve`)
+//!
+//! A client's first claim waits here until the TUI
+//! accepts or rejects it, so someone who photographed the QR code can't
+//! quietly take the session first.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+
+// Unanswered requests are turned away, the client can ask again. Kept well
+// under the ~100 s tunnels like Cloudflare allow a request before cutting it
+const APPROVAL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Who is asking, shown in the TUI and written to the audit log
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ClaimRequest {
+    pub client_id: String,
+    pub ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+}
+
+#[derive(Default)]
+struct ApprovalState {
+    /// Oldest first, the TUI asks about the front one. Counts the requests
+    /// still waiting on each, a client can have several in flight
+    pending: Vec<(ClaimRequest, usize)>,
+    decisions: HashMap<String, bool>,
+}
+
+#[derive(Clone)]
+pub struct Approvals {
+    state: Arc<Mutex<ApprovalState>>,
+    // Bumped on every decision to wake waiting requests
+    decided: Arc<watch::Sender<()>>,
+}
+
+impl Default for Approvals {
+    fn default() -> Self {
+        Self {
+            state: Arc::default(),
+            decided: Arc::new(watch::channel(()).0),
+        }
+    }
+}
+
+impl Approvals {
+    /// Wait for the decision on this client, earlier decisions are reused
+    pub async fn request(&self, request: ClaimRequest) -> bool {
+        let client_id = request.client_id.clone();
+        // Subscribe before checking so a decision in between isn't missed
+        let mut decided = self.decided.subscribe();
+
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(accepted) = state.decisions.get(&client_id) {
+                return *accepted;
+            }
+            match state
+                .pending
+                .iter_mut()
+                .find(|(r, _)| r.client_id == client_id)
+            {
+                Some((_, waiting)) => *waiting += 1,
+                None => {
+                    tracing::debug!("Claim from {} waiting for approval", client_id);
+                    state.pending.push((request, 1));
+                }
+            }
+        }
+        let _waiting = Waiting {
+            approvals: self,
+            client_id: &client_id,
+        };
+
+        let wait = async {
+            loop {
+                if let Some(accepted) = self.decision(&client_id) {
+                    return accepted;
+                }
+                if decided.changed().await.is_err() {
+                    return false;
+                }
+            }
+        };
+
+        tokio::time::timeout(APPROVAL_TIMEOUT, wait)
+            .await
+            .unwrap_or(false)
+    }
+
+    fn decision(&self, client_id: &str) -> Option<bool> {
+        self.state.lock().unwrap().decisions.get(client_id).copied()
+    }
+
+    /// Oldest request still waiting
+    pub fn next_pending(&self) -> Option<ClaimRequest> {
+        self.state
+            .lock()
+            .unwrap()
+            .pending
+            .first()
+            .map(|(request, _)| request.clone())
+    }
+
+    pub fn decide(&self, client_id: &str, accept: bool) {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.pending.retain(|(r, _)| r.client_id != client_id);
+            state.decisions.insert(client_id.to_string(), accept);
+        }
+        tracing::debug!(
+            "Claim from {} {}",
+            client_id,
+            if accept { "accepted" } else { "rejected" }
+        );
+        self.decided.send_replace(());
+    }
+}
+
+// One request waiting in `Approvals::request`. Dropped when it's answered,
+// times out, or the client or tunnel gives up on the HTTP request, the TUI
+// stops asking once nobody is waiting
+struct Waiting<'a> {
+    approvals: &'a Approvals,
+    client_id: &'a str,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        let mut state = self.approvals.state.lock().unwrap();
+        if let Some(index) = state
+            .pending
+            .iter()
+            .position(|(r, _)| r.client_id == self.client_id)
+        {
+            state.pending[index].1 -= 1;
+            if state.pending[index].1 == 0 {
+                state.pending.remove(index);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(client_id: &str) -> ClaimRequest {
+        ClaimRequest {
+            client_id: client_id.to_string(),
+            ip: None,
+            user_agent: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn dropped_requests_leave_the_prompt() {
+        let approvals = Approvals::default();
+        let first = tokio::spawn({
+            let approvals = approvals.clone();
+            async move { approvals.request(request("first")).await }
+        });
+        let second = tokio::spawn({
+            let approvals = approvals.clone();
+            async move { approvals.request(request("first")).await }
+        });
+        while approvals
+            .state
+            .lock()
+            .unwrap()
+            .pending
+            .first()
+            .map(|(_, n)| *n)
+            != Some(2)
+        {
+            tokio::task::yield_now().await;
+        }
+
+        // Still asked about while one request waits
+        first.abort();
+        let _ = first.await;
+        assert_eq!(approvals.next_pending().unwrap().client_id, "first");
+
+        second.abort();
+        let _ = second.await;
+        assert!(approvals.next_pending().is_none());
+    }
+
+    #[tokio::test]
+    async fn decisions_answer_waiting_requests() {
+        let approvals = Approvals::default();
+        let waiting = tokio::spawn({
+            let approvals = approvals.clone();
+            async move { approvals.request(request("first")).await }
+        });
+        while approvals.next_pending().is_none() {
+            tokio::task::yield_now().await;
+        }
+
+        approvals.decide("first", true);
+        assert!(waiting.await.unwrap());
+        assert!(approvals.next_pending().is_none());
+        assert!(approvals.request(request("first")).await);
+    }
+}
@@ -0,0 +1,182 @@
+//! Append-only audit log of a session (`--audit-log`)
+//!
+//! One JSON object per line: when it happened, which session, and the
+//! event. Claims carry the client's IP and user agent, every later event
+//! names the client id so each file can be traced back to who fetched it.
+//! Sessions are identified by a random id, the token is a secret and stays
+//! out of the log.
+
+use crate::crypto::CipherSuite;
+use crate::server::approval::ClaimRequest;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+#[derive(Debug, Serialize)]
+pub struct AuditFile {
+    pub path: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent {
+    Created {
+        mode: &'static str,
+        /// Offered files, send only
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        files: Vec<AuditFile>,
+        /// Where uploads go, receive only
+        #[serde(skip_serializing_if = "Option::is_none")]
+        destination: Option<PathBuf>,
+        cipher: CipherSuite,
+        passphrase: bool,
+        approve: bool,
+        max_downloads: Option<u64>,
+        burn: bool,
+    },
+    ClaimAccepted {
+        #[serde(flatten)]
+        client: ClaimRequest,
+    },
+    ClaimRejected {
+        #[serde(flatten)]
+        client: ClaimRequest,
+        reason: &'static str,
+    },
+    FileStarted {
+        client_id: String,
+        path: String,
+        size: u64,
+    },
+    FileFinished {
+        client_id: String,
+        path: String,
+        size: u64,
+        sha256: String,
+    },
+    Completed {
+        client_id: String,
+    },
+    Expired,
+    Shutdown {
+        reason: &'static str,
+        /// Transfers still running when the server stopped
+        pending_transfers: usize,
+    },
+}
+
+#[derive(Serialize)]
+struct AuditLine<'a> {
+    time: String,
+    session: &'a str,
+    #[serde(flatten)]
+    event: &'a AuditEvent,
+}
+
+struct AuditFileLog {
+    session: String,
+    file: Mutex<File>,
+}
+
+/// Shared by every clone of the session, does nothing unless opened
+#[derive(Clone, Default)]
+pub struct AuditLog {
+    inner: Option<Arc<AuditFileLog>>,
+}
+
+impl AuditLog {
+    /// Append to the file at path, created if missing
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context(format!("Failed to open audit log {}", path.display()))?;
+
+        Ok(Self {
+            inner: Some(Arc::new(AuditFileLog {
+                session: Uuid::new_v4().to_string(),
+                file: Mutex::new(file),
+            })),
+        })
+    }
+
+    /// Write one line, a failed write is logged but never fails the request
+    /// Blocking on purpose: a short append and flush under the lock, a
+    /// handful per transfer. Written before the caller moves on, so the
+    /// shutdown line is on disk when the process exits
+    pub fn record(&self, event: AuditEvent) {
+        let Some(inner) = &self.inner else {
+            return;
+        };
+
+        let line = AuditLine {
+            time: chrono::Utc::now().to_rfc3339(),
+            session: &inner.session,
+            event: &event,
+        };
+        let result = serde_json::to_string(&line)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| {
+                // One write per line so concurrent sessions don't interleave
+                let mut file = inner.file.lock().unwrap();
+                file.write_all(format!("{}\n", json).as_bytes())?;
+                Ok(file.flush()?)
+            });
+
+        if let Err(e) = result {
+            tracing::error!("Failed to write audit log: {:#}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::types::EncryptionKey;
+    use crate::server::Session;
+    use serde_json::Value;
+
+    #[test]
+    fn lines_are_json_without_the_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let session = Session::new_receive(dir.path().to_path_buf(), EncryptionKey::new(), 0)
+            .with_audit_log(AuditLog::open(&path).unwrap());
+
+        session.audit().record(AuditEvent::ClaimRejected {
+            client: ClaimRequest {
+                client_id: "client".to_string(),
+                ip: Some("203.0.113.7".parse().unwrap()),
+                user_agent: Some("curl/8".to_string()),
+            },
+            reason: "Not approved",
+        });
+        session.audit().record(AuditEvent::Shutdown {
+            reason: "interrupted",
+            pending_transfers: 0,
+        });
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        assert!(!log.contains(session.token()));
+
+        let lines: Vec<Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let events: Vec<&str> = lines.iter().map(|l| l["event"].as_str().unwrap()).collect();
+        assert_eq!(events, ["created", "claim_rejected", "shutdown"]);
+        for line in &lines {
+            assert!(line.get("token").is_none());
+            assert_eq!(line["session"], lines[0]["session"]);
+        }
+        assert_eq!(lines[1]["ip"], "203.0.113.7");
+        assert_eq!(lines[1]["reason"], "Not approved");
+        assert_eq!(lines[2]["pending_transfers"], 0);
+    }
+}
@@ -1,10 +1,50 @@
-use crate::errors::AppError;
+use crate::errors::{AppError, Forbidden, SessionGone};
+use crate::server::approval::ClaimRequest;
+use crate::server::audit::AuditEvent;
 use crate::server::session::Session;
+use crate::server::state::AppState;
+use axum::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::{header, request::Parts, HeaderMap};
+use std::net::{IpAddr, SocketAddr};
 
-#[derive(serde::Deserialize)]
-pub struct ClientIdParam {
-    #[serde(rename = "clientId")]
-    pub client_id: String,
+/// Set after a client proved it holds the session key
+pub const CLAIM_COOKIE: &str = "archdrop_claim";
+
+/// Client id from the signed claim cookie, requests without one get 403
+pub struct ClaimedClient(pub String);
+
+#[async_trait]
+impl FromRequestParts<AppState> for ClaimedClient {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, AppError> {
+        cookie_client(&parts.headers, &state.session)
+            .map(ClaimedClient)
+            .ok_or_else(|| Forbidden("Missing or invalid claim cookie").into())
+    }
+}
+
+/// Client id of a valid claim cookie in the request, if any
+pub fn cookie_client(headers: &HeaderMap, session: &Session) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().strip_prefix(CLAIM_COOKIE)?.strip_prefix('='))
+        .find_map(|value| session.client_from_cookie(value))
+}
+
+/// Set-Cookie value, scoped to the session's routes and hidden from scripts
+pub fn claim_cookie(session: &Session, service: &str, client_id: &str) -> String {
+    format!(
+        "{}={}; Path=/{}/{}; HttpOnly; Secure; SameSite=Strict",
+        CLAIM_COOKIE,
+        session.claim_cookie(client_id),
+        service,
+        session.token()
+    )
 }
 
 // Used for handlers that should only work with already claimed sessions
@@ -13,22 +53,128 @@ pub fn require_active_session(
     token: &str,
     client_id: &str,
 ) -> Result<(), AppError> {
+    if session.is_expired() {
+        return Err(anyhow::anyhow!("Session expired").into());
+    }
+    if session.is_finished() {
+        return Err(SessionGone.into());
+    }
     if !session.is_active(token, client_id) {
-        return Err(anyhow::anyhow!("Invalid or inactive session").into());
+        // Clients past the download limit, or done with theirs
+        if session.downloads_exhausted() {
+            return Err(SessionGone.into());
+        }
+        return Err(Forbidden("Invalid or inactive session").into());
     }
+    // Keeps the idle timeout from firing mid transfer
+    session.touch();
     Ok(())
 }
 
-// Used for handlers that initiate transfer
+// Used by the claim handler once the client proved the key
 pub fn claim_or_validate_session(
     session: &Session,
     token: &str,
-    client_id: &str,
+    client: &ClaimRequest,
 ) -> Result<(), AppError> {
-    if !session.claim(token, client_id) {
-        return Err(
-            anyhow::anyhow!("Invalid token or session already claimed by another client").into(),
-        );
+    if session.is_expired() {
+        reject_claim(session, client, "Session expired");
+        return Err(anyhow::anyhow!("Session expired").into());
+    }
+    if session.is_finished() {
+        reject_claim(session, client, "No downloads left");
+        return Err(SessionGone.into());
+    }
+    if !session.claim(token, client) {
+        if session.downloads_exhausted() {
+            return Err(SessionGone.into());
+        }
+        return Err(Forbidden("Invalid token or session already claimed by another client").into());
     }
+    session.touch();
     Ok(())
 }
+
+// Refused before Session::claim got to decide, audited the same way
+pub fn reject_claim(session: &Session, client: &ClaimRequest, reason: &'static str) {
+    session.audit().record(AuditEvent::ClaimRejected {
+        client: client.clone(),
+        reason,
+    });
+}
+
+/// Who is claiming, for the approval prompt and the audit log
+pub fn claim_request(client_id: &str, headers: &HeaderMap, ip: IpAddr) -> ClaimRequest {
+    ClaimRequest {
+        client_id: client_id.to_string(),
+        ip: Some(ip),
+        user_agent: headers
+            .get(header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string),
+    }
+}
+
+// Hold a new client's claim until it is accepted in the TUI (--approve)
+// Run before claim_or_validate_session, existing claims pass straight through
+pub async fn await_approval(
+    session: &Session,
+    token: &str,
+    client: &ClaimRequest,
+) -> Result<(), AppError> {
+    let Some(approvals) = session.approvals() else {
+        return Ok(());
+    };
+    // Anything the claim would refuse anyway is not worth asking about
+    if !session.is_new_claim(token, &client.client_id) {
+        return Ok(());
+    }
+
+    if !approvals.request(client.clone()).await {
+        reject_claim(session, client, "Not approved");
+        return Err(anyhow::anyhow!("Claim was not approved").into());
+    }
+    Ok(())
+}
+
+// Tunnels connect from loopback, the real address is in `trusted_header`
+// when the tunnel sets one (cloudflared). Anything else is the peer address,
+// client supplied headers are never believed
+pub fn client_ip(headers: &HeaderMap, peer: SocketAddr, trusted_header: Option<&str>) -> IpAddr {
+    if !peer.ip().is_loopback() {
+        return peer.ip();
+    }
+    trusted_header
+        .and_then(|name| headers.get(name)?.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(peer.ip())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("cf-connecting-ip", "203.0.113.7".parse().unwrap());
+        headers.insert("x-forwarded-for", "198.51.100.1".parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn client_ip_trusts_only_the_tunnel_header() {
+        let loopback = SocketAddr::from(([127, 0, 0, 1], 4000));
+        let ip = client_ip(&headers(), loopback, Some("cf-connecting-ip"));
+        assert_eq!(ip, IpAddr::from([203, 0, 113, 7]));
+
+        // Other tunnels set no trusted header, forwarded headers are ignored
+        assert_eq!(client_ip(&headers(), loopback, None), loopback.ip());
+    }
+
+    #[test]
+    fn client_ip_ignores_headers_from_remote_peers() {
+        let peer = SocketAddr::from(([192, 168, 1, 20], 4000));
+        let ip = client_ip(&headers(), peer, Some("cf-connecting-ip"));
+        assert_eq!(ip, peer.ip());
+    }
+}
@@ -0,0 +1,312 @@
+//! Persistent local certificate authority for local mode
+//!
+//! The root is generated once and kept under the XDG data dir. Each run
+//! issues a fresh leaf for the advertised host, so a device that trusts the
+//! root (see `archdrop ca export`) trusts every local session.
+//!
+//! The root carries name constraints, it can only vouch for private
+//! addresses and local names. A leaked CA key can't be used to impersonate
+//! public sites on the devices that installed the root.
+
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose, Engine};
+use chrono::{Datelike, Duration, Utc};
+use rcgen::{
+    date_time_ymd, BasicConstraints, Certificate, CertificateParams, CidrSubnet, DistinguishedName,
+    DnType, ExtendedKeyUsagePurpose, GeneralSubtree, IsCa, KeyPair, KeyUsagePurpose,
+    NameConstraints,
+};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+const CA_COMMON_NAME: &str = "ArchDrop Local CA";
+const CA_ORGANIZATION: &str = "ArchDrop";
+const CA_CERT_FILE: &str = "ca.pem";
+const CA_KEY_FILE: &str = "ca.key";
+
+// Name constraints on the root: RFC 1918, CGNAT (Tailscale), loopback and
+// link local ranges, plus mDNS and home network names with their subdomains
+const PERMITTED_NETWORKS: &[(&str, u8)] = &[
+    ("10.0.0.0", 8),
+    ("172.16.0.0", 12),
+    ("192.168.0.0", 16),
+    ("100.64.0.0", 10),
+    ("127.0.0.0", 8),
+    ("169.254.0.0", 16),
+    ("::1", 128),
+    ("fc00::", 7),
+    ("fe80::", 10),
+];
+const PERMITTED_DOMAINS: &[&str] = &["local", "home.arpa", "localhost"];
+
+// DER of the nameConstraints extension OID, 2.5.29.30
+const NAME_CONSTRAINTS_OID: [u8; 5] = [0x06, 0x03, 0x55, 0x1d, 0x1e];
+
+// Short lived leaves, well under the 398 day limit some platforms enforce
+const LEAF_VALID_DAYS: i64 = 30;
+
+pub struct LocalCa {
+    cert_pem: String,
+    signer: Certificate,
+}
+
+impl LocalCa {
+    /// Load the CA from the data dir, creating it on first use
+    pub fn load_or_create() -> Result<Self> {
+        Self::load_or_create_in(&ca_dir()?)
+    }
+
+    pub fn load_or_create_in(dir: &Path) -> Result<Self> {
+        let cert_path = dir.join(CA_CERT_FILE);
+        let key_path = dir.join(CA_KEY_FILE);
+
+        if cert_path.exists() && key_path.exists() {
+            let cert_pem = std::fs::read_to_string(&cert_path)
+                .context(format!("Failed to read {}", cert_path.display()))?;
+            let key_pem = std::fs::read_to_string(&key_path)
+                .context(format!("Failed to read {}", key_path.display()))?;
+            let key_pair = KeyPair::from_pem(&key_pem).context("Invalid CA key")?;
+
+            // Same subject + same key signs leaves the stored root validates
+            let signer = Certificate::from_params(ca_params(key_pair))
+                .context("Failed to load CA certificate")?;
+
+            return Ok(Self { cert_pem, signer });
+        }
+
+        let key_pair = KeyPair::generate(&rcgen::PKCS_ECDSA_P256_SHA256)?;
+        let signer =
+            Certificate::from_params(ca_params(key_pair)).context("Failed to generate CA")?;
+        let cert_pem = signer
+            .serialize_pem()
+            .context("Failed to serialize CA certificate")?;
+
+        std::fs::create_dir_all(dir).context(format!("Failed to create {}", dir.display()))?;
+        write_private(&key_path, &signer.serialize_private_key_pem())?;
+        std::fs::write(&cert_path, &cert_pem)
+            .context(format!("Failed to write {}", cert_path.display()))?;
+
+        tracing::info!("Created local CA in {}", dir.display());
+        Ok(Self { cert_pem, signer })
+    }
+
+    pub fn cert_pem(&self) -> &str {
+        &self.cert_pem
+    }
+
+    pub fn cert_der(&self) -> Result<Vec<u8>> {
+        first_cert_der(&self.cert_pem)
+    }
+
+    /// Roots created before name constraints were added can sign for any name
+    pub fn is_constrained(&self) -> Result<bool> {
+        Ok(self
+            .cert_der()?
+            .windows(NAME_CONSTRAINTS_OID.len())
+            .any(|w| w == NAME_CONSTRAINTS_OID))
+    }
+
+    /// Issue a leaf for hosts, returns (cert chain PEM, key PEM)
+    pub fn issue(&self, hosts: &[String]) -> Result<(String, String)> {
+        for host in hosts.iter().filter(|host| !is_permitted(host)) {
+            tracing::warn!(
+                "{} is outside the local CA's name constraints, only the link's pin covers it",
+                host
+            );
+        }
+
+        let mut params = CertificateParams::new(hosts.to_vec());
+
+        let mut dn = DistinguishedName::new();
+        dn.push(
+            DnType::CommonName,
+            hosts.first().cloned().unwrap_or_default(),
+        );
+        params.distinguished_name = dn;
+
+        let yesterday = Utc::now() - Duration::days(1);
+        let expires = Utc::now() + Duration::days(LEAF_VALID_DAYS);
+        params.not_before = date_time_ymd(
+            yesterday.year(),
+            yesterday.month() as u8,
+            yesterday.day() as u8,
+        );
+        params.not_after =
+            date_time_ymd(expires.year(), expires.month() as u8, expires.day() as u8);
+
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+        params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+        params.use_authority_key_identifier_extension = true;
+
+        let leaf = Certificate::from_params(params).context("Failed to create leaf")?;
+        let leaf_pem = leaf
+            .serialize_pem_with_signer(&self.signer)
+            .context("Failed to sign leaf certificate")?;
+
+        // Serve the root too so clients can build the chain
+        let chain = format!("{}{}", leaf_pem, self.cert_pem);
+        Ok((chain, leaf.serialize_private_key_pem()))
+    }
+}
+
+/// $XDG_DATA_HOME/archdrop (~/.local/share/archdrop)
+pub fn ca_dir() -> Result<PathBuf> {
+    dirs::data_dir()
+        .map(|d| d.join("archdrop"))
+        .context("Could not determine data directory")
+}
+
+fn ca_params(key_pair: KeyPair) -> CertificateParams {
+    let mut params = CertificateParams::default();
+
+    let mut dn = DistinguishedName::new();
+    dn.push(DnType::CommonName, CA_COMMON_NAME);
+    dn.push(DnType::OrganizationName, CA_ORGANIZATION);
+    params.distinguished_name = dn;
+
+    params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+    let networks = PERMITTED_NETWORKS.iter().map(|(ip, prefix)| {
+        GeneralSubtree::IpAddress(CidrSubnet::from_addr_prefix(ip.parse().unwrap(), *prefix))
+    });
+    let domains = PERMITTED_DOMAINS
+        .iter()
+        .map(|domain| GeneralSubtree::DnsName(domain.to_string()));
+    params.name_constraints = Some(NameConstraints {
+        permitted_subtrees: networks.chain(domains).collect(),
+        excluded_subtrees: Vec::new(),
+    });
+    params.alg = &rcgen::PKCS_ECDSA_P256_SHA256;
+    params.key_pair = Some(key_pair);
+    params
+}
+
+// Whether the root's name constraints cover a leaf SAN
+fn is_permitted(host: &str) -> bool {
+    match host.parse::<IpAddr>() {
+        Ok(ip) => PERMITTED_NETWORKS.iter().any(|(network, prefix)| {
+            match (ip, network.parse::<IpAddr>().unwrap()) {
+                (IpAddr::V4(ip), IpAddr::V4(network)) => {
+                    let mask = u32::MAX.checked_shl(32 - *prefix as u32).unwrap_or(0);
+                    u32::from(ip) & mask == u32::from(network) & mask
+                }
+                (IpAddr::V6(ip), IpAddr::V6(network)) => {
+                    let mask = u128::MAX.checked_shl(128 - *prefix as u32).unwrap_or(0);
+                    u128::from(ip) & mask == u128::from(network) & mask
+                }
+                _ => false,
+            }
+        }),
+        Err(_) => {
+            let host = host.trim_end_matches('.').to_ascii_lowercase();
+            PERMITTED_DOMAINS
+                .iter()
+                .any(|domain| host == *domain || host.ends_with(&format!(".{}", domain)))
+        }
+    }
+}
+
+/// DER of the first certificate in a PEM bundle, the leaf for a chain
+pub fn first_cert_der(pem: &str) -> Result<Vec<u8>> {
+    let body: String = pem
+        .lines()
+        .skip_while(|l| !l.starts_with("-----BEGIN"))
+        .skip(1)
+        .take_while(|l| !l.starts_with("-----END"))
+        .collect();
+
+    general_purpose::STANDARD
+        .decode(body)
+        .context("Invalid PEM certificate")
+}
+
+// Private key is only readable by the owner
+fn write_private(path: &Path, contents: &str) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::io::Write;
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)
+            .context(format!("Failed to write {}", path.display()))?;
+        file.write_all(contents.as_bytes())?;
+    }
+
+    #[cfg(not(unix))]
+    std::fs::write(path, contents).context(format!("Failed to write {}", path.display()))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::client::danger::ServerCertVerifier;
+    use rustls::client::WebPkiServerVerifier;
+    use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+    use rustls::RootCertStore;
+    use std::sync::Arc;
+
+    // Chain check the way a device with the root installed does it
+    fn verify(ca: &LocalCa, host: &str) -> bool {
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(CertificateDer::from(ca.cert_der().unwrap()))
+            .unwrap();
+        let verifier = WebPkiServerVerifier::builder_with_provider(
+            Arc::new(roots),
+            Arc::new(rustls::crypto::aws_lc_rs::default_provider()),
+        )
+        .build()
+        .unwrap();
+
+        let (chain, _) = ca.issue(&[host.to_string()]).unwrap();
+        let leaf = CertificateDer::from(first_cert_der(&chain).unwrap());
+        verifier
+            .verify_server_cert(
+                &leaf,
+                &[],
+                &ServerName::try_from(host.to_string()).unwrap(),
+                &[],
+                UnixTime::now(),
+            )
+            .is_ok()
+    }
+
+    #[test]
+    fn root_only_vouches_for_private_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let ca = LocalCa::load_or_create_in(dir.path()).unwrap();
+        assert!(ca.is_constrained().unwrap());
+
+        for host in ["192.168.1.5", "10.0.0.2", "fd00::1", "archbox.local"] {
+            assert!(verify(&ca, host), "{} should verify", host);
+        }
+        for host in ["8.8.8.8", "2001:db8::1", "example.com", "local.example.com"] {
+            assert!(!verify(&ca, host), "{} should be refused", host);
+        }
+    }
+
+    #[test]
+    fn permitted_matches_constraints() {
+        assert!(is_permitted("172.31.255.1"));
+        assert!(!is_permitted("172.32.0.1"));
+        assert!(is_permitted("nas.home.arpa"));
+        assert!(is_permitted("Archbox.Local."));
+        assert!(!is_permitted("notlocal"));
+    }
+
+    #[test]
+    fn reloaded_root_is_the_stored_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let created = LocalCa::load_or_create_in(dir.path()).unwrap();
+        let loaded = LocalCa::load_or_create_in(dir.path()).unwrap();
+        assert_eq!(created.cert_pem(), loaded.cert_pem());
+        assert!(verify(&loaded, "192.168.1.5"));
+    }
+}
@@ -0,0 +1,45 @@
+//! mDNS advertisement for code sessions in local mode
+//!
+//! `archdrop get <code>` browses for the nameplate and gets the share URL
+//! from the TXT record. The record holds nothing secret, the words of the
+//! code are still needed for the PAKE exchange.
+
+use crate::config::MDNS_SERVICE_TYPE;
+use anyhow::{Context, Result};
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+
+pub struct Advertisement {
+    daemon: ServiceDaemon,
+}
+
+/// Publish the share URL under the code's nameplate until dropped
+pub fn advertise(nameplate: &str, service: &str, url: &str, port: u16) -> Result<Advertisement> {
+    let daemon = ServiceDaemon::new().context("Failed to start mDNS")?;
+
+    let instance = format!("archdrop-{}-{}", service, nameplate);
+    let hostname = format!("{}.local.", instance);
+    let properties = [("code", nameplate), ("service", service), ("url", url)];
+
+    // No fixed address, the daemon announces whatever the interfaces have
+    let info = ServiceInfo::new(
+        MDNS_SERVICE_TYPE,
+        &instance,
+        &hostname,
+        "",
+        port,
+        &properties[..],
+    )
+    .context("Invalid mDNS record")?
+    .enable_addr_auto();
+
+    daemon.register(info).context("Failed to advertise code")?;
+    Ok(Advertisement { daemon })
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        if let Err(e) = self.daemon.shutdown() {
+            tracing::debug!("mDNS shutdown failed: {}", e);
+        }
+    }
+}
@@ -1,14 +1,20 @@
+use crate::crypto::types::CertFingerprint;
+use crate::server::ca::{self, LocalCa};
+use crate::server::TlsFiles;
 use crate::ui::tui::TransferUI;
 use anyhow::{Context, Result};
 use axum_server::tls_rustls::RustlsConfig;
-use rcgen::generate_simple_self_signed;
-use std::net::UdpSocket;
-use tokio::sync::watch;
+use std::net::SocketAddr;
 use tokio_util::sync::CancellationToken;
 
-pub async fn wait_for_server_ready(port: u16, timeout_secs: u64, use_https: bool) -> Result<()> {
+pub async fn wait_for_server_ready(
+    addr: SocketAddr,
+    timeout_secs: u64,
+    use_https: bool,
+) -> Result<()> {
     let protocol = if use_https { "https" } else { "http" };
-    let url = format!("{}://127.0.0.1:{}/health", protocol, port);
+    // SocketAddr display brackets IPv6
+    let url = format!("{}://{}/health", protocol, addr);
 
     let client = reqwest::Client::builder()
         .timeout(std::time::Duration::from_millis(500))
@@ -38,45 +44,84 @@ pub async fn wait_for_server_ready(port: u16, timeout_secs: u64, use_https: bool
     }
 }
 
+// Run TUI w/ cancellation support, the terminal is restored before an error
+// comes back so the caller can report it
 pub fn spawn_tui(
-    progress: watch::Receiver<f64>,
-    file_name: String,
-    qr_code: String,
-    is_recieving: bool,
-    status_message: watch::Receiver<Option<String>>,
+    mut ui: TransferUI,
     cancel_token: CancellationToken,
-) -> tokio::task::JoinHandle<()> {
-    tokio::spawn(async move {
-        let mut ui = TransferUI::new(progress, file_name, qr_code, is_recieving, status_message);
+) -> tokio::task::JoinHandle<std::io::Result<()>> {
+    tokio::spawn(async move { ui.run(cancel_token).await })
+}
 
-        // Run TUI w/ cancellation support
-        tokio::select! {
-            result = ui.run() => {
-                if let Err(e) = result {
-                    eprintln!("ui err: {}", e);
-                }
-            }
-            _ = cancel_token.cancelled() => {
-                tracing::debug!("TUI task cancelled gracefully");
-                // TUI will drop and restore terminal automatically
-            }
+// Issue a leaf from the local CA and load directly from memory
+// localhost is always included so the health check still validates
+// Returns the leaf fingerprint for pinning in the share URL
+pub async fn generate_cert(hosts: &[String]) -> Result<(RustlsConfig, CertFingerprint)> {
+    let mut subject_alt_names = hosts.to_vec();
+    for default in ["127.0.0.1", "localhost"] {
+        if !subject_alt_names.iter().any(|h| h == default) {
+            subject_alt_names.push(default.to_string());
         }
-    })
-}
+    }
 
-// Generate certs and load directly from memory
-pub async fn generate_cert(ip: &str) -> Result<RustlsConfig> {
-    let subject_alt_names = vec![ip.to_string(), "localhost".to_string()];
-    let cert = generate_simple_self_signed(subject_alt_names)
-        .context("Failed to generate self-signed certificate")?;
+    let ca = LocalCa::load_or_create().context("Failed to load local CA")?;
+    let (cert_pem, key_pem) = ca
+        .issue(&subject_alt_names)
+        .context("Failed to issue certificate")?;
 
-    let cert_pem = cert
-        .serialize_pem()
-        .context("Failed to serialize certificate to PEM")?
-        .into_bytes();
-    let key_pem = cert.serialize_private_key_pem().into_bytes();
+    let fingerprint = CertFingerprint::from_der(&ca::first_cert_der(&cert_pem)?);
 
-    RustlsConfig::from_pem(cert_pem, key_pem)
+    let tls_config = RustlsConfig::from_pem(cert_pem.into_bytes(), key_pem.into_bytes())
         .await
-        .context("Failed to create TLS configuration")
+        .context("Failed to create TLS configuration")?;
+    Ok((tls_config, fingerprint))
+}
+
+// Certificate supplied by the user, e.g. from an internal CA
+pub async fn load_cert(files: &TlsFiles) -> Result<RustlsConfig> {
+    RustlsConfig::from_pem_file(&files.cert, &files.key)
+        .await
+        .context(format!(
+            "Failed to load TLS certificate {} / key {}",
+            files.cert.display(),
+            files.key.display()
+        ))
+}
+
+// Reload the certificate on SIGHUP so renewals reach long running sessions
+// Existing connections keep the old certificate
+#[cfg(unix)]
+pub fn spawn_cert_reload(
+    tls_config: RustlsConfig,
+    files: TlsFiles,
+) -> Option<tokio::task::JoinHandle<()>> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            tracing::warn!("Certificate reload on SIGHUP unavailable: {}", e);
+            return None;
+        }
+    };
+
+    Some(tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            match tls_config
+                .reload_from_pem_file(&files.cert, &files.key)
+                .await
+            {
+                Ok(()) => tracing::info!("Reloaded TLS certificate {}", files.cert.display()),
+                Err(e) => tracing::error!("Certificate reload failed, keeping the old one: {}", e),
+            }
+        }
+    }))
+}
+
+#[cfg(not(unix))]
+pub fn spawn_cert_reload(
+    _tls_config: RustlsConfig,
+    _files: TlsFiles,
+) -> Option<tokio::task::JoinHandle<()>> {
+    None
 }
@@ -0,0 +1,234 @@
+//! Per IP rate limiting and lockout on the token endpoints
+//!
+//! Every IP gets a token bucket refilled at `requests_per_second`. Requests
+//! `auth` rejects with 403 count as failures, after `max_failures` of them
+//! the IP is banned for `ban_secs`. Behind cloudflared the IP comes from
+//! `cf-connecting-ip`, other tunnels' visitors all share the loopback
+//! address, see `auth::client_ip`.
+
+use crate::config::LimitsSection;
+use crate::server::state::AppState;
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Forget idle IPs once this many are tracked, keeps scanners from growing the map
+const MAX_TRACKED_IPS: usize = 10_000;
+
+struct ClientState {
+    tokens: f64,
+    refilled: Instant,
+    failures: u32,
+    first_failure: Option<Instant>,
+    banned_until: Option<Instant>,
+}
+
+#[derive(Default)]
+struct LimiterState {
+    clients: HashMap<IpAddr, ClientState>,
+    blocked: u64,
+    last_blocked: Option<IpAddr>,
+}
+
+/// Requests turned away so far, shown in the TUI
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BlockedStats {
+    pub requests: u64,
+    pub banned_ips: usize,
+    pub last_ip: Option<IpAddr>,
+}
+
+#[derive(Clone)]
+pub struct RateLimiter {
+    limits: LimitsSection,
+    state: Arc<Mutex<LimiterState>>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(LimitsSection::default())
+    }
+}
+
+impl RateLimiter {
+    pub fn new(limits: LimitsSection) -> Self {
+        Self {
+            limits,
+            state: Arc::default(),
+        }
+    }
+
+    fn ban_duration(&self) -> Duration {
+        Duration::from_secs(self.limits.ban_secs)
+    }
+
+    /// Take one request from the IP's bucket, false if banned or out of tokens
+    pub fn allow(&self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let capacity = (self.limits.requests_per_second + self.limits.burst) as f64;
+        let mut state = self.state.lock().unwrap();
+
+        if state.clients.len() >= MAX_TRACKED_IPS && !state.clients.contains_key(&ip) {
+            let ban = self.ban_duration();
+            state.clients.retain(|_, client| {
+                client.banned_until.is_some_and(|until| until > now)
+                    || now.duration_since(client.refilled) < ban
+            });
+        }
+
+        let client = state.clients.entry(ip).or_insert(ClientState {
+            tokens: capacity,
+            refilled: now,
+            failures: 0,
+            first_failure: None,
+            banned_until: None,
+        });
+
+        let allowed = if client.banned_until.is_some_and(|until| until > now) {
+            false
+        } else {
+            let elapsed = now.duration_since(client.refilled).as_secs_f64();
+            client.tokens =
+                (client.tokens + elapsed * self.limits.requests_per_second as f64).min(capacity);
+            client.refilled = now;
+            if client.tokens >= 1.0 {
+                client.tokens -= 1.0;
+                true
+            } else {
+                false
+            }
+        };
+
+        if !allowed {
+            state.blocked += 1;
+            state.last_blocked = Some(ip);
+        }
+        allowed
+    }
+
+    /// A request from this IP was rejected, bans it after too many
+    pub fn record_failure(&self, ip: IpAddr) {
+        let now = Instant::now();
+        let ban = self.ban_duration();
+        let mut state = self.state.lock().unwrap();
+        let Some(client) = state.clients.get_mut(&ip) else {
+            return;
+        };
+
+        // Failures spread out over a long session don't add up to a ban
+        if client.first_failure.is_none_or(|first| now - first > ban) {
+            client.first_failure = Some(now);
+            client.failures = 0;
+        }
+        client.failures += 1;
+
+        if client.failures >= self.limits.max_failures {
+            tracing::warn!(
+                "Banning {} for {}s after {} rejected requests",
+                ip,
+                ban.as_secs(),
+                client.failures
+            );
+            client.banned_until = Some(now + ban);
+            client.first_failure = None;
+            client.failures = 0;
+        }
+    }
+
+    pub fn blocked(&self) -> BlockedStats {
+        let now = Instant::now();
+        let state = self.state.lock().unwrap();
+        BlockedStats {
+            requests: state.blocked,
+            banned_ips: state
+                .clients
+                .values()
+                .filter(|client| client.banned_until.is_some_and(|until| until > now))
+                .count(),
+            last_ip: state.last_blocked,
+        }
+    }
+}
+
+/// Middleware for the token routes, 429 while limited or banned
+pub async fn rate_limit(
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let ip = state.client_ip(request.headers(), peer);
+    if !state.limiter.allow(ip) {
+        return StatusCode::TOO_MANY_REQUESTS.into_response();
+    }
+
+    let response = next.run(request).await;
+    if response.status() == StatusCode::FORBIDDEN {
+        state.limiter.record_failure(ip);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(requests_per_second: u32, burst: u32, max_failures: u32) -> RateLimiter {
+        RateLimiter::new(LimitsSection {
+            requests_per_second,
+            burst,
+            max_failures,
+            ban_secs: 600,
+        })
+    }
+
+    const IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 168, 1, 23));
+    const OTHER_IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 168, 1, 24));
+
+    #[test]
+    fn bans_after_max_failures() {
+        let limiter = limiter(100, 0, 3);
+        assert!(limiter.allow(IP));
+
+        limiter.record_failure(IP);
+        limiter.record_failure(IP);
+        assert!(limiter.allow(IP));
+
+        limiter.record_failure(IP);
+        assert!(!limiter.allow(IP));
+        assert!(limiter.allow(OTHER_IP));
+
+        let blocked = limiter.blocked();
+        assert_eq!(blocked.banned_ips, 1);
+        assert_eq!(blocked.requests, 1);
+        assert_eq!(blocked.last_ip, Some(IP));
+    }
+
+    #[test]
+    fn failures_from_unseen_ips_are_ignored() {
+        let limiter = limiter(100, 0, 1);
+        limiter.record_failure(IP);
+        assert!(limiter.allow(IP));
+    }
+
+    #[test]
+    fn bucket_refills_over_time() {
+        let limiter = limiter(20, 0, 10);
+        for _ in 0..20 {
+            assert!(limiter.allow(IP));
+        }
+        assert!(!limiter.allow(IP));
+
+        // 20 per second, 100ms buys back about two requests
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(limiter.allow(IP));
+        assert_eq!(limiter.blocked().banned_ips, 0);
+    }
+}
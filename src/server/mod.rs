@@ -1,7 +1,13 @@
 // Submodules
 mod api;
+pub mod approval;
+pub mod audit;
 pub mod auth;
+pub mod ca;
+mod discovery;
 mod helpers;
+pub mod limits;
+mod network;
 mod routes;
 mod runtime;
 mod session;
@@ -10,9 +16,11 @@ mod static_files;
 
 // Public API (what main.rs imports)
 pub use api::{
-    start_receive_server, start_send_server, ServerDirection, ServerInstance, ServerMode,
+    start_receive_server, start_receive_server_for_test, start_send_server,
+    start_send_server_for_test, start_send_session_for_test, LocalConfig, ServerDirection,
+    ServerInstance, ServerMode, SessionOptions, TlsFiles,
 };
 
 // Semi-public (what transfer/ imports)
-pub use session::Session;
+pub use session::{Claim, ClaimState, Recipients, Session};
 pub use state::{AppState, FileReceiveState};
@@ -0,0 +1,119 @@
+//! LAN address detection for local mode
+
+use anyhow::{bail, Result};
+use std::net::{IpAddr, UdpSocket};
+
+// Virtual bridges and VPNs a phone on the LAN can't reach
+const VIRTUAL_PREFIXES: &[&str] = &[
+    "docker", "br-", "veth", "virbr", "vmnet", "vboxnet", "wg", "tun", "tap",
+];
+
+pub struct Interface {
+    pub name: String,
+    pub ip: IpAddr,
+}
+
+/// Non-loopback interfaces, IPv4 first
+pub fn lan_interfaces() -> Vec<Interface> {
+    let mut interfaces: Vec<Interface> = if_addrs::get_if_addrs()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|iface| !iface.is_loopback())
+        .map(|iface| Interface {
+            ip: iface.ip(),
+            name: iface.name,
+        })
+        // link-local v6 needs a scope id, useless in a URL
+        .filter(
+            |iface| !matches!(iface.ip, IpAddr::V6(v6) if (v6.segments()[0] & 0xffc0) == 0xfe80),
+        )
+        .collect();
+
+    interfaces.sort_by_key(|iface| (iface.ip.is_ipv6(), is_virtual(&iface.name)));
+    interfaces
+}
+
+/// Pick the host to put in the URL and certificate
+/// `choice` is an interface name (wlan0), an IP or a hostname
+pub fn select_host(choice: Option<&str>) -> Result<IpOrHost> {
+    let interfaces = lan_interfaces();
+
+    if let Some(choice) = choice {
+        if let Some(iface) = interfaces.iter().find(|i| i.name == choice) {
+            return Ok(IpOrHost::Ip(iface.ip));
+        }
+        if let Ok(ip) = choice.parse::<IpAddr>() {
+            return Ok(IpOrHost::Ip(ip));
+        }
+        // Hostnames must be qualified, a bare word is most likely a mistyped interface
+        if choice.contains('.')
+            && choice
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+        {
+            return Ok(IpOrHost::Host(choice.to_string()));
+        }
+
+        let mut names: Vec<&str> = interfaces.iter().map(|i| i.name.as_str()).collect();
+        names.sort_unstable();
+        names.dedup();
+        bail!(
+            "Unknown interface or host '{}' (available: {})",
+            choice,
+            names.join(", ")
+        );
+    }
+
+    // Physical interfaces sort first
+    if let Some(iface) = interfaces.iter().find(|i| !is_virtual(&i.name)) {
+        return Ok(IpOrHost::Ip(iface.ip));
+    }
+
+    // Fall back to the address the default route would use
+    // connect() on UDP sends no packets
+    if let Some(ip) = default_route_ip() {
+        return Ok(IpOrHost::Ip(ip));
+    }
+
+    if let Some(iface) = interfaces.first() {
+        return Ok(IpOrHost::Ip(iface.ip));
+    }
+
+    tracing::warn!("No LAN interface found, using 127.0.0.1");
+    Ok(IpOrHost::Ip(IpAddr::from([127, 0, 0, 1])))
+}
+
+pub enum IpOrHost {
+    Ip(IpAddr),
+    Host(String),
+}
+
+impl IpOrHost {
+    /// Form used in URLs, IPv6 needs brackets
+    pub fn url_host(&self) -> String {
+        match self {
+            IpOrHost::Ip(IpAddr::V6(v6)) => format!("[{}]", v6),
+            IpOrHost::Ip(ip) => ip.to_string(),
+            IpOrHost::Host(host) => host.clone(),
+        }
+    }
+
+    /// Form used in certificate SANs
+    pub fn san(&self) -> String {
+        match self {
+            IpOrHost::Ip(ip) => ip.to_string(),
+            IpOrHost::Host(host) => host.clone(),
+        }
+    }
+}
+
+fn is_virtual(name: &str) -> bool {
+    VIRTUAL_PREFIXES.iter().any(|p| name.starts_with(p))
+}
+
+fn default_route_ip() -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("8.8.8.8:80").ok()?;
+    let ip = socket.local_addr().ok()?.ip();
+    (!ip.is_loopback() && !ip.is_unspecified()).then_some(ip)
+}
@@ -1,19 +1,32 @@
 //! Router definitions for send and receive modes
 
 use crate::{
-    server::{state::AppState, static_files},
+    server::{limits, state::AppState, static_files},
     transfer,
 };
-use axum::{routing::*, Router};
+use axum::{middleware, routing::*, Router};
 
 /// Create router for send mode
 pub fn create_send_router(state: &AppState) -> Router {
-    Router::new()
-        .route("/health", get(|| async { "OK" }))
+    // Everything that takes a token or code is rate limited per IP
+    let token_routes = Router::new()
         .route(
             "/send/:token/manifest",
             get(transfer::send_handlers::manifest_handler),
         )
+        .route(
+            "/send/:token/pake",
+            post(transfer::pake_handlers::pake_start),
+        )
+        .route(
+            "/send/:token/pake/confirm",
+            post(transfer::pake_handlers::pake_confirm),
+        )
+        .route(
+            "/send/:token/challenge",
+            post(transfer::claim_handlers::challenge),
+        )
+        .route("/send/:token/claim", post(transfer::claim_handlers::claim))
         .route(
             "/send/:token/:file_index/chunk/:chunk_index",
             get(transfer::send_handlers::send_handler),
@@ -26,8 +39,22 @@ pub fn create_send_router(state: &AppState) -> Router {
             "/send/:token/complete",
             post(transfer::send_handlers::complete_download),
         )
+        .route(
+            "/code/:nameplate",
+            get(transfer::pake_handlers::code_lookup),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            limits::rate_limit,
+        ));
+
+    Router::new()
+        .route("/health", get(|| async { "OK" }))
+        .merge(token_routes)
         .route("/send/:token", get(static_files::serve_download_page))
         .route("/download.js", get(static_files::serve_download_js))
+        .route("/", get(static_files::serve_code_page))
+        .route("/code.js", get(static_files::serve_code_js))
         .route("/styles.css", get(static_files::serve_shared_css))
         .route("/shared.js", get(static_files::serve_shared_js))
         .with_state(state.clone())
@@ -35,12 +62,28 @@ pub fn create_send_router(state: &AppState) -> Router {
 
 /// Create router for receive mode
 pub fn create_receive_router(state: &AppState) -> Router {
-    Router::new()
-        .route("/health", get(|| async { "OK" }))
+    // Everything that takes a token or code is rate limited per IP
+    let token_routes = Router::new()
         .route(
             "/receive/:token/manifest",
             post(transfer::receive_handlers::receive_manifest),
         )
+        .route(
+            "/receive/:token/pake",
+            post(transfer::pake_handlers::pake_start),
+        )
+        .route(
+            "/receive/:token/pake/confirm",
+            post(transfer::pake_handlers::pake_confirm),
+        )
+        .route(
+            "/receive/:token/challenge",
+            post(transfer::claim_handlers::challenge),
+        )
+        .route(
+            "/receive/:token/claim",
+            post(transfer::claim_handlers::claim),
+        )
         .route(
             "/receive/:token/chunk",
             post(transfer::receive_handlers::receive_handler),
@@ -49,12 +92,26 @@ pub fn create_receive_router(state: &AppState) -> Router {
             "/receive/:token/finalize",
             post(transfer::receive_handlers::finalize_upload),
         )
-        .route("/receive/:token", get(static_files::serve_upload_page))
         .route(
             "/receive/:token/complete",
             post(transfer::receive_handlers::complete_transfer),
         )
+        .route(
+            "/code/:nameplate",
+            get(transfer::pake_handlers::code_lookup),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            limits::rate_limit,
+        ));
+
+    Router::new()
+        .route("/health", get(|| async { "OK" }))
+        .merge(token_routes)
+        .route("/receive/:token", get(static_files::serve_upload_page))
         .route("/upload.js", get(static_files::serve_upload_js))
+        .route("/", get(static_files::serve_code_page))
+        .route("/code.js", get(static_files::serve_code_js))
         .route("/styles.css", get(static_files::serve_shared_css))
         .route("/shared.js", get(static_files::serve_shared_js))
         .with_state(state.clone())
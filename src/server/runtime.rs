@@ -1,54 +1,124 @@
-use crate::crypto::types::Nonce;
+use crate::crypto::code::TransferCode;
+use crate::server::audit::AuditEvent;
 use crate::server::state::{AppState, TransferStorage};
-use crate::server::{helpers, ServerDirection, ServerInstance};
-use crate::tunnel::CloudflareTunnel;
+use crate::server::{
+    discovery, helpers, network, ClaimState, LocalConfig, Recipients, ServerDirection,
+    ServerInstance, Session,
+};
+use crate::tunnel::supervisor::{self, TunnelEvent};
+use crate::tunnel::TunnelProvider;
+use crate::ui::tui::{RecipientProgress, RecipientsStatus, SessionStatus, TransferUI};
 use crate::ui::{output, qr};
 use anyhow::{Context, Result};
-use std::net::SocketAddr;
-use std::time::Duration;
+use axum_server::tls_rustls::RustlsConfig;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
 use tokio_util::sync::CancellationToken;
 
-enum Protocol {
-    Https,
+// Tunnel mode only needs to be reachable by the tunnel client, random port
+pub const TUNNEL_BIND_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+
+pub enum Protocol {
+    Https(RustlsConfig),
     Http,
 }
 pub async fn start_https(
     server: ServerInstance,
     app_state: AppState,
     direction: ServerDirection,
-    nonce: Nonce,
+    config: LocalConfig,
 ) -> Result<u16> {
     let service = direction.to_string();
 
     // Clone needed before consuming server
     let session = server.session.clone();
     let display_name = server.display_name.clone();
+    let passphrase = server.passphrase.clone();
+    let code = server.code.clone();
     let progress_receiver = server.progress_receiver();
 
-    let (port, server_handle) = start_local_server(server, Protocol::Https).await?;
+    // Advertise a LAN address so other devices can reach us
+    // Bound to one address, that is the only one that works
+    let host = match (&config.host, config.bind) {
+        (None, Some(ip)) if !ip.is_unspecified() => network::IpOrHost::Ip(ip),
+        _ => network::select_host(config.host.as_deref())?,
+    };
+    // Our own certificate gets pinned, a supplied one is trusted through its CA
+    let (tls_config, fingerprint) = match &config.tls {
+        Some(files) => {
+            if config.host.is_none() {
+                tracing::warn!(
+                    "Advertising {}, pass --host with the name your certificate covers",
+                    host.url_host()
+                );
+            }
+            (helpers::load_cert(files).await?, None)
+        }
+        None => {
+            let (tls_config, fingerprint) = helpers::generate_cert(&[host.san()])
+                .await
+                .context("Failed to generate TLS certificate")?;
+            (tls_config, Some(fingerprint))
+        }
+    };
+
+    let reload_task = config
+        .tls
+        .clone()
+        .and_then(|files| helpers::spawn_cert_reload(tls_config.clone(), files));
 
-    let base_url = format!("https://127.0.0.1:{}", port);
-    let url = format!(
-        "{}/{}/{}#key={}&nonce={}",
+    let (port, server_handle) =
+        start_local_server(server, Protocol::Https(tls_config), config.bind_addr()).await?;
+
+    let base_url = format!("https://{}:{}", host.url_host(), port);
+    let mut url = format!(
+        "{}/{}/{}#{}",
         base_url,
         service,
         session.token(),
-        session.session_key_b64(),
-        nonce.to_base64()
+        share_fragment(&session)
     );
+    if let Some(fingerprint) = &fingerprint {
+        url.push_str(&format!("&fp={}", fingerprint.to_base64()));
+    }
 
     println!("{}", url);
+    if let Some(fingerprint) = &fingerprint {
+        println!("Certificate SHA-256: {}", fingerprint);
+    }
+    print_passphrase(passphrase.as_deref());
+    print_code(code.as_ref(), &service, &base_url);
+
+    // Lets `archdrop get <code>` find us, typing the address works without it
+    let _advertisement = code.as_ref().and_then(|code| {
+        discovery::advertise(code.nameplate(), &service, &url, port)
+            .inspect_err(|e| tracing::warn!("Code not advertised on the LAN: {:#}", e))
+            .ok()
+    });
 
-    run_session(
+    let display = SessionDisplay {
+        name: display_name,
+        url,
+        fingerprint: fingerprint.map(|fp| fp.to_string()),
+        passphrase,
+        code: code.map(|code| code.to_string()),
+    };
+    let result = run_session(
         server_handle,
         app_state,
         None,
-        display_name,
+        display,
         progress_receiver,
-        url,
         service,
     )
-    .await?;
+    .await;
+
+    if let Some(task) = reload_task {
+        task.abort();
+    }
+    result?;
     Ok(port)
 }
 
@@ -56,41 +126,65 @@ pub async fn start_tunnel(
     server: ServerInstance,
     app_state: AppState,
     direction: ServerDirection,
-    nonce: Nonce,
+    mut tunnel: Box<dyn TunnelProvider>,
 ) -> Result<u16> {
     let service = direction.to_string();
 
     // Clone what we need before consuming server
     let session = server.session.clone();
     let display_name = server.display_name.clone();
+    let passphrase = server.passphrase.clone();
+    let code = server.code.clone();
     let progress_receiver = server.progress_receiver();
 
-    let (port, server_handle) = start_local_server(server, Protocol::Http).await?;
+    let (port, server_handle) =
+        start_local_server(server, Protocol::Http, TUNNEL_BIND_ADDR).await?;
 
     // Start tunnel
-    let tunnel = CloudflareTunnel::start(port)
-        .await
-        .context("Failed to establish Cloudflare tunnel")?;
+    let spinner = output::spinner(&format!("Starting {} tunnel...", tunnel.name()));
+    spinner.enable_steady_tick(Duration::from_millis(80));
+    let tunnel_url = match tunnel.start(port).await {
+        Ok(url) => url,
+        Err(e) => {
+            output::spinner_error(&spinner, "Tunnel failed");
+            return Err(e).context(format!("Failed to establish {} tunnel", tunnel.name()));
+        }
+    };
+    output::spinner_success(&spinner, "Tunnel established");
 
-    // Ensure tunnel URL doesn't have trailing slash
-    let tunnel_url = tunnel.url().trim_end_matches('/');
-    let url = format!(
-        "{}/{}/{}#key={}&nonce={}",
-        tunnel_url,
+    // Everything after the base URL, reused if the tunnel comes back elsewhere
+    let share_path = format!(
+        "/{}/{}#{}",
         service,
         session.token(),
-        session.session_key_b64(),
-        nonce.to_base64()
+        share_fragment(&session)
     );
+
+    // Ensure tunnel URL doesn't have trailing slash
+    let url = format!("{}{}", tunnel_url.trim_end_matches('/'), share_path);
     println!("{}", url);
+    print_passphrase(passphrase.as_deref());
+    print_code(code.as_ref(), &service, tunnel_url.trim_end_matches('/'));
 
+    // Tunnel certificates are publicly trusted, nothing to pin
+    let display = SessionDisplay {
+        name: display_name,
+        url,
+        fingerprint: None,
+        passphrase,
+        code: code.map(|code| code.to_string()),
+    };
+    let tunnel = ActiveTunnel {
+        provider: tunnel,
+        local_port: port,
+        share_path,
+    };
     run_session(
         server_handle,
         app_state,
         Some(tunnel),
-        display_name,
+        display,
         progress_receiver,
-        url,
         service,
     )
     .await?;
@@ -98,14 +192,28 @@ pub async fn start_tunnel(
     Ok(port)
 }
 
-async fn start_local_server(
+pub async fn start_local_server(
     server: ServerInstance,
     protocol: Protocol,
+    addr: SocketAddr,
 ) -> Result<(u16, axum_server::Handle)> {
     let spinner = output::spinner("Starting local HTTPS server...");
-    // Bind to random port
-    let addr = SocketAddr::from(([0, 0, 0, 0], 0));
-    let listener = std::net::TcpListener::bind(addr).context("Failed to bind socket")?;
+    // Port 0 binds to a random port
+    let listener = match std::net::TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+            output::spinner_error(&spinner, "Port already in use");
+            anyhow::bail!(
+                "Port {} is already in use on {}, pick another with --port",
+                addr.port(),
+                addr.ip()
+            );
+        }
+        Err(e) => {
+            output::spinner_error(&spinner, "Failed to bind socket");
+            return Err(e).context(format!("Failed to bind {}", addr));
+        }
+    };
 
     listener
         .set_nonblocking(true)
@@ -117,16 +225,19 @@ async fn start_local_server(
     let server_handle = axum_server::Handle::new();
     let server_handle_clone = server_handle.clone();
 
-    // HTTPS uses self signed certs
+    let use_https = matches!(protocol, Protocol::Https(_));
+
+    // HTTPS uses the certs from the caller
     match protocol {
-        Protocol::Https => {
-            let tls_config = helpers::generate_cert("127.0.0.1")
-                .await
-                .context("Failed to generate TLS certificate")?;
+        Protocol::Https(tls_config) => {
             tokio::spawn(async move {
                 if let Err(e) = axum_server::from_tcp_rustls(listener, tls_config)
                     .handle(server_handle_clone)
-                    .serve(server.app.into_make_service())
+                    .serve(
+                        server
+                            .app
+                            .into_make_service_with_connect_info::<SocketAddr>(),
+                    )
                     .await
                 {
                     eprintln!("Server error: {}", e);
@@ -137,7 +248,11 @@ async fn start_local_server(
             tokio::spawn(async move {
                 if let Err(e) = axum_server::from_tcp(listener)
                     .handle(server_handle_clone)
-                    .serve(server.app.into_make_service())
+                    .serve(
+                        server
+                            .app
+                            .into_make_service_with_connect_info::<SocketAddr>(),
+                    )
                     .await
                 {
                     eprintln!("Server error: {}", e);
@@ -146,8 +261,13 @@ async fn start_local_server(
         }
     }
 
-    let use_https = matches!(protocol, Protocol::Https);
-    helpers::wait_for_server_ready(port, 5, use_https)
+    // Health check over loopback unless bound to one address
+    let check_ip = match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    helpers::wait_for_server_ready(SocketAddr::new(check_ip, port), 5, use_https)
         .await
         .context("Server failed to become ready")?;
     output::spinner_success(&spinner, &format!("Server ready on port {}", port));
@@ -155,33 +275,86 @@ async fn start_local_server(
     Ok((port, server_handle))
 }
 
+// Key and cipher suite for the URL fragment, file nonces travel with the files
+// Passphrase sessions leave the key out, clients get it from the exchange
+fn share_fragment(session: &Session) -> String {
+    if session.requires_passphrase() {
+        format!("pake=1&suite={}", session.cipher_suite())
+    } else {
+        format!(
+            "key={}&suite={}",
+            session.session_key_b64(),
+            session.cipher_suite()
+        )
+    }
+}
+
+fn print_passphrase(passphrase: Option<&str>) {
+    if let Some(passphrase) = passphrase {
+        println!("Passphrase: {}", passphrase);
+    }
+}
+
+// The code page lives at the root of every server
+fn print_code(code: Option<&TransferCode>, service: &str, base_url: &str) {
+    let Some(code) = code else {
+        return;
+    };
+    let command = match service {
+        "send" => format!("archdrop get {}", code),
+        _ => format!("archdrop put {} <files>", code),
+    };
+    println!("Code: {}", code);
+    println!("Enter it at {}/ or run `{}`", base_url, command);
+}
+
+// What the TUI shows besides progress
+struct SessionDisplay {
+    name: String,
+    url: String,
+    fingerprint: Option<String>,
+    passphrase: Option<String>,
+    code: Option<String>,
+}
+
+// Started tunnel handed to the session for supervision
+struct ActiveTunnel {
+    provider: Box<dyn TunnelProvider>,
+    local_port: u16,
+    share_path: String,
+}
+
 async fn run_session(
     server_handle: axum_server::Handle,
     state: AppState,
-    mut tunnel: Option<CloudflareTunnel>,
-    display_name: String,
-    progress_receiver: tokio::sync::watch::Receiver<f64>,
-    url: String,
+    tunnel: Option<ActiveTunnel>,
+    display: SessionDisplay,
+    progress_receiver: watch::Receiver<f64>,
     service: String,
 ) -> Result<()> {
     // CancellationTokens
     let root_token = CancellationToken::new();
     let tui_token = root_token.child_token();
     let shutdown_token = root_token.child_token();
+    let tunnel_token = root_token.child_token();
 
     // TUI msgs
-    let (status_sender, status_receiver) = tokio::sync::watch::channel(None);
+    let (status_sender, status_receiver) = watch::channel(SessionStatus::default());
 
     // Spawn TUI
-    let qr_code = qr::generate_qr(&url)?;
-    let tui_handle = helpers::spawn_tui(
+    let qr_code = qr::generate_qr(&display.url)?;
+    let ui = TransferUI::new(
         progress_receiver,
-        display_name,
+        display.name,
         qr_code,
-        service == "upload",
+        service == "receive",
         status_receiver,
-        tui_token.clone(),
-    );
+    )
+    .with_fingerprint(display.fingerprint)
+    .with_passphrase(display.passphrase)
+    .with_code(display.code)
+    .with_approvals(state.session.approvals().cloned());
+    let tui_handle = helpers::spawn_tui(ui, tui_token.clone());
 
     // Spawn Ctrl+C handler with two-stage loop
     let signal_token = root_token.clone();
@@ -201,10 +374,12 @@ async fn run_session(
         let active_count = signal_state.transfer_count();
 
         if active_count > 0 {
-            let _ = signal_status_sender.send(Some(format!(
-                "Shutting down... {} transfer(s) in progress - Press Ctrl+C again to force quit",
-                active_count
-            )));
+            signal_status_sender.send_modify(|status| {
+                status.message = Some(format!(
+                    "Shutting down... {} transfer(s) in progress - Press Ctrl+C again to force quit",
+                    active_count
+                ))
+            });
         }
         // Cancel all tasks gracefully
         signal_token.cancel();
@@ -217,16 +392,64 @@ async fn run_session(
         }
     });
 
+    // Restart the tunnel if it dies, the TUI follows URL changes
+    let mut tunnel_task = tunnel.map(|tunnel| {
+        let tunnel_status_sender = status_sender.clone();
+        tokio::spawn(supervisor::supervise(
+            tunnel.provider,
+            tunnel.local_port,
+            tunnel_token.clone(),
+            move |event| on_tunnel_event(&tunnel_status_sender, &tunnel.share_path, event),
+        ))
+    });
+    let mut tunnel_failure = None;
+    let mut ui_failure = None;
+
+    let recipients_task = spawn_recipients_status(&state.session, status_sender.clone());
+    let blocked_task = spawn_blocked_status(&state, status_sender.clone());
+    let mut expired = false;
+
     //  wait for complete or cancel
-    tokio::select! {
-        result = tui_handle => {
-            tracing::info!("Transfer completed successfully");
-            result.context("TUI task failed")?;
-            ShutdownResult::Completed
-        }
+    let reason = tokio::select! {
+        result = tui_handle => match result {
+            Ok(Ok(())) => {
+                tracing::info!("Transfer completed successfully");
+                "completed"
+            }
+            Ok(Err(e)) => {
+                ui_failure = Some(anyhow::Error::new(e).context("Terminal UI failed"));
+                "ui failed"
+            }
+            Err(e) => {
+                ui_failure = Some(anyhow::Error::new(e).context("TUI task failed"));
+                "ui failed"
+            }
+        },
         _ = shutdown_token.cancelled() => {
             tracing::info!("Shutdown requested via Ctrl+C");
-            ShutdownResult::Forced
+            "interrupted"
+        }
+        _ = watch_expiry(&state.session, &status_sender) => {
+            tracing::info!("Session expired");
+            state.session.expire();
+            expired = true;
+            status_sender.send_modify(|status| status.message = Some("Session expired".to_string()));
+            "expired"
+        }
+        // Only finishes early when the tunnel is gone for good
+        Some(result) = async {
+            match tunnel_task.as_mut() {
+                Some(task) => Some(task.await),
+                None => None,
+            }
+        } => {
+            tunnel_task = None;
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => tunnel_failure = Some(e),
+                Err(e) => tunnel_failure = Some(anyhow::Error::new(e).context("Tunnel task failed")),
+            }
+            "tunnel lost"
         }
     };
 
@@ -235,22 +458,148 @@ async fn run_session(
     // Ensure all tokens are cancelled
     root_token.cancel();
 
-    // Shutdown tunnel if it exists
-    if let Some(ref mut t) = tunnel {
-        tracing::debug!("Shutting down cloudflared tunnel...");
-        if let Err(e) = t.shutdown().await {
-            tracing::warn!("Error during tunnel shutdown: {}", e);
+    // Supervisor shuts the tunnel down once cancelled
+    if let Some(task) = tunnel_task {
+        match task.await {
+            Ok(Err(e)) => tracing::warn!("Tunnel error during shutdown: {:#}", e),
+            Err(e) => tracing::warn!("Tunnel task failed: {}", e),
+            Ok(Ok(())) => {}
         }
     }
 
+    if let Some(task) = recipients_task {
+        task.abort();
+    }
+    blocked_task.abort();
+
     // Wait for signal handler to finish (should be quick)
     ctrl_c_task.abort(); // It's ok to abort this one - it's just listening
     let _ = ctrl_c_task.await;
 
     // Shutdown server and wait for transfers
-    shutdown(server_handle, state, shutdown_token, status_sender).await?;
+    shutdown(server_handle, state, shutdown_token, status_sender, reason).await?;
 
-    Ok(())
+    if expired {
+        println!("Session expired, the link no longer works");
+    }
+
+    if let Some(e) = ui_failure {
+        return Err(e);
+    }
+    match tunnel_failure {
+        Some(e) => Err(e.context("Tunnel lost, session ended")),
+        None => Ok(()),
+    }
+}
+
+fn on_tunnel_event(status: &watch::Sender<SessionStatus>, share_path: &str, event: TunnelEvent) {
+    match event {
+        TunnelEvent::Restarting { attempt, delay } => status.send_modify(|status| {
+            status.message = Some(format!(
+                "Tunnel down, restarting in {}s (attempt {})",
+                delay.as_secs(),
+                attempt
+            ));
+        }),
+        TunnelEvent::Restored(base_url) => {
+            // Old links are dead if the hostname changed
+            let url = format!("{}{}", base_url.trim_end_matches('/'), share_path);
+            let qr_code = match qr::generate_qr(&url) {
+                Ok(qr_code) => Some(qr_code),
+                Err(e) => {
+                    tracing::error!("Failed to generate QR code: {}", e);
+                    None
+                }
+            };
+            status.send_modify(|status| {
+                status.message = Some(format!("Tunnel restored at {}, scan again", base_url));
+                if qr_code.is_some() {
+                    status.qr_code = qr_code;
+                }
+            });
+        }
+    }
+}
+
+// Resolves once the session deadline passes, never if it has none
+// Keeps the TUI countdown in sync as activity pushes the idle deadline back
+async fn watch_expiry(session: &Session, status: &watch::Sender<SessionStatus>) {
+    loop {
+        let Some(deadline) = session.deadline() else {
+            return std::future::pending().await;
+        };
+        status.send_if_modified(|status| {
+            let changed = status.expires_at != Some(deadline);
+            status.expires_at = Some(deadline);
+            changed
+        });
+
+        let now = Instant::now();
+        if now >= deadline {
+            return;
+        }
+        tokio::time::sleep((deadline - now).min(Duration::from_secs(1))).await;
+    }
+}
+
+// Multi recipient sends list every client in the TUI, single ones need no list
+fn spawn_recipients_status(
+    session: &Session,
+    status: watch::Sender<SessionStatus>,
+) -> Option<tokio::task::JoinHandle<()>> {
+    // A download limit is a recipient limit too, see Session::with_max_downloads
+    let limit = match session.recipients() {
+        Recipients::Limited(1) => return None,
+        Recipients::Limited(n) => Some(n),
+        Recipients::Unlimited => None,
+    };
+
+    let session = session.clone();
+    Some(tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(250));
+        loop {
+            interval.tick().await;
+
+            let total = session.total_chunks.load(Ordering::SeqCst).max(1);
+            let clients = session
+                .claims()
+                .into_iter()
+                .map(|(client_id, claim)| RecipientProgress {
+                    client_id,
+                    percent: (claim.chunks.min(total) as f64 / total as f64) * 100.0,
+                    done: claim.state == ClaimState::Completed,
+                })
+                .collect();
+
+            status.send_modify(|status| {
+                status.recipients = Some(RecipientsStatus { limit, clients })
+            });
+        }
+    }))
+}
+
+// Shows rate limited and banned requests once the first one comes in
+fn spawn_blocked_status(
+    state: &AppState,
+    status: watch::Sender<SessionStatus>,
+) -> tokio::task::JoinHandle<()> {
+    let limiter = state.limiter.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_millis(500));
+        loop {
+            interval.tick().await;
+
+            let blocked = limiter.blocked();
+            if blocked.requests == 0 {
+                continue;
+            }
+            status.send_if_modified(|status| {
+                let changed = status.blocked.as_ref() != Some(&blocked);
+                status.blocked = Some(blocked);
+                changed
+            });
+        }
+    })
 }
 
 //==========
@@ -262,11 +611,13 @@ enum ShutdownResult {
     Forced,
 }
 
+// reason ends up in the audit log, e.g. "completed" or "expired"
 async fn shutdown(
     server_handle: axum_server::Handle,
     state: AppState,
     cancel_token: CancellationToken,
-    status_sender: tokio::sync::watch::Sender<Option<String>>,
+    status_sender: watch::Sender<SessionStatus>,
+    reason: &'static str,
 ) -> Result<()> {
     // Stop accepting new connections
     server_handle.shutdown();
@@ -276,17 +627,21 @@ async fn shutdown(
     let result = wait_for_transfers(&state, cancel_token, status_sender.clone()).await;
 
     // Clear status message before final cleanup
-    let _ = status_sender.send(None);
+    status_sender.send_modify(|status| status.message = None);
 
+    let remaining = state.transfer_count();
     match result {
         ShutdownResult::Completed => {
             tracing::info!("All transfers completed successfully");
         }
         ShutdownResult::Forced => {
-            let remaining = state.transfer_count();
             tracing::warn!("Forced shutdown with {} pending transfers", remaining);
         }
     }
+    state.session.audit().record(AuditEvent::Shutdown {
+        reason,
+        pending_transfers: remaining,
+    });
 
     // Clean up sessions
     cleanup_sessions(&state).await;
@@ -298,7 +653,7 @@ async fn shutdown(
 async fn wait_for_transfers(
     state: &AppState,
     cancel_token: CancellationToken,
-    status_sender: tokio::sync::watch::Sender<Option<String>>,
+    status_sender: watch::Sender<SessionStatus>,
 ) -> ShutdownResult {
     let mut last_count = state.transfer_count();
 
@@ -323,10 +678,12 @@ async fn wait_for_transfers(
                 // Show progress if count changed
                 if current_count != last_count {
                     tracing::info!("{} transfer(s) remaining...", current_count);
-                    let _ = status_sender.send(Some(format!(
-                        "{} transfer(s) remaining - Press Ctrl+C to force quit",
-                        current_count
-                    )));
+                    status_sender.send_modify(|status| {
+                        status.message = Some(format!(
+                            "{} transfer(s) remaining - Press Ctrl+C to force quit",
+                            current_count
+                        ))
+                    });
                     last_count = current_count;
                 }
             }
@@ -1,10 +1,18 @@
+use crate::crypto::cipher::{Cipher, CipherSuite};
+use crate::crypto::claim::{self, CookieKey};
+use crate::crypto::code::TransferCode;
+use crate::crypto::kdf::{self, Direction};
+use crate::crypto::pake::{PakeKeys, Password, Spake2};
 use crate::crypto::types::EncryptionKey;
+use crate::server::approval::{Approvals, ClaimRequest};
+use crate::server::audit::{AuditEvent, AuditFile, AuditLog};
 use crate::transfer::manifest::{FileEntry, Manifest};
-use aes_gcm::{Aes256Gcm, KeyInit};
-use sha2::digest::generic_array::GenericArray;
+use anyhow::{bail, Result};
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
 #[derive(Clone)]
@@ -13,19 +21,73 @@ pub enum SessionMode {
     Receive { destination: PathBuf },
 }
 
-#[derive(Debug, Clone)]
-pub enum SessionState {
-    Unclaimed,
-    Active { client_id: String },
+/// How many clients may claim a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recipients {
+    Limited(usize),
+    Unlimited,
+}
+
+impl Default for Recipients {
+    fn default() -> Self {
+        Recipients::Limited(1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClaimState {
+    Active,
     Completed,
 }
 
+// Claim challenges are answered right away, stale ones are dropped
+const CHALLENGE_TTL: Duration = Duration::from_secs(60);
+const MAX_PENDING_CHALLENGES: usize = 256;
+
+// Exchanges are confirmed right away too, same limits as challenges
+const EXCHANGE_TTL: Duration = Duration::from_secs(60);
+const MAX_PENDING_EXCHANGES: usize = 256;
+
+/// Passphrase protected sessions, the key is only handed out after the exchange
+/// Wrong guesses are answered with 403, which bans the IP after a few, see `limits`
+struct PakeState {
+    password: Password,
+    /// Finished exchanges waiting for the client's confirmation, by server assigned id
+    pending: HashMap<String, (PakeKeys, Instant)>,
+}
+
+/// One client's claim on the session, unclaimed clients have none
+#[derive(Debug, Clone)]
+pub struct Claim {
+    pub state: ClaimState,
+    /// Chunks served to this client, retries count again
+    pub chunks: u64,
+    /// Distinct (file, chunk) pairs served, `--burn` waits for all of them
+    pub served: HashSet<(usize, usize)>,
+    pub claimed_at: Instant,
+}
+
 pub struct Session {
     token: String,
     session_key: EncryptionKey,
-    cipher: Arc<Aes256Gcm>,
+    suite: CipherSuite,
     mode: SessionMode,
-    state: Arc<RwLock<SessionState>>,
+    recipients: Recipients,
+    claims: Arc<RwLock<HashMap<String, Claim>>>,
+    started_at: Instant,
+    expires: Option<Duration>,
+    idle_timeout: Option<Duration>,
+    last_activity: Arc<Mutex<Instant>>,
+    expired: Arc<AtomicBool>,
+    pake: Option<Arc<Mutex<PakeState>>>,
+    approvals: Option<Approvals>,
+    /// Outstanding claim challenges and when they were handed out
+    challenges: Arc<Mutex<HashMap<[u8; claim::CHALLENGE_LEN], Instant>>>,
+    cookie_key: CookieKey,
+    /// Every claim is a download, clients past the limit get 410
+    max_downloads: Option<u64>,
+    burn: bool,
+    audit: AuditLog,
     pub total_chunks: AtomicU64,
     pub chunks_sent: Arc<AtomicU64>,
 }
@@ -35,9 +97,22 @@ impl Clone for Session {
         Self {
             token: self.token.clone(),
             session_key: self.session_key.clone(),
-            cipher: self.cipher.clone(),
+            suite: self.suite,
             mode: self.mode.clone(),
-            state: self.state.clone(),
+            recipients: self.recipients,
+            claims: self.claims.clone(),
+            started_at: self.started_at,
+            expires: self.expires,
+            idle_timeout: self.idle_timeout,
+            last_activity: self.last_activity.clone(),
+            expired: self.expired.clone(),
+            pake: self.pake.clone(),
+            approvals: self.approvals.clone(),
+            challenges: self.challenges.clone(),
+            cookie_key: self.cookie_key.clone(),
+            max_downloads: self.max_downloads,
+            burn: self.burn,
+            audit: self.audit.clone(),
             total_chunks: AtomicU64::new(self.total_chunks.load(Ordering::SeqCst)),
             chunks_sent: self.chunks_sent.clone(),
         }
@@ -49,6 +124,85 @@ impl Session {
         Self::new(SessionMode::Send { manifest }, session_key, total_chunks)
     }
 
+    /// Let more than one client claim the session, each downloads everything
+    pub fn with_recipients(mut self, recipients: Recipients) -> Self {
+        self.recipients = recipients;
+        self
+    }
+
+    /// End the session after a fixed time and/or after a quiet period
+    pub fn with_expiry(
+        mut self,
+        expires: Option<Duration>,
+        idle_timeout: Option<Duration>,
+    ) -> Self {
+        self.expires = expires;
+        self.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// Serve at most this many clients, counted when they claim rather than
+    /// when they report completion, so an abandoned download still uses one up
+    pub fn with_max_downloads(mut self, max_downloads: Option<u64>) -> Self {
+        if let Some(max) = max_downloads {
+            self.recipients = Recipients::Limited(max as usize);
+        }
+        self.max_downloads = max_downloads;
+        self
+    }
+
+    /// Cipher suite for uploads, a send session uses its manifest's
+    pub fn with_cipher_suite(mut self, suite: CipherSuite) -> Self {
+        self.suite = suite;
+        self
+    }
+
+    /// Delete the source files once the session is finished
+    pub fn with_burn(mut self, burn: bool) -> Self {
+        self.burn = burn;
+        self
+    }
+
+    /// Record the session to this log, starting with its creation
+    /// Set last so the entry shows the passphrase and approval settings
+    pub fn with_audit_log(mut self, audit: AuditLog) -> Self {
+        self.audit = audit;
+        self.audit.record(AuditEvent::Created {
+            mode: match self.mode {
+                SessionMode::Send { .. } => "send",
+                SessionMode::Receive { .. } => "receive",
+            },
+            files: self
+                .manifest()
+                .map(|manifest| {
+                    manifest
+                        .files
+                        .iter()
+                        .map(|f| AuditFile {
+                            path: f.relative_path.clone(),
+                            size: f.size,
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+            destination: self.destination().cloned(),
+            cipher: self.suite,
+            passphrase: self.requires_passphrase(),
+            approve: self.approvals.is_some(),
+            max_downloads: self.max_downloads,
+            burn: self.burn,
+        });
+        self
+    }
+
+    pub fn audit(&self) -> &AuditLog {
+        &self.audit
+    }
+
+    pub fn burns_files(&self) -> bool {
+        self.burn
+    }
+
     pub fn new_receive(
         destination: PathBuf,
         session_key: EncryptionKey,
@@ -63,30 +217,72 @@ impl Session {
 
     pub fn new(mode: SessionMode, session_key: EncryptionKey, total_chunks: u64) -> Self {
         let token = Uuid::new_v4().to_string();
-
-        let cipher = Arc::new(Aes256Gcm::new(GenericArray::from_slice(
-            session_key.as_bytes(),
-        )));
+        // Manifest nonces are sized for its suite, receive sessions pick one
+        let suite = match &mode {
+            SessionMode::Send { manifest } => manifest.suite,
+            SessionMode::Receive { .. } => CipherSuite::default(),
+        };
 
         Self {
             token,
             session_key,
-            cipher,
+            suite,
             mode,
-            state: Arc::new(RwLock::new(SessionState::Unclaimed)),
+            recipients: Recipients::default(),
+            claims: Arc::new(RwLock::new(HashMap::new())),
+            started_at: Instant::now(),
+            expires: None,
+            idle_timeout: None,
+            last_activity: Arc::new(Mutex::new(Instant::now())),
+            expired: Arc::new(AtomicBool::new(false)),
+            pake: None,
+            approvals: None,
+            challenges: Arc::new(Mutex::new(HashMap::new())),
+            cookie_key: CookieKey::new(),
+            max_downloads: None,
+            burn: false,
+            audit: AuditLog::default(),
             total_chunks: AtomicU64::new(total_chunks),
             chunks_sent: Arc::new(AtomicU64::new(0)),
         }
     }
 
-    // The method to safely increment the count and return the necessary data
-    pub fn increment_sent_chunk(&self) -> (u64, u64) {
-        // Increment the atomic counter
-        let new_count = self.chunks_sent.fetch_add(1, Ordering::SeqCst) + 1;
+    // Count a chunk against the client's claim, returns (sent, total) for that client
+    pub fn increment_sent_chunk(
+        &self,
+        client_id: &str,
+        file_index: usize,
+        chunk_index: usize,
+    ) -> (u64, u64) {
         let total = self.total_chunks.load(Ordering::SeqCst);
 
-        // Return the new count and the total session size for calculation
-        (new_count, total)
+        let mut claims = self.claims.write().unwrap();
+        let sent = match claims.get_mut(client_id) {
+            Some(claim) => {
+                claim.chunks += 1;
+                claim.served.insert((file_index, chunk_index));
+                claim.chunks
+            }
+            None => 0,
+        };
+        (sent, total)
+    }
+
+    /// Overall send progress in percent across every recipient
+    pub fn send_progress(&self) -> f64 {
+        let total = self.total_chunks.load(Ordering::SeqCst);
+        let claims = self.claims.read().unwrap();
+        if total == 0 || claims.is_empty() {
+            return 0.0;
+        }
+
+        let sent: u64 = claims.values().map(|c| c.chunks.min(total)).sum();
+        // Unlimited sessions have no target, show the average instead
+        let expected = match self.recipients {
+            Recipients::Limited(n) => n as u64,
+            Recipients::Unlimited => claims.len() as u64,
+        };
+        (sent as f64 / (total * expected) as f64) * 100.0
     }
 
     // Set total chunks (for receive mode when manifest arrives)
@@ -109,60 +305,331 @@ impl Session {
         &self.session_key
     }
 
-    pub fn cipher(&self) -> &Arc<Aes256Gcm> {
-        &self.cipher
+    fn direction(&self) -> Direction {
+        match self.mode {
+            SessionMode::Send { .. } => Direction::Send,
+            SessionMode::Receive { .. } => Direction::Receive,
+        }
+    }
+
+    pub fn cipher_suite(&self) -> CipherSuite {
+        self.suite
+    }
+
+    /// Cipher for one file's chunks, see `crypto::kdf` for the file id
+    pub fn file_cipher(&self, file_id: &str) -> Cipher {
+        kdf::file_cipher(&self.session_key, self.suite, self.direction(), file_id)
+    }
+
+    /// Cipher for the manifest, names and sizes stay hidden from the tunnel too
+    pub fn metadata_cipher(&self) -> Cipher {
+        kdf::metadata_cipher(&self.session_key, self.suite, self.direction())
     }
 
     pub fn session_key_b64(&self) -> String {
         self.session_key.to_base64()
     }
 
-    // session lock logic
+    pub fn recipients(&self) -> Recipients {
+        self.recipients
+    }
 
-    // Claims inactive session, creates client_id
-    pub fn claim(&self, token: &str, client_id: &str) -> bool {
-        if token != self.token {
+    /// Clients must prove the passphrase before claiming,
+    /// the session key is only handed out wrapped by the exchange
+    pub fn with_passphrase(mut self, passphrase: &str) -> Self {
+        self.pake = Some(Arc::new(Mutex::new(PakeState {
+            password: Password::new(&self.token, passphrase),
+            pending: HashMap::new(),
+        })));
+        self
+    }
+
+    /// New clients wait for an accept in the TUI before they can claim
+    pub fn with_approval(mut self) -> Self {
+        self.approvals = Some(Approvals::default());
+        self
+    }
+
+    pub fn approvals(&self) -> Option<&Approvals> {
+        self.approvals.as_ref()
+    }
+
+    /// Use the code's nameplate as the token and its words as the passphrase
+    pub fn with_code(mut self, code: &TransferCode) -> Self {
+        self.token = code.nameplate().to_string();
+        self.with_passphrase(&code.passphrase())
+    }
+
+    pub fn requires_passphrase(&self) -> bool {
+        self.pake.is_some()
+    }
+
+    // passphrase exchange
+
+    /// Answer a client's PAKE message, returns the exchange id and our message
+    pub fn pake_respond(&self, token: &str, message: &[u8]) -> Result<(String, Vec<u8>)> {
+        let Some(pake) = &self.pake else {
+            bail!("Session has no passphrase");
+        };
+        if token != self.token || self.is_expired() {
+            bail!("Invalid or expired session");
+        }
+
+        let password = {
+            let mut pake = pake.lock().unwrap();
+            pake.pending
+                .retain(|_, (_, started)| started.elapsed() < EXCHANGE_TTL);
+            if pake.pending.len() >= MAX_PENDING_EXCHANGES {
+                bail!("Too many passphrase exchanges in progress");
+            }
+            pake.password.clone()
+        };
+
+        // Curve math, kept outside the lock
+        let exchange_id = Uuid::new_v4().to_string();
+        let exchange = Spake2::start_server(password);
+        let response = exchange.message().to_vec();
+        let keys = exchange.finish(message, &exchange_id)?;
+
+        let mut pake = pake.lock().unwrap();
+        if pake.pending.len() >= MAX_PENDING_EXCHANGES {
+            bail!("Too many passphrase exchanges in progress");
+        }
+        pake.pending
+            .insert(exchange_id.clone(), (keys, Instant::now()));
+
+        Ok((exchange_id, response))
+    }
+
+    /// Check the client's confirmation, returns the wrapped session key
+    pub fn pake_confirm(
+        &self,
+        token: &str,
+        exchange_id: &str,
+        confirmation: &[u8],
+    ) -> Result<Vec<u8>> {
+        let Some(pake) = &self.pake else {
+            bail!("Session has no passphrase");
+        };
+        if token != self.token || self.is_expired() {
+            bail!("Invalid or expired session");
+        }
+
+        // One confirmation per exchange, right or wrong
+        let Some((keys, started)) = pake.lock().unwrap().pending.remove(exchange_id) else {
+            bail!("No passphrase exchange in progress");
+        };
+        if started.elapsed() >= EXCHANGE_TTL {
+            bail!("Passphrase exchange expired");
+        }
+        if !keys.verify_client_confirmation(confirmation) {
+            bail!("Wrong passphrase");
+        }
+
+        keys.wrap(&self.session_key)
+    }
+
+    // proof of key
+
+    /// Random challenge the client answers with `claim::claim_proof`
+    pub fn new_challenge(&self, token: &str) -> Option<[u8; claim::CHALLENGE_LEN]> {
+        if token != self.token || self.is_expired() {
+            return None;
+        }
+
+        let mut challenges = self.challenges.lock().unwrap();
+        challenges.retain(|_, issued| issued.elapsed() < CHALLENGE_TTL);
+        if challenges.len() >= MAX_PENDING_CHALLENGES {
+            return None;
+        }
+        let challenge = claim::new_challenge();
+        challenges.insert(challenge, Instant::now());
+        Some(challenge)
+    }
+
+    /// Check the proof for a challenge we handed out, each one works once
+    pub fn verify_claim_proof(&self, token: &str, challenge: &[u8], proof: &[u8]) -> bool {
+        if token != self.token || self.is_expired() {
             return false;
         }
+        let Ok(challenge) = <[u8; claim::CHALLENGE_LEN]>::try_from(challenge) else {
+            return false;
+        };
 
-        // Convert for storage in SessionState::Active
-        let client_id_owned = client_id.to_string();
+        let issued = self.challenges.lock().unwrap().remove(&challenge);
+        issued.is_some_and(|issued| issued.elapsed() < CHALLENGE_TTL)
+            && claim::verify_claim_proof(&self.session_key, token, &challenge, proof)
+    }
 
-        // Try to claim
-        let mut state = self.state.write().unwrap();
-        match *state {
-            SessionState::Unclaimed => {
-                *state = SessionState::Active {
-                    client_id: client_id_owned,
-                };
-                true
+    /// Signed cookie value naming a client that proved the key
+    pub fn claim_cookie(&self, client_id: &str) -> String {
+        self.cookie_key.sign(client_id)
+    }
+
+    /// Client id from a claim cookie, None unless we signed it
+    pub fn client_from_cookie(&self, cookie: &str) -> Option<String> {
+        self.cookie_key.verify(cookie)
+    }
+
+    // expiry
+
+    /// Record client activity, pushes the idle deadline back
+    pub fn touch(&self) {
+        *self.last_activity.lock().unwrap() = Instant::now();
+    }
+
+    /// Whichever of the absolute and idle deadlines comes first
+    /// A deadline too far out for `Instant` never comes
+    pub fn deadline(&self) -> Option<Instant> {
+        let absolute = self
+            .expires
+            .and_then(|ttl| self.started_at.checked_add(ttl));
+        let idle = self
+            .idle_timeout
+            .and_then(|idle| self.last_activity.lock().unwrap().checked_add(idle));
+
+        match (absolute, idle) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Refuse every request from now on
+    pub fn expire(&self) {
+        if !self.expired.swap(true, Ordering::SeqCst) {
+            self.audit.record(AuditEvent::Expired);
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expired.load(Ordering::SeqCst)
+    }
+
+    // session lock logic
+
+    // Claims a free recipient slot for the client, the attempt is audited either way
+    pub fn claim(&self, token: &str, client: &ClaimRequest) -> bool {
+        let result = self.try_claim(token, &client.client_id);
+        let client = client.clone();
+        self.audit.record(match result {
+            Ok(()) => AuditEvent::ClaimAccepted { client },
+            Err(reason) => AuditEvent::ClaimRejected { client, reason },
+        });
+        result.is_ok()
+    }
+
+    fn try_claim(&self, token: &str, client_id: &str) -> Result<(), &'static str> {
+        if token != self.token || self.is_expired() {
+            return Err("Invalid or expired session");
+        }
+
+        let mut claims = self.claims.write().unwrap();
+        match claims.get(client_id) {
+            // Re-claim by the owner is fine, e.g. a reloaded page still has its cookie
+            Some(claim) if claim.state == ClaimState::Active => Ok(()),
+            Some(_) => Err("Client already completed"),
+            None => {
+                if self.is_full(&claims) {
+                    return Err(match self.max_downloads {
+                        Some(_) => "No downloads left",
+                        None => "Session already claimed by another client",
+                    });
+                }
+                claims.insert(
+                    client_id.to_string(),
+                    Claim {
+                        state: ClaimState::Active,
+                        chunks: 0,
+                        served: HashSet::new(),
+                        claimed_at: Instant::now(),
+                    },
+                );
+                Ok(())
             }
-            _ => false, // Already claimed or in another state, return false
         }
     }
 
+    fn is_full(&self, claims: &HashMap<String, Claim>) -> bool {
+        match self.recipients {
+            Recipients::Limited(n) => claims.len() >= n,
+            Recipients::Unlimited => false,
+        }
+    }
+
+    /// Every download under `--max-downloads` is handed out
+    pub fn downloads_exhausted(&self) -> bool {
+        self.max_downloads.is_some() && self.is_full(&self.claims.read().unwrap())
+    }
+
+    /// Was this client served every chunk of every file, whatever it reports
+    pub fn was_served_everything(&self, client_id: &str) -> bool {
+        let total = self.total_chunks.load(Ordering::SeqCst);
+        let claims = self.claims.read().unwrap();
+        claims
+            .get(client_id)
+            .is_some_and(|claim| claim.served.len() as u64 >= total)
+    }
+
+    /// Would `claim` add a new claim for this client
+    pub fn is_new_claim(&self, token: &str, client_id: &str) -> bool {
+        if token != self.token || self.is_expired() {
+            return false;
+        }
+        let claims = self.claims.read().unwrap();
+        !claims.contains_key(client_id) && !self.is_full(&claims)
+    }
+
     pub fn is_active(&self, token: &str, client_id: &str) -> bool {
+        if token != self.token || self.is_expired() {
+            return false;
+        }
+
+        let claims = self.claims.read().unwrap();
+        claims
+            .get(client_id)
+            .is_some_and(|claim| claim.state == ClaimState::Active)
+    }
+
+    pub fn complete(&self, token: &str, client_id: &str) -> bool {
         if token != self.token {
             return false;
         }
 
-        let state = self.state.read().unwrap();
-        match &*state {
-            SessionState::Active {
-                client_id: stored_id,
-            } => stored_id == client_id,
+        let mut claims = self.claims.write().unwrap();
+        match claims.get_mut(client_id) {
+            Some(claim) if claim.state == ClaimState::Active => {
+                claim.state = ClaimState::Completed;
+                self.audit.record(AuditEvent::Completed {
+                    client_id: client_id.to_string(),
+                });
+                true
+            }
             _ => false,
         }
     }
 
-    pub fn complete(&self, token: &str, client_id: &str) -> bool {
-        if !self.is_active(token, client_id) {
+    /// Every recipient slot is used up and done, `--max-downloads` included
+    pub fn is_finished(&self) -> bool {
+        let Recipients::Limited(n) = self.recipients else {
             return false;
-        }
+        };
+        let claims = self.claims.read().unwrap();
+        claims
+            .values()
+            .filter(|claim| claim.state == ClaimState::Completed)
+            .count()
+            >= n
+    }
 
-        let mut state = self.state.write().unwrap();
-        *state = SessionState::Completed;
-        true
+    /// Snapshot of every claim, oldest first
+    pub fn claims(&self) -> Vec<(String, Claim)> {
+        let claims = self.claims.read().unwrap();
+        let mut claims: Vec<_> = claims
+            .iter()
+            .map(|(id, claim)| (id.clone(), claim.clone()))
+            .collect();
+        claims.sort_by_key(|(_, claim)| claim.claimed_at);
+        claims
     }
 
     // Mode based Helpers
@@ -184,3 +651,101 @@ impl Session {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> Session {
+        Session::new_receive(PathBuf::from("."), EncryptionKey::new(), 0)
+            .with_passphrase("guitar-orbit")
+    }
+
+    // Client side of the exchange against `session`, returns the exchange id and keys
+    fn start(session: &Session, passphrase: &str) -> (String, PakeKeys) {
+        let client = Spake2::start_client(Password::new(session.token(), passphrase));
+        let (exchange_id, response) = session
+            .pake_respond(session.token(), client.message())
+            .unwrap();
+        let keys = client.finish(&response, &exchange_id).unwrap();
+        (exchange_id, keys)
+    }
+
+    fn client(client_id: &str) -> ClaimRequest {
+        ClaimRequest {
+            client_id: client_id.to_string(),
+            ip: None,
+            user_agent: None,
+        }
+    }
+
+    #[test]
+    fn max_downloads_counts_claims() {
+        let session = Session::new_receive(PathBuf::from("."), EncryptionKey::new(), 2)
+            .with_max_downloads(Some(1));
+        assert!(session.claim(session.token(), &client("first")));
+        assert!(session.downloads_exhausted());
+        assert!(!session.claim(session.token(), &client("second")));
+
+        // Burning waits for every chunk, not the client's word
+        session.increment_sent_chunk("first", 0, 0);
+        session.increment_sent_chunk("first", 0, 0);
+        assert!(!session.was_served_everything("first"));
+        session.increment_sent_chunk("first", 0, 1);
+        assert!(session.was_served_everything("first"));
+    }
+
+    #[test]
+    fn passphrase_exchange_hands_out_the_key_once() {
+        let session = session();
+        let (exchange_id, keys) = start(&session, "guitar-orbit");
+
+        let wrapped = session
+            .pake_confirm(session.token(), &exchange_id, &keys.client_confirmation())
+            .unwrap();
+        assert_eq!(
+            keys.unwrap(&wrapped).unwrap().as_bytes(),
+            session.session_key.as_bytes()
+        );
+
+        // The exchange is used up, replaying the confirmation fails
+        assert!(session
+            .pake_confirm(session.token(), &exchange_id, &keys.client_confirmation())
+            .is_err());
+    }
+
+    #[test]
+    fn wrong_passphrase_uses_up_the_exchange() {
+        let session = session();
+        let (exchange_id, keys) = start(&session, "guitar-orbot");
+
+        assert!(session
+            .pake_confirm(session.token(), &exchange_id, &keys.client_confirmation())
+            .is_err());
+        assert!(session
+            .pake
+            .as_ref()
+            .unwrap()
+            .lock()
+            .unwrap()
+            .pending
+            .is_empty());
+    }
+
+    #[test]
+    fn exchange_ids_come_from_the_server() {
+        let session = session();
+        let (exchange_id, keys) = start(&session, "guitar-orbit");
+
+        assert!(session
+            .pake_confirm(
+                session.token(),
+                "client-chosen",
+                &keys.client_confirmation()
+            )
+            .is_err());
+        assert!(session
+            .pake_confirm("other-token", &exchange_id, &keys.client_confirmation())
+            .is_err());
+    }
+}
@@ -1,9 +1,22 @@
-use std::{fs::File, sync::Arc};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
 
+use axum::http::HeaderMap;
 use dashmap::DashMap;
 use tokio::sync::watch;
 
-use crate::{server::session::Session, transfer::storage::ChunkStorage};
+use crate::config::LimitsSection;
+use crate::{
+    server::{auth, limits::RateLimiter, session::Session},
+    transfer::storage::ChunkStorage,
+};
+
+/// A file from the receive manifest, known by its server assigned id
+pub struct AnnouncedFile {
+    pub relative_path: String,
+    pub size: u64,
+    pub total_chunks: usize,
+}
 
 pub struct FileReceiveState {
     pub storage: ChunkStorage,
@@ -24,6 +37,11 @@ pub struct AppState {
     pub session: Session,
     pub progress_sender: watch::Sender<f64>,
     pub transfers: TransferStorage,
+    /// Files from the receive manifest by id, empty when sending
+    pub announced: Arc<DashMap<String, AnnouncedFile>>,
+    pub limiter: RateLimiter,
+    /// Tunnel header trusted for the client's address, see `auth::client_ip`
+    pub client_ip_header: Option<&'static str>,
 }
 impl AppState {
     pub fn new_send(session: Session, progress_sender: watch::Sender<f64>) -> Self {
@@ -31,6 +49,9 @@ impl AppState {
             session,
             progress_sender,
             transfers: TransferStorage::Send(Arc::new(DashMap::new())),
+            announced: Arc::new(DashMap::new()),
+            limiter: RateLimiter::default(),
+            client_ip_header: None,
         }
     }
 
@@ -39,9 +60,28 @@ impl AppState {
             session,
             progress_sender,
             transfers: TransferStorage::Receive(Arc::new(DashMap::new())),
+            announced: Arc::new(DashMap::new()),
+            limiter: RateLimiter::default(),
+            client_ip_header: None,
         }
     }
 
+    /// Rate limit and lockout for the token routes
+    pub fn with_limits(mut self, limits: LimitsSection) -> Self {
+        self.limiter = RateLimiter::new(limits);
+        self
+    }
+
+    pub fn with_client_ip_header(mut self, header: Option<&'static str>) -> Self {
+        self.client_ip_header = header;
+        self
+    }
+
+    /// Address rate limits and audit records are keyed on
+    pub fn client_ip(&self, headers: &HeaderMap, peer: SocketAddr) -> IpAddr {
+        auth::client_ip(headers, peer, self.client_ip_header)
+    }
+
     //-- Helper Functions for safe access
 
     pub fn file_handles(&self) -> Option<&Arc<DashMap<usize, Arc<std::fs::File>>>> {
@@ -41,6 +41,15 @@ pub async fn serve_download_js() -> Response<Body> {
     serve_js(include_str!("../../templates/download.js"))
 }
 
+//-- CODE PAGE
+pub async fn serve_code_page() -> Result<Html<&'static str>, StatusCode> {
+    serve_html(include_str!("../../templates/code.html"))
+}
+
+pub async fn serve_code_js() -> Response<Body> {
+    serve_js(include_str!("../../templates/code.js"))
+}
+
 //-- SHARED JS AND CSS
 pub async fn serve_shared_js() -> Response<Body> {
     const JS: &str = include_str!("../../templates/shared.js");
@@ -0,0 +1,86 @@
+//! Claiming a session, shared by send and receive
+//!
+//! 1. `POST /{service}/{token}/challenge` hands out a one time challenge
+//! 2. `POST /{service}/{token}/claim` with `crypto::claim::claim_proof` over it,
+//!    answered with the signed `archdrop_claim` cookie
+//!
+//! Every other session route reads the client id from that cookie, see
+//! `auth::ClaimedClient`. A passphrase session's key only comes out of the
+//! PAKE exchange, so proving the key also proves the passphrase.
+
+use crate::errors::{AppError, Forbidden};
+use crate::server::auth;
+use crate::server::state::AppState;
+use anyhow::Context;
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+    Json,
+};
+use base64::{engine::general_purpose, Engine};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use uuid::Uuid;
+
+#[derive(serde::Deserialize)]
+pub struct ClaimProof {
+    challenge: String,
+    proof: String,
+}
+
+pub async fn challenge(
+    Path(token): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<Value>, AppError> {
+    let challenge = state
+        .session
+        .new_challenge(&token)
+        .ok_or(Forbidden("Invalid or expired session"))?;
+
+    Ok(Json(json!({
+        "challenge": general_purpose::URL_SAFE_NO_PAD.encode(challenge)
+    })))
+}
+
+pub async fn claim(
+    Path(token): Path<String>,
+    State(state): State<AppState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(request): Json<ClaimProof>,
+) -> Result<Response, AppError> {
+    let b64 = general_purpose::URL_SAFE_NO_PAD;
+    let challenge = b64
+        .decode(&request.challenge)
+        .context("Invalid challenge encoding")?;
+    let proof = b64
+        .decode(&request.proof)
+        .context("Invalid proof encoding")?;
+
+    // A reloaded page keeps its claim, everyone else gets a fresh id
+    let client_id = auth::cookie_client(&headers, &state.session)
+        .filter(|client_id| state.session.is_active(&token, client_id))
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let client = auth::claim_request(&client_id, &headers, state.client_ip(&headers, peer));
+
+    if !state.session.verify_claim_proof(&token, &challenge, &proof) {
+        auth::reject_claim(&state.session, &client, "Invalid claim proof");
+        return Err(Forbidden("Invalid claim proof").into());
+    }
+
+    auth::await_approval(&state.session, &token, &client).await?;
+    auth::claim_or_validate_session(&state.session, &token, &client)?;
+
+    let service = match state.file_handles() {
+        Some(_) => "send",
+        None => "receive",
+    };
+    let cookie = auth::claim_cookie(&state.session, service, &client_id);
+
+    Ok((
+        [(header::SET_COOKIE, cookie)],
+        Json(json!({ "success": true })),
+    )
+        .into_response())
+}
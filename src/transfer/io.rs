@@ -1,5 +1,8 @@
 use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
 use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 // Implement required traits based on OS
@@ -30,3 +33,44 @@ pub fn read_chunk_at_position(file_handle: &Arc<File>, start: u64, len: usize) -
 
     Ok(buffer)
 }
+
+/// SHA-256 of a whole file, hex encoded
+pub async fn hash_file(path: &Path) -> Result<String> {
+    // Use spawn_blocking for disk I/O
+    let path = path.to_owned();
+
+    tokio::task::spawn_blocking(move || {
+        let mut file = File::open(&path).context(format!(
+            "Failed to open file for hashing: {}",
+            path.display()
+        ))?;
+
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0u8; 65536]; // 64 KB chunks
+
+        loop {
+            let n = file
+                .read(&mut buffer)
+                .context("Failed to read file for hashing")?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buffer[..n]);
+        }
+
+        Ok::<String, anyhow::Error>(hex::encode(hasher.finalize()))
+    })
+    .await
+    .context("Hash computation task panicked")?
+}
+
+/// Delete sent files (`--burn`), missing files are already gone
+pub async fn remove_files(paths: Vec<PathBuf>) {
+    for path in paths {
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => tracing::info!("Burned {}", path.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!("Failed to burn {}: {}", path.display(), e),
+        }
+    }
+}
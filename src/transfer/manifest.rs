@@ -2,7 +2,10 @@ use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 
-use crate::{crypto::types::Nonce, transfer::security};
+use crate::{
+    crypto::{types::Nonce, CipherSuite},
+    transfer::security,
+};
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct FileEntry {
@@ -18,10 +21,17 @@ pub struct FileEntry {
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Manifest {
     pub files: Vec<FileEntry>,
+    /// Suite the file nonces are sized for, checked against the link
+    #[serde(default)]
+    pub suite: CipherSuite,
 }
 
 impl Manifest {
-    pub async fn new(file_paths: Vec<PathBuf>, base_path: Option<&Path>) -> Result<Self> {
+    pub async fn new(
+        file_paths: Vec<PathBuf>,
+        base_path: Option<&Path>,
+        suite: CipherSuite,
+    ) -> Result<Self> {
         let mut files = Vec::new();
 
         // determine common base, no base, use parent
@@ -48,7 +58,7 @@ impl Manifest {
             security::validate_filename(&name).context("Invalid fine name")?;
 
             // Unique nonce for each file
-            let nonce = Nonce::new();
+            let nonce = Nonce::new(suite);
 
             files.push(FileEntry {
                 index,
@@ -60,14 +70,14 @@ impl Manifest {
             });
         }
 
-        Ok(Manifest { files })
+        Ok(Manifest { files, suite })
     }
 
     /// Calculate total chunks needed for all files in manifest
     pub fn total_chunks(&self) -> u64 {
         self.files
             .iter()
-            .map(|f| (f.size + crate::config::CHUNK_SIZE - 1) / crate::config::CHUNK_SIZE)
+            .map(|f| f.size.div_ceil(crate::config::CHUNK_SIZE))
             .sum()
     }
 }
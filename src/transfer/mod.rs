@@ -1,5 +1,7 @@
+pub mod claim_handlers;
 pub mod io;
 pub mod manifest;
+pub mod pake_handlers;
 pub mod receive_handlers;
 pub mod security;
 pub mod send_handlers;
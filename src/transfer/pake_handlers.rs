@@ -0,0 +1,101 @@
+//! Passphrase exchange shared by send and receive sessions
+//!
+//! 1. `POST /{service}/{token}/pake` with the client's SPAKE2 message,
+//!    answered with ours and an exchange id the server picks
+//! 2. `POST /{service}/{token}/pake/confirm` with the exchange id and proof
+//!    of the shared key, answered with the session key wrapped under it
+//!
+//! A wrong passphrase is a 403 like a bad token, the rate limiter bans the
+//! IP after a few.
+//!
+//! The client then claims with the key like any other, see `claim_handlers`.
+//!
+//! Code sessions add `GET /code/{nameplate}`, the code page uses it to find
+//! the session before running the same exchange.
+
+use crate::errors::{AppError, Forbidden};
+use crate::server::state::AppState;
+use anyhow::Context;
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use base64::{engine::general_purpose, Engine};
+use serde_json::{json, Value};
+
+#[derive(serde::Deserialize)]
+pub struct PakeRequest {
+    message: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ConfirmRequest {
+    #[serde(rename = "exchangeId")]
+    exchange_id: String,
+    confirmation: String,
+}
+
+pub async fn pake_start(
+    Path(token): Path<String>,
+    State(state): State<AppState>,
+    Json(request): Json<PakeRequest>,
+) -> Result<Json<Value>, AppError> {
+    let message = general_purpose::URL_SAFE_NO_PAD
+        .decode(&request.message)
+        .context("Invalid PAKE message encoding")?;
+
+    // A few modpows on 2048 bit numbers, off the async workers
+    let session = state.session.clone();
+    let (exchange_id, response) =
+        tokio::task::spawn_blocking(move || session.pake_respond(&token, &message))
+            .await
+            .context("PAKE task panicked")?
+            .context(Forbidden("Passphrase exchange refused"))?;
+
+    Ok(Json(json!({
+        "exchangeId": exchange_id,
+        "message": general_purpose::URL_SAFE_NO_PAD.encode(response)
+    })))
+}
+
+pub async fn pake_confirm(
+    Path(token): Path<String>,
+    State(state): State<AppState>,
+    Json(request): Json<ConfirmRequest>,
+) -> Result<Json<Value>, AppError> {
+    let confirmation = general_purpose::URL_SAFE_NO_PAD
+        .decode(&request.confirmation)
+        .context("Invalid confirmation encoding")?;
+
+    let wrapped = state
+        .session
+        .pake_confirm(&token, &request.exchange_id, &confirmation)
+        .context(Forbidden("Passphrase rejected"))?;
+
+    Ok(Json(json!({
+        "key": general_purpose::URL_SAFE_NO_PAD.encode(wrapped)
+    })))
+}
+
+/// Path and cipher suite of the session behind a code's nameplate
+pub async fn code_lookup(
+    Path(nameplate): Path<String>,
+    State(state): State<AppState>,
+) -> Result<Json<Value>, AppError> {
+    let session = &state.session;
+    if nameplate != session.token() || !session.requires_passphrase() || session.is_expired() {
+        // Counts as a failure, codes are short enough to guess at
+        return Err(anyhow::anyhow!("No session for code {}", nameplate)
+            .context(Forbidden("Unknown code"))
+            .into());
+    }
+
+    let service = match state.file_handles() {
+        Some(_) => "send",
+        None => "receive",
+    };
+    Ok(Json(json!({
+        "path": format!("/{}/{}", service, nameplate),
+        "suite": session.cipher_suite(),
+    })))
+}
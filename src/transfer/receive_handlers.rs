@@ -1,16 +1,20 @@
-use crate::crypto::types::Nonce;
+use crate::crypto::{self, types::Nonce, CipherSuite};
 use crate::errors::AppError;
-use crate::server::auth::{self, ClientIdParam};
-use crate::server::state::{AppState, FileReceiveState};
+use crate::server::audit::AuditEvent;
+use crate::server::auth::{self, ClaimedClient};
+use crate::server::state::{AnnouncedFile, AppState, FileReceiveState};
 use crate::transfer::security;
 use crate::transfer::storage::ChunkStorage;
 use anyhow::{Context, Result};
-use axum::extract::{Multipart, Path, Query, State};
+use axum::extract::{Multipart, Path, State};
 use axum::Json;
 use axum_typed_multipart::{TryFromMultipart, TypedMultipart};
+use base64::{engine::general_purpose, Engine};
 use bytes::Bytes;
 use serde_json::{json, Value};
+use std::collections::HashSet;
 use tokio_util::bytes;
+use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
 pub struct ClientManifestEntry {
@@ -18,56 +22,104 @@ pub struct ClientManifestEntry {
     pub size: u64,
 }
 
+/// Decrypted from `SealedManifest`
 #[derive(serde::Deserialize)]
 pub struct ClientManifest {
     pub files: Vec<ClientManifestEntry>,
+    /// Suite from the client's link, must be the session's
+    #[serde(default)]
+    pub suite: CipherSuite,
+}
+
+/// `ClientManifest` sealed with the session's metadata key, see `crypto::kdf`
+/// Paths and sizes never cross the tunnel in the clear
+#[derive(serde::Deserialize)]
+pub struct SealedManifest {
+    pub manifest: String,
 }
 
 #[derive(TryFromMultipart)]
 pub struct ChunkUploadRequest {
+    // Default field limit is 1MiB, chunk is CHUNK_SIZE + 16 byte tag, plus a
+    // 24 byte nonce for XChaCha
+    #[form_data(limit = "2MiB")]
     pub chunk: Bytes,
-    #[form_data(field_name = "relativePath")]
-    pub relative_path: String,
+    /// Assigned by receive_manifest, path and size stay on the server
+    #[form_data(field_name = "fileId")]
+    pub file_id: String,
     #[form_data(field_name = "chunkIndex")]
     pub chunk_index: usize,
-    #[form_data(field_name = "totalChunks")]
-    pub total_chunks: usize,
-    #[form_data(field_name = "fileSize")]
-    pub file_size: u64,
     pub nonce: Option<String>,
-    #[form_data(field_name = "clientId")]
-    pub client_id: String,
 }
 
 pub async fn receive_manifest(
     Path(token): Path<String>,
-    Query(params): Query<ClientIdParam>,
+    ClaimedClient(client_id): ClaimedClient,
     State(state): State<AppState>,
-    Json(manifest): Json<ClientManifest>,
+    Json(sealed): Json<SealedManifest>,
 ) -> Result<axum::Json<Value>, AppError> {
-    let client_id = &params.client_id;
+    // Session is claimed through claim_handlers first
+    auth::require_active_session(&state.session, &token, &client_id)?;
+
+    let sealed = general_purpose::URL_SAFE_NO_PAD
+        .decode(&sealed.manifest)
+        .context("Invalid manifest encoding")?;
+    let manifest: ClientManifest = serde_json::from_slice(
+        &crypto::open(&state.session.metadata_cipher(), &sealed)
+            .context("Failed to decrypt the manifest")?,
+    )
+    .context("Invalid manifest")?;
+    if manifest.suite != state.session.cipher_suite() {
+        return Err(anyhow::anyhow!(
+            "Manifest uses {}, session uses {}",
+            manifest.suite,
+            state.session.cipher_suite()
+        )
+        .into());
+    }
+
+    // Reject the whole list up front, nothing is written for a bad path
+    let mut paths = HashSet::new();
+    for file in &manifest.files {
+        security::validate_path(&file.relative_path).context("Invalid file path")?;
+        if !paths.insert(file.relative_path.as_str()) {
+            return Err(anyhow::anyhow!("Duplicate file path in manifest").into());
+        }
+    }
 
-    // Claim session with manifest
-    auth::claim_or_validate_session(&state.session, &token, client_id)?;
+    // Opaque ids in manifest order, the client maps them back to its files
+    let mut file_ids = Vec::with_capacity(manifest.files.len());
+    let mut total_chunks = 0;
+    for file in manifest.files {
+        // Empty files still upload one (empty) chunk so they get created
+        let file_chunks = file.size.div_ceil(crate::config::CHUNK_SIZE).max(1);
+        total_chunks += file_chunks;
 
-    // Calculate total chunks from manifest
-    let total_chunks: u64 = manifest
-        .files
-        .iter()
-        .map(|f| (f.size + crate::config::CHUNK_SIZE - 1) / crate::config::CHUNK_SIZE)
-        .sum();
+        let file_id = Uuid::new_v4().to_string();
+        state.announced.insert(
+            file_id.clone(),
+            AnnouncedFile {
+                relative_path: file.relative_path,
+                size: file.size,
+                total_chunks: file_chunks as usize,
+            },
+        );
+        file_ids.push(file_id);
+    }
 
     // Update session with total chunks
     state.session.set_total_chunks(total_chunks);
 
     Ok(Json(json!({
         "success": true,
-        "total_chunks": total_chunks
+        "total_chunks": total_chunks,
+        "file_ids": file_ids,
     })))
 }
 
 pub async fn receive_handler(
     Path(token): Path<String>,
+    ClaimedClient(client_id): ClaimedClient,
     State(state): State<AppState>,
     TypedMultipart(payload): TypedMultipart<ChunkUploadRequest>,
 ) -> Result<axum::Json<Value>, AppError> {
@@ -75,21 +127,11 @@ pub async fn receive_handler(
         .receive_sessions()
         .ok_or_else(|| anyhow::anyhow!("Invalid server mode: not a receive server"))?;
 
-    // Get or create session
-    let file_id = security::hash_path(&payload.relative_path);
-
-    // Sessions are claimed on first file and verified on rest
-    let client_id = &payload.client_id;
-    let is_new_file = !receive_sessions.contains_key(&file_id);
+    auth::require_active_session(&state.session, &token, &client_id)?;
 
-    if is_new_file && payload.chunk_index == 0 {
-        auth::claim_or_validate_session(&state.session, &token, client_id)?;
-    } else {
-        auth::require_active_session(&state.session, &token, client_id)?;
-    }
-
-    // Lock receive session
-    let session_exits = receive_sessions.contains_key(&file_id);
+    // Get or create session
+    let file_id = &payload.file_id;
+    let session_exits = receive_sessions.contains_key(file_id);
 
     if !session_exits {
         let destination = state
@@ -98,38 +140,54 @@ pub async fn receive_handler(
             .cloned()
             .ok_or_else(|| anyhow::anyhow!("Invalid session type"))?;
 
-        // Validate provided path and join to base
-        security::validate_path(&payload.relative_path).context("Invalid file path")?;
-        let dest_path = destination.join(&payload.relative_path);
+        let (relative_path, size, total_chunks) = state
+            .announced
+            .get(file_id)
+            .map(|file| (file.relative_path.clone(), file.size, file.total_chunks))
+            .ok_or_else(|| anyhow::anyhow!("Unknown file id"))?;
+
+        // Checked with the manifest already, the join below relies on it
+        security::validate_path(&relative_path).context("Invalid file path")?;
+        let dest_path = destination.join(&relative_path);
 
         let storage = ChunkStorage::new(dest_path)
             .await
             .context("Failed to create storage")?;
 
+        state.session.audit().record(AuditEvent::FileStarted {
+            client_id: client_id.clone(),
+            path: relative_path.clone(),
+            size,
+        });
+
         receive_sessions.insert(
             file_id.clone(),
             FileReceiveState {
                 storage,
-                total_chunks: payload.total_chunks,
+                total_chunks,
                 nonce: payload.nonce.clone().unwrap_or_default(),
-                relative_path: payload.relative_path.clone(),
-                file_size: payload.file_size,
+                relative_path,
+                file_size: size,
             },
         );
     }
 
     let mut session = receive_sessions
-        .get_mut(&file_id)
+        .get_mut(file_id)
         .ok_or_else(|| anyhow::anyhow!("Invalid session"))?;
 
     // Update nonce if provided (chunk 0 contains the nonce)
     if let Some(ref nonce_str) = payload.nonce {
         if session.nonce.is_empty() {
-            eprintln!("[receive] Setting nonce from chunk {}", payload.chunk_index);
+            tracing::debug!("Setting nonce from chunk {}", payload.chunk_index);
             session.nonce = nonce_str.clone();
         }
     }
 
+    if payload.chunk_index >= session.total_chunks {
+        return Err(anyhow::anyhow!("Chunk index out of bounds").into());
+    }
+
     // Check for duplicates
     if session.storage.has_chunk(payload.chunk_index) {
         return Ok(axum::Json(json!({
@@ -142,20 +200,22 @@ pub async fn receive_handler(
     }
 
     // store chunk
-    let nonce = Nonce::from_base64(&session.nonce)?;
+    let nonce = Nonce::from_base64(&session.nonce, state.session.cipher_suite())?;
 
-    let cipher = state.session.cipher();
+    let cipher = state.session.file_cipher(&session.relative_path);
+    let last = payload.chunk_index + 1 == session.total_chunks;
 
     session
         .storage
-        .store_chunk(payload.chunk_index, payload.chunk, cipher, &nonce)
+        .store_chunk(payload.chunk_index, payload.chunk, &cipher, &nonce, last)
         .await?;
 
     // Track progress
     let (chunks_processed, total_chunks) = state.session.increment_received_chunk();
 
     if total_chunks > 0 {
-        let progress = (chunks_processed as f64 / total_chunks as f64) * 100.0;
+        // 100% shuts the server down, leave that to complete_transfer
+        let progress = ((chunks_processed as f64 / total_chunks as f64) * 100.0).min(99.0);
         let _ = state.progress_sender.send(progress);
     }
 
@@ -168,7 +228,7 @@ pub async fn receive_handler(
 }
 pub async fn finalize_upload(
     Path(token): Path<String>,
-    Query(params): Query<ClientIdParam>,
+    ClaimedClient(client_id): ClaimedClient,
     State(state): State<AppState>,
     mut multipart: Multipart,
 ) -> Result<axum::Json<Value>, AppError> {
@@ -177,25 +237,24 @@ pub async fn finalize_upload(
         .ok_or_else(|| anyhow::anyhow!("Invalid server mode: not a receive server"))?;
 
     // Validate session
-    let client_id = &params.client_id;
-    auth::require_active_session(&state.session, &token, client_id)?;
+    auth::require_active_session(&state.session, &token, &client_id)?;
 
-    // Parse relativePath
-    let mut relative_path = None;
+    // Parse fileId
+    let mut file_id = None;
     while let Some(field) = multipart.next_field().await? {
-        if field.name() == Some("relativePath") {
-            relative_path = Some(field.text().await?);
+        if field.name() == Some("fileId") {
+            file_id = Some(field.text().await?);
             break;
         }
     }
-    let relative_path = relative_path.ok_or_else(|| anyhow::anyhow!("Missing relativePath"))?;
-
-    // Generate file ID and remove from sessions map
-    let file_id = security::hash_path(&relative_path);
+    let file_id = file_id.ok_or_else(|| anyhow::anyhow!("Missing fileId"))?;
 
+    // Remove from sessions map
     let (_key, session) = receive_sessions
         .remove(&file_id)
-        .ok_or_else(|| anyhow::anyhow!("No upload session found for file: {}", relative_path))?;
+        .ok_or_else(|| anyhow::anyhow!("No upload session found for file id: {}", file_id))?;
+    state.announced.remove(&file_id);
+    let relative_path = session.relative_path.clone();
 
     // Verify all chunks received
     if session.storage.chunk_count() != session.total_chunks {
@@ -211,6 +270,13 @@ pub async fn finalize_upload(
     // Finalize storage
     let computed_hash = session.storage.finalize().await?;
 
+    state.session.audit().record(AuditEvent::FileFinished {
+        client_id,
+        path: relative_path,
+        size: session.file_size,
+        sha256: computed_hash.clone(),
+    });
+
     Ok(axum::Json(json!({
         "success": true,
         "sha256": computed_hash,
@@ -219,12 +285,11 @@ pub async fn finalize_upload(
 
 pub async fn complete_transfer(
     Path(token): Path<String>,
-    Query(params): Query<ClientIdParam>,
+    ClaimedClient(client_id): ClaimedClient,
     State(state): State<AppState>,
 ) -> Result<axum::Json<Value>, AppError> {
-    let client_id = &params.client_id;
-    auth::require_active_session(&state.session, &token, client_id)?;
-    state.session.complete(&token, &params.client_id);
+    auth::require_active_session(&state.session, &token, &client_id)?;
+    state.session.complete(&token, &client_id);
 
     let _ = state.progress_sender.send(100.0);
 
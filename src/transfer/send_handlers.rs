@@ -3,34 +3,29 @@ use std::path::PathBuf;
 
 use crate::crypto::types::Nonce;
 use crate::errors::AppError;
-use crate::server::auth::{self, ClientIdParam};
+use crate::server::audit::AuditEvent;
+use crate::server::auth::{self, ClaimedClient};
 use crate::server::state::AppState;
-use crate::transfer::manifest::Manifest;
+use crate::transfer::io;
 use crate::{config, crypto};
 use anyhow::{Context, Result};
-use axum::extract::Query;
 use axum::{
     body::Body,
     extract::{Path, State},
     http::Response,
     Json,
 };
+use base64::{engine::general_purpose, Engine};
 use reqwest::header;
 
-#[derive(serde::Deserialize)]
-pub struct ChunkParams {
-    #[serde(rename = "clientId")]
-    client_id: String,
-}
-
 pub async fn manifest_handler(
     Path(token): Path<String>,
-    Query(params): Query<ClientIdParam>,
+    ClaimedClient(client_id): ClaimedClient,
     State(state): State<AppState>,
-) -> Result<Json<Manifest>, AppError> {
-    // Session claimed when fetching manifest
+) -> Result<Json<serde_json::Value>, AppError> {
+    // Session is claimed through claim_handlers first
     // Manifests holds info about files (sizes, names) only client should see
-    auth::claim_or_validate_session(&state.session, &token, &params.client_id)?;
+    auth::require_active_session(&state.session, &token, &client_id)?;
 
     // Get manifest from session
     let manifest = state
@@ -38,17 +33,25 @@ pub async fn manifest_handler(
         .manifest()
         .ok_or_else(|| anyhow::anyhow!("Not a send session"))?;
 
-    Ok(Json(manifest.clone()))
+    // Sealed like the file contents, a tunnel in the middle sees neither
+    let sealed = crypto::seal(
+        &state.session.metadata_cipher(),
+        &serde_json::to_vec(manifest)?,
+    )?;
+
+    Ok(Json(serde_json::json!({
+        "manifest": general_purpose::URL_SAFE_NO_PAD.encode(sealed)
+    })))
 }
 
 pub async fn send_handler(
     Path((token, file_index, chunk_index)): Path<(String, usize, usize)>,
-    Query(params): Query<ChunkParams>,
+    ClaimedClient(client_id): ClaimedClient,
     State(state): State<AppState>,
 ) -> Result<Response<Body>, AppError> {
-    let client_id = &params.client_id;
+    let client_id = &client_id;
 
-    // Sessions are claimed by manifest, so just check client
+    // Sessions are claimed up front, so just check client
     auth::require_active_session(&state.session, &token, client_id)?;
 
     let file_entry = state
@@ -65,24 +68,38 @@ pub async fn send_handler(
     }
 
     let chunk_len = (end - start) as usize;
+    let last = end == file_entry.size;
+
+    // Retries of the first chunk show up again, the audit log keeps them
+    if chunk_index == 0 {
+        state.session.audit().record(AuditEvent::FileStarted {
+            client_id: client_id.clone(),
+            path: file_entry.relative_path.clone(),
+            size: file_entry.size,
+        });
+    }
 
     // read chunk
     let buffer = read_chunk_blocking(file_entry.full_path.clone(), start, chunk_len)
         .await
         .context("Failed reading chunkdata")?;
 
-    let (new_total_chunks, session_total_chunks) = state.session.increment_sent_chunk();
-    let progress = (new_total_chunks as f64 / session_total_chunks as f64) * 100.0;
+    state
+        .session
+        .increment_sent_chunk(client_id, file_index, chunk_index);
+    // 100% closes the TUI and shuts the server down, only complete_download may send it
+    // otherwise hash and complete requests race the shutdown
+    let progress = state.session.send_progress().min(99.0);
     let _ = state.progress_sender.send(progress);
 
     // encrypt and return
-    let file_nonce = Nonce::from_base64(&file_entry.nonce)
+    let file_nonce = Nonce::from_base64(&file_entry.nonce, state.session.cipher_suite())
         .context(format!("Invalid nonce for file: {}", file_entry.name))?;
 
-    let cipher = state.session.cipher();
+    let cipher = state.session.file_cipher(&file_index.to_string());
 
     let encrypted =
-        crypto::encrypt_chunk_at_position(cipher, &file_nonce, &buffer, chunk_index as u32)
+        crypto::encrypt_chunk_at_position(&cipher, &file_nonce, &buffer, chunk_index as u32, last)
             .context(format!(
                 "Failed to encrypt chunk {} of file {}",
                 chunk_index, file_entry.name
@@ -95,17 +112,34 @@ pub async fn send_handler(
 
 pub async fn complete_download(
     Path(token): Path<String>,
-    Query(params): Query<ChunkParams>,
+    ClaimedClient(client_id): ClaimedClient,
     State(state): State<AppState>,
 ) -> Result<axum::Json<serde_json::Value>, AppError> {
     // Session must be active and owned to complete
-    let client_id = &params.client_id;
+    let client_id = &client_id;
     auth::require_active_session(&state.session, &token, client_id)?;
 
-    state.session.complete(&token, client_id);
+    let completed = state.session.complete(&token, client_id);
 
     // Set progress to 100% to signal completion and close TUI
-    let _ = state.progress_sender.send(100.0);
+    // Other recipients may still be downloading
+    let progress = if state.session.is_finished() {
+        // Burn before the TUI closes and the server shuts down, only once the
+        // server itself handed this client every chunk
+        if completed
+            && state.session.burns_files()
+            && state.session.was_served_everything(client_id)
+        {
+            if let Some(manifest) = state.session.manifest() {
+                let paths = manifest.files.iter().map(|f| f.full_path.clone()).collect();
+                io::remove_files(paths).await;
+            }
+        }
+        100.0
+    } else {
+        state.session.send_progress().min(99.0)
+    };
+    let _ = state.progress_sender.send(progress);
 
     Ok(axum::Json(serde_json::json!({
         "success": true,
@@ -115,10 +149,10 @@ pub async fn complete_download(
 
 pub async fn get_file_hash(
     Path((token, file_index)): Path<(String, usize)>,
-    Query(params): Query<ChunkParams>,
+    ClaimedClient(client_id): ClaimedClient,
     State(state): State<AppState>,
 ) -> Result<axum::Json<serde_json::Value>, AppError> {
-    let client_id = &params.client_id;
+    let client_id = &client_id;
     auth::require_active_session(&state.session, &token, client_id)?;
 
     let file_entry = state
@@ -126,45 +160,21 @@ pub async fn get_file_hash(
         .get_file(file_index)
         .ok_or_else(|| anyhow::anyhow!("Invalid file index"))?;
 
-    let hash = compute_file_hash(&file_entry.full_path).await?;
+    let hash = io::hash_file(&file_entry.full_path).await?;
+
+    // Clients ask for the hash to verify a finished file
+    state.session.audit().record(AuditEvent::FileFinished {
+        client_id: client_id.clone(),
+        path: file_entry.relative_path.clone(),
+        size: file_entry.size,
+        sha256: hash.clone(),
+    });
 
     Ok(axum::Json(serde_json::json!({
         "sha256": hash
     })))
 }
 
-async fn compute_file_hash(path: &std::path::Path) -> Result<String> {
-    use sha2::{Digest, Sha256};
-    // Use spawn_blocking for disk I/O
-    let path = path.to_owned();
-
-    tokio::task::spawn_blocking(move || {
-        use std::io::Read;
-
-        let mut file = std::fs::File::open(&path).context(format!(
-            "Failed to open file for hashing: {}",
-            path.display()
-        ))?;
-
-        let mut hasher = Sha256::new();
-        let mut buffer = vec![0u8; 65536]; // 64 KB chunks
-
-        loop {
-            let n = file
-                .read(&mut buffer)
-                .context("Failed to read file for hashing")?;
-            if n == 0 {
-                break;
-            }
-            hasher.update(&buffer[..n]);
-        }
-
-        Ok::<String, anyhow::Error>(hex::encode(hasher.finalize()))
-    })
-    .await
-    .context("Hash computation task panicked")?
-}
-
 /// Opens, reads a chunk, and closes the file handle using a blocking task.
 async fn read_chunk_blocking(path: PathBuf, start: u64, chunk_len: usize) -> Result<Vec<u8>> {
     // File reading is sync
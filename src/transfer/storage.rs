@@ -2,7 +2,6 @@
 // Provides operations for chunk management
 // RAII guard is used for cleanups on Error
 
-use aes_gcm::Aes256Gcm;
 use anyhow::{Context, Result};
 use axum::body::Bytes;
 use sha2::{Digest, Sha256};
@@ -13,8 +12,7 @@ use tokio::fs::{File, OpenOptions};
 use tokio::io::{AsyncSeekExt, AsyncWriteExt};
 
 use crate::config::CHUNK_SIZE;
-use crate::crypto;
-use crate::crypto::types::Nonce;
+use crate::crypto::{self, types::Nonce, Cipher};
 
 pub struct ChunkStorage {
     file: File,
@@ -34,6 +32,7 @@ impl ChunkStorage {
             .read(true)
             .write(true)
             .create(true)
+            .truncate(true)
             .open(&dest_path)
             .await
             .context(format!(
@@ -64,13 +63,20 @@ impl ChunkStorage {
         &mut self,
         chunk_index: usize,
         encrypted_data: Bytes,
-        cipher: &Aes256Gcm,
+        cipher: &Cipher,
         nonce: &Nonce,
+        last: bool,
     ) -> Result<()> {
         // Decrypt chunk
-        // AES-GCM auth tag handles single chunk integrity
-        let decrypted =
-            crypto::decrypt_chunk_at_position(cipher, nonce, &encrypted_data, chunk_index as u32)?;
+        // AEAD tag handles single chunk integrity, the STREAM position binds it
+        // to its place in the file
+        let decrypted = crypto::decrypt_chunk_at_position(
+            cipher,
+            nonce,
+            &encrypted_data,
+            chunk_index as u32,
+            last,
+        )?;
 
         // Seek positon - handles out of order arival
         let offset = (chunk_index as u64) * CHUNK_SIZE;
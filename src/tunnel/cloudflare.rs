@@ -0,0 +1,268 @@
+use super::{process_health, shutdown_process, TunnelProvider, TUNNEL_URL_TIMEOUT};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::{Child, ChildStderr, Command};
+use tracing::{debug, warn};
+
+const TUNNEL_POLL_INTERVAL: Duration = Duration::from_millis(200);
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Deserialize)]
+struct QuickTunnelResponse {
+    hostname: String,
+}
+
+/// cloudflared tunnel
+///
+/// Quick tunnels get a random trycloudflare.com hostname every run. Named
+/// tunnels keep a stable hostname, routed once with
+/// `cloudflared tunnel route dns <name> <hostname>`.
+#[derive(Default)]
+pub struct CloudflareTunnel {
+    named: Option<NamedTunnel>,
+    process: Option<Child>,
+    metrics_port: Option<u16>,
+    url: Option<String>,
+}
+
+struct NamedTunnel {
+    name: String,
+    hostname: String,
+    credentials_file: Option<PathBuf>,
+}
+
+impl CloudflareTunnel {
+    /// Quick tunnel, no account needed
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `cloudflared tunnel run <name>`, served on https://<hostname>
+    pub fn named(name: impl Into<String>, hostname: &str) -> Self {
+        // Accept a full URL too
+        let hostname = hostname
+            .trim_start_matches("https://")
+            .trim_start_matches("http://")
+            .trim_end_matches('/')
+            .to_string();
+
+        Self {
+            named: Some(NamedTunnel {
+                name: name.into(),
+                hostname,
+                credentials_file: None,
+            }),
+            ..Self::default()
+        }
+    }
+
+    /// Credentials JSON from `cloudflared tunnel create`
+    /// cloudflared looks in ~/.cloudflared when unset
+    pub fn credentials_file(mut self, path: PathBuf) -> Self {
+        if let Some(named) = &mut self.named {
+            named.credentials_file = Some(path);
+        }
+        self
+    }
+}
+
+#[async_trait]
+impl TunnelProvider for CloudflareTunnel {
+    fn name(&self) -> &'static str {
+        "cloudflared"
+    }
+
+    // Cloudflare overwrites it, unlike X-Forwarded-For which it appends to
+    fn client_ip_header(&self) -> Option<&'static str> {
+        Some("cf-connecting-ip")
+    }
+
+    async fn start(&mut self, local_port: u16) -> Result<String> {
+        let metrics_port = get_available_port()
+            .ok_or_else(|| anyhow::anyhow!("No free ports for tunnel metrics"))?;
+
+        let origin = format!("http://127.0.0.1:{}", local_port);
+        let mut command = Command::new("cloudflared");
+        command.args([
+            "tunnel",
+            "--metrics",
+            &format!("localhost:{}", metrics_port),
+            "--no-autoupdate",
+            "--protocol",
+            "http2",
+        ]);
+
+        match &self.named {
+            Some(named) => {
+                command.arg("run");
+                if let Some(credentials) = &named.credentials_file {
+                    command.arg("--credentials-file").arg(credentials);
+                }
+                command.args(["--url", &origin, &named.name]);
+            }
+            None => {
+                command.args(["--url", &origin]);
+            }
+        }
+
+        // spawn cloudflared process & capture output
+        let mut child = command
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .context("Failed to spawn cloudflared process")?;
+
+        // log stderr for debugging
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(log_stderr(stderr));
+        }
+
+        // Quick tunnel hostname comes from the metrics server, not the logs
+        let ready = match &self.named {
+            Some(named) => wait_for_ready(&mut child, metrics_port)
+                .await
+                .map(|_| format!("https://{}", named.hostname)),
+            None => wait_for_url(&mut child, metrics_port).await,
+        };
+        let url = match ready {
+            Ok(u) => u,
+            Err(e) => {
+                if let Err(kill_err) = child.kill().await {
+                    eprintln!("Failed to kill tunnel process: {}", kill_err);
+                }
+                return Err(e).context("Failed to obtain tunnel URL");
+            }
+        };
+
+        self.process = Some(child);
+        self.metrics_port = Some(metrics_port);
+        self.url = Some(url.clone());
+        Ok(url)
+    }
+
+    fn url(&self) -> Option<&str> {
+        self.url.as_deref()
+    }
+
+    async fn health(&mut self) -> Result<()> {
+        let name = self.name();
+        process_health(&mut self.process, name)?;
+
+        // Running is not enough, it also needs a live connection to the edge
+        let Some(metrics_port) = self.metrics_port else {
+            return Ok(());
+        };
+        let res = reqwest::Client::new()
+            .get(format!("http://localhost:{}/ready", metrics_port))
+            .timeout(HEALTH_TIMEOUT)
+            .send()
+            .await
+            .context("Tunnel metrics unreachable")?;
+        if !res.status().is_success() {
+            bail!("No connection to Cloudflare ({})", res.status());
+        }
+        Ok(())
+    }
+
+    async fn shutdown(&mut self) -> Result<()> {
+        shutdown_process(&mut self.process).await
+    }
+}
+
+async fn wait_for_url(child: &mut Child, metrics_port: u16) -> Result<String> {
+    let client = reqwest::Client::new();
+    let api_url = format!("http://localhost:{}/quicktunnel", metrics_port);
+
+    tokio::time::timeout(TUNNEL_URL_TIMEOUT, async {
+        let mut interval = tokio::time::interval(TUNNEL_POLL_INTERVAL);
+
+        loop {
+            interval.tick().await;
+            ensure_running(child)?;
+
+            match client.get(&api_url).send().await {
+                Ok(res) => {
+                    if res.status().is_success() {
+                        match res.json::<QuickTunnelResponse>().await {
+                            Ok(json) if !json.hostname.is_empty() => {
+                                return Ok(format!("https://{}", json.hostname));
+                            }
+                            Ok(_) => {
+                                debug!("Waiting for hostname from tunnel...");
+                            }
+                            Err(e) => {
+                                warn!("Failed to parse tunnel response: {}", e);
+                            }
+                        }
+                    } else {
+                        debug!("Tunnel metrics returned status: {}", res.status());
+                    }
+                }
+                Err(e) => {
+                    debug!("Tunnel not ready yet: {}", e);
+                }
+            }
+        }
+    })
+    .await
+    .context("Timed out waiting for tunnel URL")?
+}
+
+// Named tunnels know their hostname, /ready turns 200 once connected to the edge
+async fn wait_for_ready(child: &mut Child, metrics_port: u16) -> Result<()> {
+    let client = reqwest::Client::new();
+    let ready_url = format!("http://localhost:{}/ready", metrics_port);
+
+    tokio::time::timeout(TUNNEL_URL_TIMEOUT, async {
+        let mut interval = tokio::time::interval(TUNNEL_POLL_INTERVAL);
+
+        loop {
+            interval.tick().await;
+            ensure_running(child)?;
+
+            match client.get(&ready_url).send().await {
+                Ok(res) if res.status().is_success() => return Ok(()),
+                Ok(res) => debug!("Tunnel not connected yet: {}", res.status()),
+                Err(e) => debug!("Tunnel not ready yet: {}", e),
+            }
+        }
+    })
+    .await
+    .context("Timed out waiting for tunnel to connect")?
+}
+
+// Fail fast instead of polling a dead process until the timeout
+fn ensure_running(child: &mut Child) -> Result<()> {
+    match child.try_wait()? {
+        Some(status) => bail!("cloudflared exited with {}", status),
+        None => Ok(()),
+    }
+}
+
+fn get_available_port() -> Option<u16> {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .ok()
+        .and_then(|l| l.local_addr().ok())
+        .map(|a| a.port())
+}
+
+// Cloudflar only uses stderr for logging
+async fn log_stderr(stderr: ChildStderr) {
+    let reader = BufReader::new(stderr);
+    let mut lines = reader.lines();
+
+    // Cloudflare uses stderr for both logs and errors
+    // errors will contain error/fatal
+    while let Some(line) = lines.next_line().await.ok().flatten() {
+        let lowercase_line = line.to_lowercase();
+        if lowercase_line.contains("error") || lowercase_line.contains("fatal") {
+            tracing::error!("cloudflared stderr: {}", line);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use super::{process_health, shutdown_process, wait_for_output_url, TunnelProvider};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use regex::Regex;
+use std::process::Stdio;
+use tokio::process::{Child, Command};
+
+// Any URL printed on stdout or stderr
+const DEFAULT_URL_PATTERN: &str = r"https://[^\s]+";
+
+/// Runs a tunnel client and reads its public URL from the output
+///
+/// `{port}` in the command is replaced with the local port, e.g.
+/// `ngrok http {port} --log stdout`. Arguments are split like a shell
+/// would, but no shell is involved.
+pub struct CommandTunnel {
+    command: String,
+    url_pattern: Regex,
+    process: Option<Child>,
+    url: Option<String>,
+}
+
+impl CommandTunnel {
+    pub fn new(command: impl Into<String>) -> Self {
+        Self {
+            command: command.into(),
+            url_pattern: Regex::new(DEFAULT_URL_PATTERN).expect("valid default pattern"),
+            process: None,
+            url: None,
+        }
+    }
+
+    /// Pattern for the URL in the output, capture group 1 if present
+    pub fn url_pattern(mut self, pattern: Regex) -> Self {
+        self.url_pattern = pattern;
+        self
+    }
+}
+
+#[async_trait]
+impl TunnelProvider for CommandTunnel {
+    fn name(&self) -> &'static str {
+        "command"
+    }
+
+    async fn start(&mut self, local_port: u16) -> Result<String> {
+        let command = self.command.replace("{port}", &local_port.to_string());
+        let Some(args) = shlex::split(&command) else {
+            bail!("Invalid tunnel command: {}", command);
+        };
+        let Some((program, args)) = args.split_first() else {
+            bail!("Tunnel command is empty");
+        };
+
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .context(format!("Failed to spawn {}", program))?;
+
+        let url = match wait_for_output_url(&mut child, &self.url_pattern, "command").await {
+            // A loose pattern can match something that isn't a URL
+            Ok(url) if reqwest::Url::parse(&url).is_err() => {
+                let _ = child.kill().await;
+                bail!("Tunnel command printed '{}', which is not a URL", url);
+            }
+            Ok(url) => url,
+            Err(e) => {
+                let _ = child.kill().await;
+                return Err(e).context(format!("Tunnel command '{}' failed", command));
+            }
+        };
+
+        self.process = Some(child);
+        self.url = Some(url.clone());
+        Ok(url)
+    }
+
+    fn url(&self) -> Option<&str> {
+        self.url.as_deref()
+    }
+
+    async fn health(&mut self) -> Result<()> {
+        let name = self.name();
+        process_health(&mut self.process, name)
+    }
+
+    async fn shutdown(&mut self) -> Result<()> {
+        shutdown_process(&mut self.process).await
+    }
+}
@@ -0,0 +1,134 @@
+//! Public tunnels for internet mode
+//!
+//! Each provider exposes the local HTTP port and reports the public URL.
+//! The server only talks to `TunnelProvider`, so a stand-in process can
+//! replace the real thing.
+
+mod cloudflare;
+mod command;
+mod ssh;
+pub mod supervisor;
+
+pub use cloudflare::CloudflareTunnel;
+pub use command::CommandTunnel;
+pub use ssh::{SshTunnel, DEFAULT_SSH_HOST};
+
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use regex::Regex;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Child;
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+const TUNNEL_URL_TIMEOUT: Duration = Duration::from_secs(15);
+
+#[async_trait]
+pub trait TunnelProvider: Send {
+    /// Short name for messages, e.g. "cloudflared"
+    fn name(&self) -> &'static str;
+
+    /// Expose local_port and return the public base URL
+    async fn start(&mut self, local_port: u16) -> Result<String>;
+
+    /// Public base URL once started, no trailing slash
+    fn url(&self) -> Option<&str>;
+
+    /// Header the tunnel puts the visitor's address in, if it sets one the
+    /// visitor can't forge. Without it every visitor shares the loopback address
+    fn client_ip_header(&self) -> Option<&'static str> {
+        None
+    }
+
+    /// Err once the tunnel is no longer usable
+    async fn health(&mut self) -> Result<()>;
+
+    async fn shutdown(&mut self) -> Result<()>;
+}
+
+//-----------------
+// PROCESS HELPERS
+//-----------------
+// Every provider so far is a child process
+
+// Err if the process has exited
+pub(crate) fn process_health(process: &mut Option<Child>, name: &str) -> Result<()> {
+    let Some(child) = process else {
+        bail!("{} tunnel is not running", name);
+    };
+    match child.try_wait().context("Failed to check tunnel process")? {
+        Some(status) => bail!("{} exited with {}", name, status),
+        None => Ok(()),
+    }
+}
+
+pub(crate) async fn shutdown_process(process: &mut Option<Child>) -> Result<()> {
+    let Some(mut child) = process.take() else {
+        return Ok(());
+    };
+
+    // send kill signal
+    if let Err(e) = child.kill().await {
+        // failed kill often means the process is already dead
+        warn!("Failed to send graceful signal to tunnel process: {}", e);
+        return Ok(());
+    }
+
+    match tokio::time::timeout(Duration::from_secs(5), child.wait()).await {
+        Ok(Ok(status)) => {
+            info!("Tunnel process exited with status: {}", status);
+            Ok(())
+        }
+        Ok(Err(e)) => Err(e).context("Failed to wait for tunnel process"),
+        Err(_) => {
+            warn!("Tunnel process did not exit after 5 seconds, may be stuck");
+            // exhausted attempts, just log
+            Ok(())
+        }
+    }
+}
+
+// Scan stdout and stderr for the first URL matching pattern
+// Capture group 1 is used when the pattern has one
+// Readers keep draining after the match so the pipes never fill up
+pub(crate) async fn wait_for_output_url(
+    child: &mut Child,
+    pattern: &Regex,
+    name: &'static str,
+) -> Result<String> {
+    let (sender, mut receiver) = mpsc::channel(1);
+
+    if let Some(stdout) = child.stdout.take() {
+        tokio::spawn(scan_lines(stdout, pattern.clone(), sender.clone(), name));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        tokio::spawn(scan_lines(stderr, pattern.clone(), sender, name));
+    }
+
+    let url = tokio::time::timeout(TUNNEL_URL_TIMEOUT, receiver.recv())
+        .await
+        .context("Timed out waiting for tunnel URL")?
+        .context(format!("{} exited before printing a URL", name))?;
+
+    Ok(url.trim_end_matches('/').to_string())
+}
+
+async fn scan_lines<R>(reader: R, pattern: Regex, sender: mpsc::Sender<String>, name: &str)
+where
+    R: AsyncRead + Unpin,
+{
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await.ok().flatten() {
+        debug!("{}: {}", name, line);
+
+        if let Some(captures) = pattern.captures(&line) {
+            let found = captures.get(1).or_else(|| captures.get(0));
+            if let Some(url) = found {
+                // Receiver is gone after the first URL, later ones are ignored
+                let _ = sender.try_send(url.as_str().to_string());
+            }
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use super::{process_health, shutdown_process, wait_for_output_url, TunnelProvider};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use regex::Regex;
+use std::process::Stdio;
+use tokio::process::{Child, Command};
+
+/// localhost.run, free and needs no account
+pub const DEFAULT_SSH_HOST: &str = "nokey@localhost.run";
+
+// localhost.run prints "<id>.lhr.life tunneled with tls termination, https://<id>.lhr.life"
+// Its banner has other links, so match the whole phrase
+const DEFAULT_URL_PATTERN: &str = r"tunneled with tls termination, (https://\S+)";
+
+// ssh -v logs this once the server accepted the -R forward
+const FORWARD_READY_PATTERN: &str = r"remote forward success";
+
+/// Reverse tunnel with `ssh -R`
+///
+/// Hosted services print the public URL, pass a pattern for anything other
+/// than localhost.run. For your own server with a fixed name set `public_url`.
+pub struct SshTunnel {
+    destination: String,
+    remote_port: u16,
+    url_pattern: Regex,
+    public_url: Option<String>,
+    process: Option<Child>,
+    url: Option<String>,
+}
+
+impl SshTunnel {
+    pub fn new(destination: impl Into<String>) -> Self {
+        Self {
+            destination: destination.into(),
+            remote_port: 80,
+            url_pattern: Regex::new(DEFAULT_URL_PATTERN).expect("valid default pattern"),
+            public_url: None,
+            process: None,
+            url: None,
+        }
+    }
+
+    /// Port the ssh server listens on, 80 for hosted services
+    pub fn remote_port(mut self, port: u16) -> Self {
+        self.remote_port = port;
+        self
+    }
+
+    /// Pattern for the URL in ssh output, capture group 1 if present
+    pub fn url_pattern(mut self, pattern: Regex) -> Self {
+        self.url_pattern = pattern;
+        self
+    }
+
+    /// Known public URL, e.g. a reverse proxy in front of your own server
+    pub fn public_url(mut self, url: impl Into<String>) -> Self {
+        self.public_url = Some(url.into());
+        self
+    }
+}
+
+#[async_trait]
+impl TunnelProvider for SshTunnel {
+    fn name(&self) -> &'static str {
+        "ssh"
+    }
+
+    async fn start(&mut self, local_port: u16) -> Result<String> {
+        let mut command = Command::new("ssh");
+        command.args([
+            "-T",
+            "-o",
+            "ExitOnForwardFailure=yes",
+            "-o",
+            "ServerAliveInterval=30",
+            "-o",
+            "StrictHostKeyChecking=accept-new",
+            "-R",
+            &format!("{}:127.0.0.1:{}", self.remote_port, local_port),
+        ]);
+
+        // Nothing gets printed for a fixed URL, wait for the forward instead
+        if self.public_url.is_some() {
+            command.arg("-v");
+        }
+
+        // Stdin stays open, some services end the session on EOF
+        let mut child = command
+            .arg(&self.destination)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .context("Failed to spawn ssh")?;
+
+        let result = match &self.public_url {
+            Some(public_url) => {
+                let ready = Regex::new(FORWARD_READY_PATTERN).expect("valid ready pattern");
+                wait_for_output_url(&mut child, &ready, "ssh")
+                    .await
+                    .map(|_| public_url.trim_end_matches('/').to_string())
+            }
+            None => wait_for_output_url(&mut child, &self.url_pattern, "ssh").await,
+        };
+
+        let url = match result {
+            Ok(url) => url,
+            Err(e) => {
+                let _ = child.kill().await;
+                return Err(e).context(format!("ssh -R to {} failed", self.destination));
+            }
+        };
+
+        self.process = Some(child);
+        self.url = Some(url.clone());
+        Ok(url)
+    }
+
+    fn url(&self) -> Option<&str> {
+        self.url.as_deref()
+    }
+
+    async fn health(&mut self) -> Result<()> {
+        let name = self.name();
+        process_health(&mut self.process, name)
+    }
+
+    async fn shutdown(&mut self) -> Result<()> {
+        shutdown_process(&mut self.process).await
+    }
+}
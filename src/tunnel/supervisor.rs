@@ -0,0 +1,124 @@
+//! Keeps a tunnel alive for the length of a session
+
+use super::TunnelProvider;
+use anyhow::{bail, Result};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+const HEALTH_INTERVAL: Duration = Duration::from_secs(2);
+// A few misses in a row, cloudflared reconnects to the edge on its own
+const MAX_HEALTH_FAILURES: u32 = 3;
+const MAX_RESTART_ATTEMPTS: u32 = 5;
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(30);
+
+pub enum TunnelEvent {
+    /// Health checks failed, retrying after delay
+    Restarting { attempt: u32, delay: Duration },
+    /// Back up, possibly on a different base URL
+    Restored(String),
+}
+
+/// Watch a started tunnel and restart it with backoff until cancelled
+/// Shuts the tunnel down on return, Err when it could not be brought back
+pub async fn supervise(
+    mut tunnel: Box<dyn TunnelProvider>,
+    local_port: u16,
+    cancel: CancellationToken,
+    mut on_event: impl FnMut(TunnelEvent) + Send,
+) -> Result<()> {
+    let mut failures = 0;
+
+    let result = loop {
+        tokio::select! {
+            _ = cancel.cancelled() => break Ok(()),
+            _ = tokio::time::sleep(HEALTH_INTERVAL) => {}
+        }
+
+        let reason = match tunnel.health().await {
+            Ok(()) => {
+                failures = 0;
+                continue;
+            }
+            Err(e) => e,
+        };
+
+        failures += 1;
+        debug!(
+            "{} health check failed ({}): {:#}",
+            tunnel.name(),
+            failures,
+            reason
+        );
+        if failures < MAX_HEALTH_FAILURES {
+            continue;
+        }
+        failures = 0;
+
+        warn!("{} tunnel down: {:#}", tunnel.name(), reason);
+
+        match restart(tunnel.as_mut(), local_port, &cancel, &mut on_event).await {
+            Ok(Some(url)) => on_event(TunnelEvent::Restored(url)),
+            Ok(None) => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+
+    debug!("Shutting down {} tunnel...", tunnel.name());
+    if let Err(e) = tunnel.shutdown().await {
+        warn!("Error during tunnel shutdown: {}", e);
+    }
+    result
+}
+
+// Ok(None) when cancelled while retrying
+async fn restart(
+    tunnel: &mut dyn TunnelProvider,
+    local_port: u16,
+    cancel: &CancellationToken,
+    on_event: &mut (impl FnMut(TunnelEvent) + Send),
+) -> Result<Option<String>> {
+    let mut last_error = None;
+
+    for attempt in 1..=MAX_RESTART_ATTEMPTS {
+        // 1s, 2s, 4s ... capped
+        let delay = Duration::from_secs(1 << (attempt - 1)).min(MAX_RESTART_DELAY);
+        on_event(TunnelEvent::Restarting { attempt, delay });
+
+        tokio::select! {
+            _ = cancel.cancelled() => return Ok(None),
+            _ = tokio::time::sleep(delay) => {}
+        }
+
+        // Clear out whatever is left of the old process
+        if let Err(e) = tunnel.shutdown().await {
+            debug!("Error stopping old tunnel: {}", e);
+        }
+
+        let started = tokio::select! {
+            _ = cancel.cancelled() => return Ok(None),
+            started = tunnel.start(local_port) => started,
+        };
+
+        match started {
+            Ok(url) => {
+                info!("{} tunnel restored at {}", tunnel.name(), url);
+                return Ok(Some(url));
+            }
+            Err(e) => {
+                warn!("Tunnel restart {} failed: {:#}", attempt, e);
+                last_error = Some(e);
+            }
+        }
+    }
+
+    match last_error {
+        Some(e) => bail!(
+            "{} tunnel could not be restarted after {} attempts: {:#}",
+            tunnel.name(),
+            MAX_RESTART_ATTEMPTS,
+            e
+        ),
+        None => bail!("{} tunnel could not be restarted", tunnel.name()),
+    }
+}
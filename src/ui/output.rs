@@ -22,3 +22,17 @@ pub fn spinner_success(spinner: &ProgressBar, msg: &str) {
 pub fn spinner_error(spinner: &ProgressBar, msg: &str) {
     spinner.finish_with_message(format!("{} {}", style("✗").red().bold(), msg));
 }
+
+pub fn progress_bar(total_bytes: u64) -> ProgressBar {
+    let pb = ProgressBar::new(total_bytes);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{bar:40.cyan/blue} {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")
+            .unwrap(),
+    );
+    pb
+}
+
+pub fn check_mark() -> String {
+    style("✓").green().bold().to_string()
+}
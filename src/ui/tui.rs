@@ -1,3 +1,5 @@
+use crate::server::approval::{Approvals, ClaimRequest};
+use crate::server::limits::BlockedStats;
 use crossterm::{
     event::{self, Event, KeyCode},
     execute,
@@ -6,11 +8,15 @@ use crossterm::{
 use ratatui::{
     backend::CrosstermBackend,
     layout::{Constraint, Direction, Layout, Rect},
-    widgets::{Block, Borders, Gauge, Paragraph},
+    widgets::{Block, Borders, Clear, Gauge, Paragraph, Wrap},
     Frame, Terminal,
 };
-use std::{io, time::Duration};
+use std::{
+    io,
+    time::{Duration, Instant},
+};
 use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
 
 // Config for differnt terminal sizes
 struct LayoutConfig {
@@ -37,12 +43,48 @@ impl LayoutConfig {
     }
 }
 
+/// Pushed to the TUI while the session runs
+#[derive(Clone, Default)]
+pub struct SessionStatus {
+    /// Shown in the bottom bar
+    pub message: Option<String>,
+    /// Replaces the QR code, e.g. after the tunnel came back on a new URL
+    pub qr_code: Option<String>,
+    /// Per client progress, only for sends with more than one recipient
+    pub recipients: Option<RecipientsStatus>,
+    /// Session ends at this point, counted down next to the progress
+    pub expires_at: Option<Instant>,
+    /// Requests the rate limiter turned away, None until the first one
+    pub blocked: Option<BlockedStats>,
+}
+
+#[derive(Clone, Default)]
+pub struct RecipientsStatus {
+    /// None when unlimited
+    pub limit: Option<usize>,
+    pub clients: Vec<RecipientProgress>,
+}
+
+#[derive(Clone)]
+pub struct RecipientProgress {
+    pub client_id: String,
+    pub percent: f64,
+    pub done: bool,
+}
+
+// Rows shown before the list gets cut off
+const MAX_RECIPIENT_ROWS: usize = 8;
+
 pub struct TransferUI {
     progress: watch::Receiver<f64>,
     file_name: String,
     qr_code: String,
     is_recieving: bool,
-    status_message: watch::Receiver<Option<String>>,
+    fingerprint: Option<String>,
+    passphrase: Option<String>,
+    code: Option<String>,
+    approvals: Option<Approvals>,
+    status: watch::Receiver<SessionStatus>,
 }
 
 impl TransferUI {
@@ -51,19 +93,57 @@ impl TransferUI {
         file_name: String,
         qr_code: String,
         is_recieving: bool,
-        status_message: watch::Receiver<Option<String>>,
+        status: watch::Receiver<SessionStatus>,
     ) -> Self {
         Self {
             progress,
             file_name,
             qr_code,
             is_recieving,
-            status_message,
+            fingerprint: None,
+            passphrase: None,
+            code: None,
+            approvals: None,
+            status,
         }
     }
 
-    pub async fn run(&mut self) -> Result<(), io::Error> {
+    /// Local mode certificate fingerprint
+    pub fn with_fingerprint(mut self, fingerprint: Option<String>) -> Self {
+        self.fingerprint = fingerprint;
+        self
+    }
+
+    /// Passphrase to read out to the other person
+    pub fn with_passphrase(mut self, passphrase: Option<String>) -> Self {
+        self.passphrase = passphrase;
+        self
+    }
+
+    /// Transfer code, shown in place of the passphrase
+    pub fn with_code(mut self, code: Option<String>) -> Self {
+        self.code = code;
+        self
+    }
+
+    /// Ask before new clients may claim, answered with a/r
+    pub fn with_approvals(mut self, approvals: Option<Approvals>) -> Self {
+        self.approvals = approvals;
+        self
+    }
+
+    /// Runs until the transfer completes, the user quits or cancel fires
+    /// Draw until done or cancelled, the terminal is restored even on error
+    pub async fn run(&mut self, cancel: CancellationToken) -> Result<(), io::Error> {
         enable_raw_mode()?;
+        let result = self.draw_loop(cancel).await;
+
+        let restored =
+            disable_raw_mode().and_then(|()| execute!(io::stdout(), LeaveAlternateScreen));
+        result.and(restored)
+    }
+
+    async fn draw_loop(&mut self, cancel: CancellationToken) -> Result<(), io::Error> {
         let mut stdout = io::stdout();
         execute!(stdout, EnterAlternateScreen)?;
 
@@ -72,47 +152,60 @@ impl TransferUI {
 
         loop {
             let progress = *self.progress.borrow();
-            let status_msg = self.status_message.borrow().clone();
+            let status = self.status.borrow().clone();
+            if let Some(qr_code) = &status.qr_code {
+                self.qr_code = qr_code.clone();
+            }
+            let pending = self.approvals.as_ref().and_then(Approvals::next_pending);
 
             terminal.draw(|f| {
-                self.render_layout(f, progress, status_msg.as_deref());
+                self.render_layout(f, progress, &status);
+                if let Some(request) = &pending {
+                    self.render_approval_popup(f, request);
+                }
             })?;
 
             // Check for keypresses
             if event::poll(Duration::from_millis(100))? {
                 if let Event::Key(key) = event::read()? {
-                    match key.code {
-                        KeyCode::Char('q') | KeyCode::Char('c') | KeyCode::Esc => break,
+                    match (key.code, &pending, &self.approvals) {
+                        (KeyCode::Char('a'), Some(request), Some(approvals)) => {
+                            approvals.decide(&request.client_id, true)
+                        }
+                        (KeyCode::Char('r'), Some(request), Some(approvals)) => {
+                            approvals.decide(&request.client_id, false)
+                        }
+                        (KeyCode::Char('q') | KeyCode::Char('c') | KeyCode::Esc, _, _) => break,
                         _ => {}
                     }
                 }
             }
 
-            if progress >= 100.0 {
+            // The loop never yields, so cancellation has to be checked here
+            if progress >= 100.0 || cancel.is_cancelled() {
                 break;
             }
+            // Tasks woken from this loop (approvals) are queued on this worker
+            tokio::task::yield_now().await;
         }
 
-        disable_raw_mode()?;
-        execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
-
         Ok(())
     }
 
     // Render layout with correct format for size of terminal
-    fn render_layout(&self, f: &mut Frame, progress: f64, status_msg: Option<&str>) {
+    fn render_layout(&self, f: &mut Frame, progress: f64, status: &SessionStatus) {
         let config = LayoutConfig::size(f.size().width);
 
-        let main_areas = self.split_for_status(f.size(), status_msg.is_some());
+        let main_areas = self.split_for_status(f.size(), status.message.is_some());
         let content_area = main_areas[0];
 
         if config.horizontal {
-            self.render_horizontal(f, content_area, progress, &config);
+            self.render_horizontal(f, content_area, progress, status, &config);
         } else {
-            self.render_veritcal(f, content_area, progress, &config);
+            self.render_veritcal(f, content_area, progress, status, &config);
         }
 
-        if let Some(msg) = status_msg {
+        if let Some(msg) = &status.message {
             self.render_status_widget(f, msg, main_areas[1]);
         }
     }
@@ -129,7 +222,15 @@ impl TransferUI {
         }
     }
 
-    fn render_horizontal(&self, f: &mut Frame, area: Rect, progress: f64, config: &LayoutConfig) {
+    fn render_horizontal(
+        &self,
+        f: &mut Frame,
+        area: Rect,
+        progress: f64,
+        status: &SessionStatus,
+        config: &LayoutConfig,
+    ) {
+        let recipients = status.recipients.as_ref();
         let sides = Layout::default()
             .direction(Direction::Horizontal)
             .margin(2)
@@ -145,37 +246,61 @@ impl TransferUI {
             .direction(Direction::Vertical)
             .margin(1)
             .constraints(vec![
-                Constraint::Length(3), // File
-                Constraint::Length(3), // Progress
-                Constraint::Min(5),
+                Constraint::Length(3),                             // File
+                Constraint::Length(3),                             // Progress
+                Constraint::Length(recipients_height(recipients)), // Recipients
+                Constraint::Length(self.passphrase_height()),      // Passphrase
+                Constraint::Length(blocked_height(status)),        // Blocked
+                Constraint::Min(5),                                // Fingerprint
             ])
             .split(left[1]);
 
         self.render_logo(f, config.logo, left[0]);
         self.render_file_widget(f, info[0]);
-        self.render_progress_widget(f, progress, info[1]);
+        self.render_progress_widget(f, progress, status.expires_at, info[1]);
+        self.render_recipients_widget(f, recipients, info[2]);
+        self.render_passphrase_widget(f, info[3]);
+        self.render_blocked_widget(f, status.blocked.as_ref(), info[4]);
+        self.render_fingerprint_widget(f, info[5]);
         self.render_qr_widget(f, sides[1]);
     }
 
-    fn render_veritcal(&self, f: &mut Frame, area: Rect, progress: f64, config: &LayoutConfig) {
+    fn render_veritcal(
+        &self,
+        f: &mut Frame,
+        area: Rect,
+        progress: f64,
+        status: &SessionStatus,
+        config: &LayoutConfig,
+    ) {
+        let recipients = status.recipients.as_ref();
         let logo_height = if config.logo == ONE_LINE_LOGO { 10 } else { 4 };
+        let fingerprint_height = if self.fingerprint.is_some() { 5 } else { 0 };
 
         // Full vertical stack - single column
         let chunks = Layout::default()
             .direction(Direction::Vertical)
             .margin(1)
             .constraints(vec![
-                Constraint::Length(logo_height), // logo
-                Constraint::Length(3),           // File
-                Constraint::Length(3),           // Progress
-                Constraint::Min(15),             // QR
+                Constraint::Length(logo_height),                   // logo
+                Constraint::Length(3),                             // File
+                Constraint::Length(3),                             // Progress
+                Constraint::Length(recipients_height(recipients)), // Recipients
+                Constraint::Length(self.passphrase_height()),      // Passphrase
+                Constraint::Length(blocked_height(status)),        // Blocked
+                Constraint::Length(fingerprint_height),            // Fingerprint
+                Constraint::Min(15),                               // QR
             ])
             .split(area);
 
         self.render_logo(f, config.logo, chunks[0]);
         self.render_file_widget(f, chunks[1]);
-        self.render_progress_widget(f, progress, chunks[2]);
-        self.render_qr_widget(f, chunks[3]);
+        self.render_progress_widget(f, progress, status.expires_at, chunks[2]);
+        self.render_recipients_widget(f, recipients, chunks[3]);
+        self.render_passphrase_widget(f, chunks[4]);
+        self.render_blocked_widget(f, status.blocked.as_ref(), chunks[5]);
+        self.render_fingerprint_widget(f, chunks[6]);
+        self.render_qr_widget(f, chunks[7]);
     }
 
     fn render_logo(&self, f: &mut Frame, logo: &str, area: Rect) {
@@ -205,14 +330,131 @@ impl TransferUI {
         f.render_widget(widget, area);
     }
 
-    fn render_progress_widget(&self, f: &mut Frame, progress: f64, area: ratatui::layout::Rect) {
+    fn render_progress_widget(
+        &self,
+        f: &mut Frame,
+        progress: f64,
+        expires_at: Option<Instant>,
+        area: ratatui::layout::Rect,
+    ) {
+        let title = match expires_at {
+            Some(deadline) => format!(
+                "Progress - expires in {}",
+                format_remaining(deadline.saturating_duration_since(Instant::now()))
+            ),
+            None => "Progress".to_string(),
+        };
         let widget = Gauge::default()
-            .block(Block::default().title("Progress").borders(Borders::ALL))
+            .block(Block::default().title(title).borders(Borders::ALL))
             .gauge_style(ratatui::style::Style::default().fg(ratatui::style::Color::Green))
             .percent(progress as u16);
         f.render_widget(widget, area);
     }
 
+    // One row per client, short id and how far along it is
+    fn render_recipients_widget(
+        &self,
+        f: &mut Frame,
+        recipients: Option<&RecipientsStatus>,
+        area: ratatui::layout::Rect,
+    ) {
+        let Some(recipients) = recipients else {
+            return;
+        };
+
+        let done = recipients.clients.iter().filter(|c| c.done).count();
+        let title = match recipients.limit {
+            Some(limit) => format!("Recipients ({}/{} done)", done, limit),
+            None => format!("Recipients ({} done)", done),
+        };
+
+        let mut lines: Vec<String> = recipients
+            .clients
+            .iter()
+            .take(MAX_RECIPIENT_ROWS)
+            .map(|client| {
+                let id: String = client.client_id.chars().take(8).collect();
+                if client.done {
+                    format!("{}  done", id)
+                } else {
+                    format!("{}  {:>3.0}%", id, client.percent)
+                }
+            })
+            .collect();
+        if recipients.clients.is_empty() {
+            lines.push("Waiting for downloads...".to_string());
+        } else if recipients.clients.len() > MAX_RECIPIENT_ROWS {
+            lines.push(format!(
+                "+{} more",
+                recipients.clients.len() - MAX_RECIPIENT_ROWS
+            ));
+        }
+
+        let widget = Paragraph::new(lines.join("\n"))
+            .block(Block::default().title(title).borders(Borders::ALL));
+        f.render_widget(widget, area);
+    }
+
+    fn passphrase_height(&self) -> u16 {
+        if self.passphrase.is_some() || self.code.is_some() {
+            3
+        } else {
+            0
+        }
+    }
+
+    // Not part of the QR code, has to be shared some other way
+    fn render_passphrase_widget(&self, f: &mut Frame, area: ratatui::layout::Rect) {
+        let (title, secret) = match (&self.code, &self.passphrase) {
+            (Some(code), _) => ("Code", code),
+            (None, Some(passphrase)) => ("Passphrase", passphrase),
+            (None, None) => return,
+        };
+        let widget = Paragraph::new(secret.clone())
+            .block(Block::default().title(title).borders(Borders::ALL));
+        f.render_widget(widget, area);
+    }
+
+    // Someone is hammering the token routes, worth knowing about
+    fn render_blocked_widget(
+        &self,
+        f: &mut Frame,
+        blocked: Option<&BlockedStats>,
+        area: ratatui::layout::Rect,
+    ) {
+        use ratatui::style::{Color, Style};
+
+        let Some(blocked) = blocked else {
+            return;
+        };
+        let mut text = format!("{} request(s)", blocked.requests);
+        if blocked.banned_ips > 0 {
+            text.push_str(&format!(", {} IP(s) banned", blocked.banned_ips));
+        }
+        if let Some(ip) = blocked.last_ip {
+            text.push_str(&format!(", last {}", ip));
+        }
+        let widget = Paragraph::new(text)
+            .style(Style::default().fg(Color::Red))
+            .block(Block::default().title("Blocked").borders(Borders::ALL));
+        f.render_widget(widget, area);
+    }
+
+    // Compare with the page and the browser's certificate details
+    fn render_fingerprint_widget(&self, f: &mut Frame, area: ratatui::layout::Rect) {
+        let Some(fingerprint) = &self.fingerprint else {
+            return;
+        };
+        let widget = Paragraph::new(fingerprint.clone())
+            .block(
+                Block::default()
+                    .title("Certificate SHA-256")
+                    .borders(Borders::ALL),
+            )
+            .wrap(Wrap { trim: true });
+        f.render_widget(widget, area);
+    }
+
     fn render_qr_widget(&self, f: &mut Frame, area: ratatui::layout::Rect) {
         let widget = Paragraph::new(self.qr_code.clone())
             .block(Block::default().title("Scan").borders(Borders::ALL))
@@ -220,6 +462,38 @@ impl TransferUI {
         f.render_widget(widget, area);
     }
 
+    // Drawn over everything until answered
+    fn render_approval_popup(&self, f: &mut Frame, request: &ClaimRequest) {
+        use ratatui::style::{Color, Modifier, Style};
+
+        let area = centered_rect(60, 9, f.size());
+        let ip = request
+            .ip
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        let text = format!(
+            "IP: {}\nBrowser: {}\nClient: {}\n\n[a] accept   [r] reject",
+            ip,
+            request.user_agent.as_deref().unwrap_or("unknown"),
+            request.client_id
+        );
+
+        let widget = Paragraph::new(text)
+            .block(
+                Block::default()
+                    .title("New claim request")
+                    .borders(Borders::ALL)
+                    .border_style(
+                        Style::default()
+                            .fg(Color::Yellow)
+                            .add_modifier(Modifier::BOLD),
+                    ),
+            )
+            .wrap(Wrap { trim: true });
+        f.render_widget(Clear, area);
+        f.render_widget(widget, area);
+    }
+
     fn render_status_widget(&self, f: &mut Frame, msg: &str, area: ratatui::layout::Rect) {
         use ratatui::style::{Color, Modifier, Style};
         let widget = Paragraph::new(msg)
@@ -234,6 +508,43 @@ impl TransferUI {
     }
 }
 
+// 1:02:03 or 02:03
+fn format_remaining(remaining: Duration) -> String {
+    let secs = remaining.as_secs();
+    match secs / 3600 {
+        0 => format!("{:02}:{:02}", secs / 60, secs % 60),
+        hours => format!("{}:{:02}:{:02}", hours, (secs % 3600) / 60, secs % 60),
+    }
+}
+
+// Percent of the width, fixed height, clamped to the screen
+fn centered_rect(percent_x: u16, height: u16, area: Rect) -> Rect {
+    let width = (area.width * percent_x / 100).max(40).min(area.width);
+    let height = height.min(area.height);
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+fn blocked_height(status: &SessionStatus) -> u16 {
+    if status.blocked.is_some() {
+        3
+    } else {
+        0
+    }
+}
+
+fn recipients_height(recipients: Option<&RecipientsStatus>) -> u16 {
+    match recipients {
+        // Waiting line when empty, "+N more" line on overflow, plus borders
+        Some(r) => r.clients.len().clamp(1, MAX_RECIPIENT_ROWS + 1) as u16 + 2,
+        None => 0,
+    }
+}
+
 const ONE_LINE_LOGO: &str = r#"
    _____               .__    ________                        
   /  _  \______   ____ |  |__ \______ \_______  ____ ______  
@@ -0,0 +1,37 @@
+// Key name shared with unlockWithPassphrase in shared.js
+const CODE_PASSPHRASE_KEY = 'archdrop_code_passphrase'
+
+// Same rules as TransferCode::from_str, "7 Guitar Orbit" works too
+function parseCode(input) {
+    const parts = input.toLowerCase().split(/[\s-]+/).filter(p => p)
+    const nameplate = parseInt(parts[0], 10)
+    if (!nameplate || nameplate < 1 || parts.length < 2) {
+        throw new Error('Codes look like 7-guitar-orbit')
+    }
+    return { nameplate: String(nameplate), passphrase: parts.slice(1).join('-') }
+}
+
+document.getElementById('codeForm').addEventListener('submit', async (e) => {
+    e.preventDefault()
+    const errorText = document.getElementById('codeError')
+    const button = e.target.querySelector('button')
+    errorText.textContent = ''
+    button.disabled = true
+
+    try {
+        const { nameplate, passphrase } = parseCode(document.getElementById('codeInput').value)
+
+        const response = await fetch(`/code/${nameplate}`)
+        if (!response.ok) {
+            throw new Error('No transfer with that code')
+        }
+        const { path, suite } = await response.json()
+
+        // Handed to the page we open, it runs the exchange with it
+        sessionStorage.setItem(CODE_PASSPHRASE_KEY, passphrase)
+        location.assign(`${path}#pake=1&suite=${suite}`)
+    } catch (error) {
+        errorText.textContent = error.message
+        button.disabled = false
+    }
+})
@@ -22,7 +22,6 @@ function detectBrowserCapabilities() {
 //=============
 let cachedManifest = null
 let cachedToken = null
-let cachedClientId = null
 
 // Download button
 document.addEventListener('DOMContentLoaded', async () => {
@@ -34,20 +33,48 @@ document.addEventListener('DOMContentLoaded', async () => {
     // Load manifest and display files
     try {
         cachedToken = window.location.pathname.split('/').pop()
-        cachedClientId = getClientId()
-        const manifestResponse = await fetch(`/send/${token}/manifest?clientId=${clientId}`)
+
+        // Passphrase sessions unlock the key first, then we claim with it
+        const claimResponse = await claimSession('send', cachedToken)
+        if (claimResponse.status === 410) {
+            showUnavailable()
+            return
+        }
+        if (!claimResponse.ok) {
+            throw new Error(`Failed to claim session: HTTP ${claimResponse.status}`);
+        }
+
+        const manifestResponse = await fetch(`/send/${cachedToken}/manifest`)
+        if (manifestResponse.status === 410) {
+            showUnavailable()
+            return
+        }
         if (!manifestResponse.ok) {
             throw new Error(`Failed to fetch manifest: HTTP ${manifestResponse.status}`);
         }
 
-        cachedManifest = await manifestResponse.json()
-        displayFileList(manifest.files)
+        // Names and sizes are encrypted like the files themselves
+        const { fileKeys } = await getCredentialsFromUrl()
+        const metadataCipher = await deriveMetadataCipher(fileKeys, 'send')
+        cachedManifest = await openJson(metadataCipher, (await manifestResponse.json()).manifest)
+        if ((cachedManifest.suite || 'aes-256-gcm') !== fileKeys.suite) {
+            throw new Error(`Manifest uses ${cachedManifest.suite}, the link says ${fileKeys.suite}`)
+        }
+        displayFileList(cachedManifest.files)
 
     } catch (error) {
         console.error('Failed to load file list:', error)
     }
 })
 
+// Download limit reached, nothing left to fetch
+function showUnavailable() {
+    const subtitle = document.querySelector('.subtitle')
+    if (subtitle) subtitle.textContent = 'This transfer was already downloaded and is no longer available.'
+    const downloadBtn = document.getElementById('downloadBtn')
+    if (downloadBtn) downloadBtn.hidden = true
+}
+
 // List of files to download
 function displayFileList(files) {
     const fileList = document.getElementById('fileList')
@@ -87,7 +114,7 @@ async function startDownload() {
 
     try {
         // Get session key form url
-        const { key } = await getCredentialsFromUrl()
+        const { fileKeys } = await getCredentialsFromUrl()
         const token = window.location.pathname.split('/').pop()
 
         // download files concurrently
@@ -96,7 +123,7 @@ async function startDownload() {
             async ({ file, fileItem }) => {
                 fileItem.classList.add('downloading')
                 try {
-                    await downloadFile(token, file, key, fileItem)
+                    await downloadFile(token, file, fileKeys, fileItem)
                     fileItem.classList.remove('downloading')
                     fileItem.classList.add('completed')
                 } catch (error) {
@@ -108,6 +135,7 @@ async function startDownload() {
             MAX_CONCURRENT_FILES
         )
 
+        // Counts against --max-downloads, the server may burn the files now
         await fetch(`/send/${token}/complete`, { method: 'POST' })
 
         const downloadBtn = document.getElementById('downloadBtn')
@@ -119,13 +147,14 @@ async function startDownload() {
     }
 }
 
-async function downloadFile(token, fileEntry, key, fileItem) {
+async function downloadFile(token, fileEntry, fileKeys, fileItem) {
+    const cipher = await deriveFileCipher(fileKeys, 'send', fileEntry.index)
     const nonceBase = urlSafeBase64ToUint8Array(fileEntry.nonce)
     const totalChunks = Math.ceil(fileEntry.size / CHUNK_SIZE)
     
     if (browserCaps.hasFileSystemAccess && fileEntry.size > FILE_SYSTEM_API_THRESHOLD) {
         console.log(`Using File System API for ${fileEntry.name} (${formatFileSize(fileEntry.size)})`)
-        await downloadViaFileSystemAPI(token, fileEntry, key, nonceBase, totalChunks, fileItem)
+        await downloadViaFileSystemAPI(token, fileEntry, cipher, nonceBase, totalChunks, fileItem)
     } else {
         // Check if file might be too large for available memory
         if (fileEntry.size > browserCaps.estimatedMemory * 0.5) {
@@ -133,11 +162,11 @@ async function downloadFile(token, fileEntry, key, fileItem) {
         }
         
         console.log(`Using in-memory download for ${fileEntry.name}`)
-        await downloadViaBlob(token, fileEntry, key, nonceBase, totalChunks, fileItem)
+        await downloadViaBlob(token, fileEntry, cipher, nonceBase, totalChunks, fileItem)
     }
 }
 
-async function downloadViaFileSystemAPI(token, fileEntry, key, nonceBase, totalChunks, fileItem) {
+async function downloadViaFileSystemAPI(token, fileEntry, cipher, nonceBase, totalChunks, fileItem) {
     // Prompt user to save file
     const fileHandle = await window.showSaveFilePicker({
         suggestedName: fileEntry.name,
@@ -153,15 +182,11 @@ async function downloadViaFileSystemAPI(token, fileEntry, key, nonceBase, totalC
             Array.from({ length: totalChunks }, (_, i) => i),
             async (chunkIndex) => {
                 const encrypted = await downloadChunk(token, fileEntry.index, chunkIndex)
-                const nonce = generateNonce(nonceBase, chunkIndex)
-                const decrypted = await crypto.subtle.decrypt(
-                    { name: 'AES-GCM', iv: nonce },
-                    key,
-                    encrypted
-                )
+                const last = chunkIndex === totalChunks - 1
+                const decrypted = await decryptChunk(cipher, nonceBase, encrypted, chunkIndex, last)
 
                 // Write directly to disk (not stored in memory)
-                await writable.write(new Uint8Array(decrypted))
+                await writable.write(decrypted)
 
                 completedChunks++
                 updateFileProgress(fileItem, completedChunks, totalChunks)
@@ -187,7 +212,7 @@ async function downloadViaFileSystemAPI(token, fileEntry, key, nonceBase, totalC
 }
 
 // In-memory blob path (Firefox/Safari/small files)
-async function downloadViaBlob(token, fileEntry, key, nonceBase, totalChunks, fileItem) {
+async function downloadViaBlob(token, fileEntry, cipher, nonceBase, totalChunks, fileItem) {
     const decryptedChunks = new Array(totalChunks)
     let completedChunks = 0
 
@@ -195,13 +220,8 @@ async function downloadViaBlob(token, fileEntry, key, nonceBase, totalChunks, fi
         Array.from({ length: totalChunks }, (_, i) => i),
         async (chunkIndex) => {
             const encrypted = await downloadChunk(token, fileEntry.index, chunkIndex)
-            const nonce = generateNonce(nonceBase, chunkIndex)
-            const decrypted = await crypto.subtle.decrypt(
-                { name: 'AES-GCM', iv: nonce },
-                key,
-                encrypted
-            )
-            decryptedChunks[chunkIndex] = new Uint8Array(decrypted)
+            const last = chunkIndex === totalChunks - 1
+            decryptedChunks[chunkIndex] = await decryptChunk(cipher, nonceBase, encrypted, chunkIndex, last)
             completedChunks++
             updateFileProgress(fileItem, completedChunks, totalChunks)
         },
@@ -252,8 +272,7 @@ async function verifyHash(blob, fileEntry, token) {
         .join('')
 
     // Request hash from server
-    const clientId = getClientId()
-    const response = await fetch(`/send/${token}/${fileEntry.index}/hash?clientId=${clientId}`)
+    const response = await fetch(`/send/${token}/${fileEntry.index}/hash`)
     if (!response.ok) {
         console.warn(`Could not verify ${fileEntry.name}: ${response.status}`)
         return // Skip if hash unavailable
@@ -269,12 +288,10 @@ async function verifyHash(blob, fileEntry, token) {
 }
 
 async function downloadChunk(token, fileIndex, chunkIndex, maxRetries = 3) {
-    const clientId = getClientId()
-
     return await retryWithExponentialBackoff(async () => {
-        const response = await fetch(`/send/${token}/${fileIndex}/chunk/${chunkIndex}?clientId=${clientId}`)
+        const response = await fetch(`/send/${token}/${fileIndex}/chunk/${chunkIndex}`)
         if (!response.ok) {
-            throw new Error(`HTTP ${response.status}`)
+            throw httpError(response)
         }
         return await response.arrayBuffer()
     }, maxRetries, `chunk ${chunkIndex}`)
@@ -28,13 +28,32 @@ function urlSafeBase64ToUint8Array(str) {
     return bytes
 }
 
-async function getCredentialsFromUrl() {
+// Cached, the fragment is cleared on first read
+let credentialsPromise = null
+
+function getCredentialsFromUrl() {
+    if (!credentialsPromise) {
+        credentialsPromise = readCredentials()
+        // Let a failed attempt be retried
+        credentialsPromise.catch(() => { credentialsPromise = null })
+    }
+    return credentialsPromise
+}
+
+async function readCredentials() {
     const fragment = window.location.hash.substring(1) // remove #
     const params = new URLSearchParams(fragment)
     const keyBase64 = params.get('key')
-    const nonceBase64 = params.get('nonce')
+    const pake = params.get('pake') === '1'
+    // Links without a suite predate XChaCha20, see CipherSuite in src/crypto/cipher.rs
+    const suite = params.get('suite') || 'aes-256-gcm'
 
-    if (!keyBase64 || !nonceBase64) {
+    if (!CIPHER_SUITES.includes(suite)) {
+        throw new Error(`Unknown cipher suite ${suite}`)
+    }
+
+    // Older links also carry a nonce, unused, every file has its own
+    if (!pake && !keyBase64) {
         throw new Error('Missing encryption key')
     }
 
@@ -42,20 +61,54 @@ async function getCredentialsFromUrl() {
     history.replaceState(null, document.title, location.pathname + location.search)
 
     // base64 -> string -> byte array
-    const keyData = urlSafeBase64ToUint8Array(keyBase64);
-    const nonceData = urlSafeBase64ToUint8Array(nonceBase64);
-
-    const key = await crypto.subtle.importKey(
+    const keyData = pake
+        ? await unlockWithPassphrase()
+        : urlSafeBase64ToUint8Array(keyBase64)
+
+    // Chunks are encrypted under per file keys, see deriveFileCipher
+    const fileKeys = {
+        key: await crypto.subtle.importKey('raw', keyData, 'HKDF', false, ['deriveBits']),
+        suite,
+    }
+    // Same raw key, only used to answer claim challenges
+    const claimKey = await crypto.subtle.importKey(
         'raw',
         keyData,
-        { name: 'AES-GCM' },
+        { name: 'HMAC', hash: 'SHA-256' },
         false,
-        ['encrypt', 'decrypt']
+        ['sign']
     )
 
-    return { key, nonceBase: nonceData }
+    return { fileKeys, claimKey }
 }
 
+// Passphrase sessions show the form on load, the key comes from the exchange
+document.addEventListener('DOMContentLoaded', () => {
+    const params = new URLSearchParams(window.location.hash.substring(1))
+    if (params.get('pake') === '1') {
+        getCredentialsFromUrl().catch(error => console.error(error))
+    }
+})
+
+// Certificate pin from the fragment, shown so users can compare it with
+// the browser's certificate details. Read on load, before the fragment is cleared
+function showCertFingerprint() {
+    const params = new URLSearchParams(window.location.hash.substring(1))
+    const fp = params.get('fp')
+    const container = document.getElementById('certInfo')
+    if (!fp || !container) return
+
+    // Colon separated hex, same as the terminal and browser show
+    const hex = Array.from(urlSafeBase64ToUint8Array(fp))
+        .map(b => b.toString(16).padStart(2, '0').toUpperCase())
+        .join(':')
+
+    document.getElementById('certFingerprint').textContent = hex
+    container.hidden = false
+}
+
+document.addEventListener('DOMContentLoaded', showCertFingerprint)
+
 function arrayBufferToBase64(buffer) {
     // Convert Uint8Array to base64 string
     const bytes = new Uint8Array(buffer)
@@ -72,37 +125,397 @@ function arrayBufferToBase64(buffer) {
 }
 
 //===============
-// CLIENT_ID
+// Claim
 //==============
-const CLIENT_ID_KEY = 'archdrop_client_id';
-
-function getClientId() {
-    let clientId = localStorage.getItem(CLIENT_ID_KEY);
-
-    if (!clientId) {
-        clientId = generateUuid();
-        localStorage.setItem(CLIENT_ID_KEY, clientId);
+// Prove we hold the key, the server answers with an HttpOnly cookie
+// that identifies us on every later request. Matches src/crypto/claim.rs
+async function claimSession(service, token) {
+    const { claimKey } = await getCredentialsFromUrl()
+
+    const challengeResponse = await fetch(`/${service}/${token}/challenge`, { method: 'POST' })
+    if (!challengeResponse.ok) {
+        return challengeResponse
     }
-    return clientId;
+    const { challenge } = await challengeResponse.json()
+    const challengeData = urlSafeBase64ToUint8Array(challenge)
+
+    // label || u64 length || token || challenge
+    const tokenData = textEncoder.encode(token)
+    const proof = await crypto.subtle.sign('HMAC', claimKey, concatArrays(
+        textEncoder.encode('archdrop claim'),
+        lengthPrefix(tokenData.length),
+        tokenData,
+        challengeData,
+    ))
+
+    return fetch(`/${service}/${token}/claim`, {
+        method: 'POST',
+        headers: { 'Content-Type': 'application/json' },
+        body: JSON.stringify({ challenge, proof: arrayBufferToBase64(proof) })
+    })
 }
 
 //==============
 // Crypto
 //==============
-// Construct nonce to match Rusts EncryptorBE32
-// [7 byte base][4 byte counter][1 byte last flag]
-function generateNonce(nonceBase64, counter) {
-    const nonce = new Uint8Array(12)
-    nonce.set(nonceBase64,  0) // first 7 bytes
+const CIPHER_SUITES = ['aes-256-gcm', 'xchacha20-poly1305']
+
+// HKDF-SHA256 from the session key, matches src/crypto/kdf.rs
+// info is label || u64 length || part for every part
+async function deriveKey(fileKeys, label, parts) {
+    const info = [textEncoder.encode(label)]
+    for (const part of parts) {
+        const data = textEncoder.encode(String(part))
+        info.push(lengthPrefix(data.length), data)
+    }
+
+    return new Uint8Array(await crypto.subtle.deriveBits(
+        { name: 'HKDF', hash: 'SHA-256', salt: new Uint8Array(0), info: concatArrays(...info) },
+        fileKeys.key,
+        256
+    ))
+}
 
+// Both suites share the derived key and the cipher's shape, see crypto::Cipher
+async function deriveCipher(fileKeys, label, parts) {
+    const keyData = await deriveKey(fileKeys, label, parts)
+
+    return suiteCipher(fileKeys.suite, keyData)
+}
+
+// nonceLength and prefixLength match CipherSuite::nonce_len and nonce_prefix_len
+async function suiteCipher(suite, keyData) {
+    if (suite === 'xchacha20-poly1305') {
+        return {
+            nonceLength: 24,
+            prefixLength: 0,
+            encrypt: async (nonce, data, aad = new Uint8Array(0)) =>
+                xchachaSeal(keyData, nonce, new Uint8Array(data), aad),
+            decrypt: async (nonce, data, aad = new Uint8Array(0)) =>
+                xchachaOpen(keyData, nonce, new Uint8Array(data), aad),
+        }
+    }
+
+    const key = await crypto.subtle.importKey('raw', keyData, 'AES-GCM', false, ['encrypt', 'decrypt'])
+    const params = (nonce, aad = new Uint8Array(0)) => ({ name: 'AES-GCM', iv: nonce, additionalData: aad })
+    return {
+        nonceLength: 12,
+        prefixLength: 7,
+        encrypt: async (nonce, data, aad) =>
+            new Uint8Array(await crypto.subtle.encrypt(params(nonce, aad), key, data)),
+        decrypt: async (nonce, data, aad) =>
+            new Uint8Array(await crypto.subtle.decrypt(params(nonce, aad), key, data)),
+    }
+}
+
+const FILE_KEY_LABEL = 'archdrop file key'
+const METADATA_KEY_LABEL = 'archdrop metadata key'
+
+// Cipher for one file
+// fileId is the manifest index when sending, the relative path when receiving
+function deriveFileCipher(fileKeys, direction, fileId) {
+    return deriveCipher(fileKeys, FILE_KEY_LABEL, [direction, fileId])
+}
+
+// Cipher for file names, paths and sizes
+function deriveMetadataCipher(fileKeys, direction) {
+    return deriveCipher(fileKeys, METADATA_KEY_LABEL, [direction])
+}
+
+// nonce || ciphertext, matches crypto::seal and crypto::open
+async function sealJson(cipher, value) {
+    const nonce = crypto.getRandomValues(new Uint8Array(cipher.nonceLength))
+    const ciphertext = await cipher.encrypt(nonce, textEncoder.encode(JSON.stringify(value)))
+    return arrayBufferToBase64(concatArrays(nonce, ciphertext))
+}
+
+async function openJson(cipher, sealedBase64) {
+    const sealed = urlSafeBase64ToUint8Array(sealedBase64)
+    const plaintext = await cipher.decrypt(
+        sealed.slice(0, cipher.nonceLength),
+        sealed.slice(cipher.nonceLength)
+    )
+    return JSON.parse(new TextDecoder().decode(plaintext))
+}
+
+// Random part of a file's chunk nonces, 7 bytes for AES-GCM and none for
+// XChaCha20, whose chunks each draw a whole nonce
+function generateNonceBase(cipher) {
+    return crypto.getRandomValues(new Uint8Array(cipher.prefixLength))
+}
+
+// Construct nonce to match Rusts EncryptorBE32
+// [random base][4 byte counter][1 byte last flag]
+// The flag is only set on a file's final chunk, a truncated file fails to decrypt
+// XChaCha20 only takes the position, as associated data, see encryptChunk
+function generateNonce(nonceBase, counter, last) {
+    const nonce = new Uint8Array(nonceBase.length + 5)
+    nonce.set(nonceBase, 0)
 
     // last 5 bytes (4 + last flag)
     const view = new DataView(nonce.buffer)
-    view.setUint32(7, counter, false) // false = BE32
+    view.setUint32(nonceBase.length, counter, false) // false = BE32
+    nonce[nonceBase.length + 4] = last ? 1 : 0
 
     return nonce
 }
 
+// Matches crypto::encrypt_chunk_at_position, XChaCha20 chunks are nonce || ciphertext
+async function encryptChunk(cipher, nonceBase, data, counter, last) {
+    const position = generateNonce(nonceBase, counter, last)
+    if (cipher.prefixLength) {
+        return cipher.encrypt(position, data)
+    }
+    const nonce = crypto.getRandomValues(new Uint8Array(cipher.nonceLength))
+    return concatArrays(nonce, await cipher.encrypt(nonce, data, position))
+}
+
+async function decryptChunk(cipher, nonceBase, data, counter, last) {
+    const position = generateNonce(nonceBase, counter, last)
+    if (cipher.prefixLength) {
+        return cipher.decrypt(position, data)
+    }
+    const encrypted = new Uint8Array(data)
+    if (encrypted.length < cipher.nonceLength) {
+        throw new Error('Decryption failed')
+    }
+    return cipher.decrypt(
+        encrypted.subarray(0, cipher.nonceLength),
+        encrypted.subarray(cipher.nonceLength),
+        position
+    )
+}
+
+//=============================================
+// XChaCha20-Poly1305, same as the chacha20poly1305 crate on the Rust side
+//=============================================
+// WebCrypto has no ChaCha. ChaCha20-Poly1305 as in RFC 8439, XChaCha20 runs
+// it under an HChaCha20 subkey (draft-irtf-cfrg-xchacha). Both are checked
+// against the published vectors in tests/vectors/aead.json
+
+function chachaRounds(s) {
+    const quarterRound = (a, b, c, d) => {
+        s[a] += s[b]; s[d] ^= s[a]; s[d] = (s[d] << 16) | (s[d] >>> 16)
+        s[c] += s[d]; s[b] ^= s[c]; s[b] = (s[b] << 12) | (s[b] >>> 20)
+        s[a] += s[b]; s[d] ^= s[a]; s[d] = (s[d] << 8) | (s[d] >>> 24)
+        s[c] += s[d]; s[b] ^= s[c]; s[b] = (s[b] << 7) | (s[b] >>> 25)
+    }
+    for (let i = 0; i < 10; i++) {
+        quarterRound(0, 4, 8, 12)
+        quarterRound(1, 5, 9, 13)
+        quarterRound(2, 6, 10, 14)
+        quarterRound(3, 7, 11, 15)
+        quarterRound(0, 5, 10, 15)
+        quarterRound(1, 6, 11, 12)
+        quarterRound(2, 7, 8, 13)
+        quarterRound(3, 4, 9, 14)
+    }
+}
+
+// "expand 32-byte k" || key || 16 byte input
+function chachaState(key, input) {
+    const state = new Uint32Array(16)
+    state.set([0x61707865, 0x3320646e, 0x79622d32, 0x6b206574])
+    const keyView = new DataView(key.buffer, key.byteOffset, 32)
+    const inputView = new DataView(input.buffer, input.byteOffset, 16)
+    for (let i = 0; i < 8; i++) state[4 + i] = keyView.getUint32(i * 4, true)
+    for (let i = 0; i < 4; i++) state[12 + i] = inputView.getUint32(i * 4, true)
+    return state
+}
+
+function hchacha20(key, nonce) {
+    const state = chachaState(key, nonce)
+    chachaRounds(state)
+
+    const subkey = new Uint8Array(32)
+    const view = new DataView(subkey.buffer)
+    for (let i = 0; i < 4; i++) {
+        view.setUint32(i * 4, state[i], true)
+        view.setUint32(16 + i * 4, state[12 + i], true)
+    }
+    return subkey
+}
+
+// IETF ChaCha20 with a 12 byte nonce, returns block(counter)
+function chachaBlocks(key, nonce) {
+    const input = new Uint8Array(16)
+    input.set(nonce, 4)
+    const initial = chachaState(key, input)
+    const working = new Uint32Array(16)
+    const block = new Uint8Array(64)
+    const view = new DataView(block.buffer)
+
+    return (counter) => {
+        initial[12] = counter
+        working.set(initial)
+        chachaRounds(working)
+        for (let i = 0; i < 16; i++) view.setUint32(i * 4, working[i] + initial[i], true)
+        return block
+    }
+}
+
+// Block 0 is the Poly1305 key, data starts at block 1
+function chachaXor(blocks, data) {
+    const output = new Uint8Array(data.length)
+    for (let offset = 0, counter = 1; offset < data.length; offset += 64, counter++) {
+        const keystream = blocks(counter)
+        const end = Math.min(64, data.length - offset)
+        for (let i = 0; i < end; i++) output[offset + i] = data[offset + i] ^ keystream[i]
+    }
+    return output
+}
+
+// Poly1305 with 13 bit limbs so every product fits in 32 bits (poly1305-donna-16)
+function poly1305(key, data) {
+    const r = new Uint16Array(10)
+    const h = new Uint16Array(10)
+    const d = new Uint32Array(10)
+    const t = new Uint16Array(8)
+
+    const limbs = (bytes, offset, out, masks, hibit) => {
+        for (let i = 0; i < 8; i++) t[i] = bytes[offset + 2 * i] | (bytes[offset + 2 * i + 1] << 8)
+        out[0] = t[0] & masks[0]
+        out[1] = ((t[0] >>> 13) | (t[1] << 3)) & masks[1]
+        out[2] = ((t[1] >>> 10) | (t[2] << 6)) & masks[2]
+        out[3] = ((t[2] >>> 7) | (t[3] << 9)) & masks[3]
+        out[4] = ((t[3] >>> 4) | (t[4] << 12)) & masks[4]
+        out[5] = (t[4] >>> 1) & masks[5]
+        out[6] = ((t[4] >>> 14) | (t[5] << 2)) & masks[6]
+        out[7] = ((t[5] >>> 11) | (t[6] << 5)) & masks[7]
+        out[8] = ((t[6] >>> 8) | (t[7] << 8)) & masks[8]
+        out[9] = ((t[7] >>> 5) & masks[9]) | hibit
+    }
+
+    // Clamped r
+    limbs(key, 0, r, [0x1fff, 0x1fff, 0x1f03, 0x1fff, 0x00ff, 0x1ffe, 0x1fff, 0x1f81, 0x1fff, 0x007f], 0)
+
+    // Every block is a full 16 bytes, the AEAD zero pads its inputs
+    const m = new Uint16Array(10)
+    const fullLimb = new Array(10).fill(0x1fff)
+    for (let offset = 0; offset < data.length; offset += 16) {
+        limbs(data, offset, m, fullLimb, 1 << 11)
+        for (let i = 0; i < 10; i++) h[i] += m[i]
+
+        let c = 0
+        for (let i = 0; i < 10; i++) {
+            d[i] = c
+            for (let j = 0; j < 10; j++) {
+                d[i] += h[j] * (j <= i ? r[i - j] : 5 * r[i + 10 - j])
+                if (j === 4) {
+                    c = d[i] >>> 13
+                    d[i] &= 0x1fff
+                }
+            }
+            c += d[i] >>> 13
+            d[i] &= 0x1fff
+        }
+        c = c * 5 + d[0]
+        d[0] = c & 0x1fff
+        d[1] += c >>> 13
+        h.set(d)
+    }
+
+    // Full carry, then subtract p when h >= p
+    let c = h[1] >>> 13
+    h[1] &= 0x1fff
+    for (let i = 2; i < 10; i++) {
+        h[i] += c
+        c = h[i] >>> 13
+        h[i] &= 0x1fff
+    }
+    h[0] += c * 5
+    c = h[0] >>> 13
+    h[0] &= 0x1fff
+    h[1] += c
+    c = h[1] >>> 13
+    h[1] &= 0x1fff
+    h[2] += c
+
+    const g = new Uint16Array(10)
+    g[0] = h[0] + 5
+    c = g[0] >>> 13
+    g[0] &= 0x1fff
+    for (let i = 1; i < 10; i++) {
+        g[i] = h[i] + c
+        c = g[i] >>> 13
+        g[i] &= 0x1fff
+    }
+    // c is 1 when h + 5 carried past 2^130
+    const select = c ? 0xffff : 0
+    for (let i = 0; i < 10; i++) h[i] = (h[i] & ~select) | (g[i] & select)
+
+    // Back to 8 x 16 bits, then add the pad mod 2^128
+    const words = [
+        h[0] | (h[1] << 13),
+        (h[1] >>> 3) | (h[2] << 10),
+        (h[2] >>> 6) | (h[3] << 7),
+        (h[3] >>> 9) | (h[4] << 4),
+        (h[4] >>> 12) | (h[5] << 1) | (h[6] << 14),
+        (h[6] >>> 2) | (h[7] << 11),
+        (h[7] >>> 5) | (h[8] << 8),
+        (h[8] >>> 8) | (h[9] << 5),
+    ]
+    const tag = new Uint8Array(16)
+    let f = 0
+    for (let i = 0; i < 8; i++) {
+        f = (words[i] & 0xffff) + (key[16 + 2 * i] | (key[17 + 2 * i] << 8)) + (f >>> 16)
+        tag[2 * i] = f & 0xff
+        tag[2 * i + 1] = (f >>> 8) & 0xff
+    }
+    return tag
+}
+
+// aad || pad || ciphertext || pad || u64 le lengths of both
+function chachaTag(blocks, aad, ciphertext) {
+    const aadPadded = Math.ceil(aad.length / 16) * 16
+    const padded = aadPadded + Math.ceil(ciphertext.length / 16) * 16
+    const macData = new Uint8Array(padded + 16)
+    macData.set(aad, 0)
+    macData.set(ciphertext, aadPadded)
+    const view = new DataView(macData.buffer)
+    view.setBigUint64(padded, BigInt(aad.length), true)
+    view.setBigUint64(padded + 8, BigInt(ciphertext.length), true)
+    return poly1305(blocks(0).slice(0, 32), macData)
+}
+
+// Returns ciphertext || 16 byte tag
+function chachaSeal(key, nonce, plaintext, aad) {
+    const blocks = chachaBlocks(key, nonce)
+    const ciphertext = chachaXor(blocks, plaintext)
+    return concatArrays(ciphertext, chachaTag(blocks, aad, ciphertext))
+}
+
+function chachaOpen(key, nonce, sealed, aad) {
+    if (sealed.length < 16) {
+        throw new Error('Decryption failed')
+    }
+    const blocks = chachaBlocks(key, nonce)
+    const ciphertext = sealed.subarray(0, sealed.length - 16)
+    const expected = chachaTag(blocks, aad, ciphertext)
+
+    let diff = 0
+    for (let i = 0; i < 16; i++) diff |= expected[i] ^ sealed[sealed.length - 16 + i]
+    if (diff !== 0) {
+        throw new Error('Decryption failed')
+    }
+    return chachaXor(blocks, ciphertext)
+}
+
+// HChaCha20 subkey from the first 16 nonce bytes, the last 8 go after 4 zero bytes
+function xchachaSubkey(key, nonce) {
+    const chachaNonce = new Uint8Array(12)
+    chachaNonce.set(nonce.subarray(16, 24), 4)
+    return [hchacha20(key, nonce.subarray(0, 16)), chachaNonce]
+}
+
+function xchachaSeal(key, nonce, plaintext, aad) {
+    return chachaSeal(...xchachaSubkey(key, nonce), plaintext, aad)
+}
+
+function xchachaOpen(key, nonce, sealed, aad) {
+    return chachaOpen(...xchachaSubkey(key, nonce), sealed, aad)
+}
+
 function generateUuid() {
     return 'xxxxxxxx-xxxx-4xxx-yxxx-xxxxxxxxxxxx'.replace(/[xy]/g, function(c) {
         var r = Math.random() * 16 | 0,
@@ -232,13 +645,19 @@ function formatFileSize(bytes) {
 // Retry Helper
 //================
 
+// Error for a failed response, carries the status for retryWithExponentialBackoff
+function httpError(response) {
+    return Object.assign(new Error(`HTTP ${response.status}`), { status: response.status })
+}
+
 // Retry an async function with exponential backoff
 async function retryWithExponentialBackoff(asyncFn, maxRetries = 3, context = '') {
     for (let attempt = 0; attempt < maxRetries; attempt++) {
         try {
             return await asyncFn()
         } catch (e) {
-            if (attempt === maxRetries - 1) {
+            // Refusals (403, 404, 410, 429) would only be refused again
+            if (attempt === maxRetries - 1 || e.status < 500) {
                 throw e
             }
             // Exponential backoff: 1s, 2s, 4s
@@ -270,3 +689,303 @@ async function runWithConcurrency(items, asyncFn, concurrency) {
 
     return Promise.all(results)
 }
+
+//=============================================
+// Passphrase exchange, mirrors src/crypto/pake.rs
+//=============================================
+// SPAKE2 over Ed25519, the construction of the spake2 crate and python-spake2
+// Field elements and scalars are BigInt, points are extended coordinates [X, Y, Z, T]
+const ED_P = 2n ** 255n - 19n
+const ED_L = 2n ** 252n + 27742317777372353535851937790883648493n
+const ED_D = 37095705934669439343138083508754565189542113879843219016388785533085940283555n
+const ED_SQRT_M1 = 19681161376707505956807079304988542015446066515923890162744021073123829784752n
+const ED_BASE = '5866666666666666666666666666666666666666666666666666666666666666'
+// Blinding points from the spake2 crate, side A blinds with M
+const SPAKE2_M = '15cfd18e385952982b6a8f8c7854963b58e34388c8e6dae891db756481a02312'
+const SPAKE2_N = 'f04f2e7eb734b2a8f8b472eaf9c3c632576ac64aea650b496a8a20ff00e583c3'
+const PAKE_CLIENT_IDENTITY = 'archdrop client'
+const textEncoder = new TextEncoder()
+
+// Resolves with the raw session key once the server accepts the passphrase
+function unlockWithPassphrase() {
+    const form = document.getElementById('passphraseForm')
+    const input = document.getElementById('passphraseInput')
+    const errorText = document.getElementById('passphraseError')
+    const token = window.location.pathname.split('/').pop()
+    const service = window.location.pathname.split('/')[1]
+
+    // Coming from the code page, try the code before asking
+    const fromCode = sessionStorage.getItem('archdrop_code_passphrase')
+    sessionStorage.removeItem('archdrop_code_passphrase')
+
+    return new Promise(resolve => {
+        const attempt = async (passphrase) => {
+            const button = form.querySelector('button')
+            button.disabled = true
+            errorText.textContent = ''
+
+            try {
+                const key = await runPake(service, token, normalizePassphrase(passphrase, token))
+                form.hidden = true
+                resolve(key)
+            } catch (error) {
+                console.error(error)
+                form.hidden = false
+                errorText.textContent = error.message
+                button.disabled = false
+                input.focus()
+            }
+        }
+
+        form.addEventListener('submit', (e) => {
+            e.preventDefault()
+            attempt(input.value)
+        })
+
+        if (fromCode) {
+            attempt(fromCode)
+        } else {
+            form.hidden = false
+            input.focus()
+        }
+    })
+}
+
+// Code sessions (numeric token) take the words in any case and spacing, with
+// or without the nameplate in front, "7 Guitar Orbit" works on session 7.
+// Other passphrases are used as is. Mirrors TransferCode::normalize_passphrase
+function normalizePassphrase(passphrase, token) {
+    if (!/^\d+$/.test(token)) {
+        return passphrase
+    }
+    const parts = passphrase.toLowerCase().split(/[\s-]+/).filter(p => p)
+    if (parts.length && /^\d+$/.test(parts[0]) && parseInt(parts[0], 10) === parseInt(token, 10)) {
+        parts.shift()
+    }
+    return parts.join('-')
+}
+
+async function runPake(service, token, passphrase) {
+    const spake = await spake2Start(
+        textEncoder.encode(passphrase.trim()),
+        textEncoder.encode(PAKE_CLIENT_IDENTITY),
+        textEncoder.encode(token)
+    )
+
+    const start = await fetch(`/${service}/${token}/pake`, {
+        method: 'POST',
+        headers: { 'Content-Type': 'application/json' },
+        body: JSON.stringify({ message: arrayBufferToBase64(spake.message) })
+    })
+    if (!start.ok) {
+        throw new Error('Passphrase exchange failed, the link may have expired')
+    }
+    // The server picks the exchange id, the confirmation must quote it
+    const { exchangeId, message } = await start.json()
+    const key = await spake.finish(urlSafeBase64ToUint8Array(message))
+    const exchangeData = textEncoder.encode(exchangeId)
+    const transcript = await sha256(concatArrays(
+        lengthPrefix(key.length),
+        key,
+        lengthPrefix(exchangeData.length),
+        exchangeData
+    ))
+
+    const confirmation = await hmac(transcript, 'archdrop client confirm')
+    const confirm = await fetch(`/${service}/${token}/pake/confirm`, {
+        method: 'POST',
+        headers: { 'Content-Type': 'application/json' },
+        body: JSON.stringify({ exchangeId, confirmation: arrayBufferToBase64(confirmation) })
+    })
+    if (!confirm.ok) {
+        throw new Error('Wrong passphrase, or too many attempts from this network')
+    }
+
+    // Only the right passphrase decrypts, this also authenticates the server
+    const wrapped = urlSafeBase64ToUint8Array((await confirm.json()).key)
+    const wrapKey = await crypto.subtle.importKey(
+        'raw',
+        await hmac(transcript, 'archdrop wrap key'),
+        { name: 'AES-GCM' },
+        false,
+        ['decrypt']
+    )
+    try {
+        return new Uint8Array(await crypto.subtle.decrypt(
+            { name: 'AES-GCM', iv: wrapped.slice(0, 12) },
+            wrapKey,
+            wrapped.slice(12)
+        ))
+    } catch {
+        throw new Error('Server failed to prove the passphrase')
+    }
+}
+
+// Side A of SPAKE2, X = x*B + w*M and K = x*(Y - w*N). `scalar` is x, only
+// the tests pick it. Returns our message and finish(serverMessage) -> key
+async function spake2Start(password, idA, idB, scalar = randomScalar()) {
+    const w = await passwordScalar(password)
+    const x = edAdd(edMultiply(edDecode(hexBytes(ED_BASE)), scalar), edMultiply(edDecode(hexBytes(SPAKE2_M)), w))
+    const xBytes = edEncode(x)
+
+    return {
+        message: concatArrays(new Uint8Array([0x41]), xBytes),
+        finish: async (serverMessage) => {
+            if (serverMessage.length !== 33 || serverMessage[0] !== 0x42) {
+                throw new Error('Invalid passphrase exchange response')
+            }
+            const yBytes = serverMessage.slice(1)
+            const unblind = edNegate(edMultiply(edDecode(hexBytes(SPAKE2_N)), w))
+            const k = edMultiply(edAdd(edDecode(yBytes), unblind), scalar)
+
+            // H(H(pw) || H(idA) || H(idB) || X || Y || K)
+            return sha256(concatArrays(
+                await sha256(password),
+                await sha256(idA),
+                await sha256(idB),
+                xBytes,
+                yBytes,
+                edEncode(k)
+            ))
+        },
+    }
+}
+
+// HKDF-SHA256(ikm = password, info = "SPAKE2 pw") to 48 bytes, big endian mod L
+async function passwordScalar(password) {
+    const ikm = await crypto.subtle.importKey('raw', password, 'HKDF', false, ['deriveBits'])
+    const bits = await crypto.subtle.deriveBits(
+        { name: 'HKDF', hash: 'SHA-256', salt: new Uint8Array(0), info: textEncoder.encode('SPAKE2 pw') },
+        ikm,
+        384
+    )
+    return bytesToBigInt(new Uint8Array(bits)) % ED_L
+}
+
+// 64 random bytes mod L, like curve25519-dalek's Scalar::random
+function randomScalar() {
+    const bytes = crypto.getRandomValues(new Uint8Array(64))
+    return bytesToBigInt(bytes.reverse()) % ED_L
+}
+
+const edMod = (a) => ((a % ED_P) + ED_P) % ED_P
+
+// Only for public exponents, inversion and square roots
+function edPow(base, exponent) {
+    let result = 1n
+    base = edMod(base)
+    while (exponent > 0n) {
+        if (exponent & 1n) result = result * base % ED_P
+        base = base * base % ED_P
+        exponent >>= 1n
+    }
+    return result
+}
+
+// add-2008-hwcd-3, complete on Ed25519 so it also doubles
+function edAdd([x1, y1, z1, t1], [x2, y2, z2, t2]) {
+    const a = edMod((y1 - x1) * (y2 - x2))
+    const b = edMod((y1 + x1) * (y2 + x2))
+    const c = edMod(2n * ED_D * t1 * t2)
+    const d = edMod(2n * z1 * z2)
+    const e = b - a
+    const f = d - c
+    const g = d + c
+    const h = b + a
+    return [edMod(e * f), edMod(g * h), edMod(f * g), edMod(e * h)]
+}
+
+function edNegate([x, y, z, t]) {
+    return [edMod(-x), y, z, edMod(-t)]
+}
+
+// Montgomery ladder over all 253 scalar bits with a masked swap, the same
+// additions run whatever the scalar. BigInt itself isn't constant time, this
+// only removes the scalar dependent branches of double and add
+function edMultiply(point, scalar) {
+    let r0 = [0n, 1n, 1n, 0n]
+    let r1 = point
+    for (let i = 252; i >= 0; i--) {
+        const bit = (scalar >> BigInt(i)) & 1n;
+        [r0, r1] = edSwap(r0, r1, bit)
+        r1 = edAdd(r0, r1)
+        r0 = edAdd(r0, r0);
+        [r0, r1] = edSwap(r0, r1, bit)
+    }
+    return r0
+}
+
+function edSwap(p, q, bit) {
+    const mask = -bit
+    const t = p.map((v, i) => mask & (v ^ q[i]))
+    return [p.map((v, i) => v ^ t[i]), q.map((v, i) => v ^ t[i])]
+}
+
+// Little endian y with the sign of x in the top bit
+function edEncode([x, y, z]) {
+    const zInv = edPow(z, ED_P - 2n)
+    const bytes = bigIntToBytes(edMod(y * zInv), 32).reverse()
+    bytes[31] |= Number(edMod(x * zInv) & 1n) << 7
+    return bytes
+}
+
+// Same as curve25519-dalek's decompress, RFC 8032 5.1.3 without the
+// canonical encoding checks
+function edDecode(bytes) {
+    if (bytes.length !== 32) {
+        throw new Error('Invalid passphrase exchange response')
+    }
+    const sign = BigInt(bytes[31] >> 7)
+    const y = edMod(bytesToBigInt(bytes.slice().reverse()) & ((1n << 255n) - 1n))
+    const u = edMod(y * y - 1n)
+    const v = edMod(ED_D * y * y + 1n)
+
+    let x = edMod(u * edPow(v, 3n) * edPow(u * edPow(v, 7n), (ED_P - 5n) / 8n))
+    const check = edMod(v * x * x)
+    if (check === edMod(-u)) {
+        x = edMod(x * ED_SQRT_M1)
+    } else if (check !== u) {
+        throw new Error('Invalid passphrase exchange response')
+    }
+    if ((x & 1n) !== sign) {
+        x = edMod(-x)
+    }
+    return [x, y, 1n, edMod(x * y)]
+}
+
+function hexBytes(hex) {
+    return Uint8Array.from(hex.match(/../g), (h) => parseInt(h, 16))
+}
+
+function bytesToBigInt(bytes) {
+    let hex = ''
+    for (const b of bytes) hex += b.toString(16).padStart(2, '0')
+    return hex ? BigInt('0x' + hex) : 0n
+}
+
+// Fixed size big endian
+function bigIntToBytes(value, length) {
+    return hexBytes(value.toString(16).padStart(length * 2, '0'))
+}
+
+// u64 big endian length
+function lengthPrefix(length) {
+    const bytes = new Uint8Array(8)
+    new DataView(bytes.buffer).setBigUint64(0, BigInt(length), false)
+    return bytes
+}
+
+async function sha256(data) {
+    return new Uint8Array(await crypto.subtle.digest('SHA-256', data))
+}
+
+async function hmac(keyData, label) {
+    const key = await crypto.subtle.importKey(
+        'raw',
+        keyData,
+        { name: 'HMAC', hash: 'SHA-256' },
+        false,
+        ['sign']
+    )
+    return new Uint8Array(await crypto.subtle.sign('HMAC', key, textEncoder.encode(label)))
+}
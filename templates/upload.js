@@ -100,20 +100,22 @@ function removeFile(index) {
 //===========
 // LOGIC
 //==========
-async function sendManifest(token, files) {
+// Paths and sizes are sealed, the server answers with one opaque id per file
+async function sendManifest(token, files, fileKeys) {
     const manifest = {
         files: files.map(file => ({
             relative_path: file.webkitRelativePath || file.name,
             size: file.size
-        }))
+        })),
+        suite: fileKeys.suite
     };
+    const metadataCipher = await deriveMetadataCipher(fileKeys, 'receive')
 
-    const clientId = getClientId();
-    const url = `/receive/${token}/manifest?clientId=${clientId}`;
+    const url = `/receive/${token}/manifest`;
     const response = await fetch(url, {
         method: 'POST',
         headers: { 'Content-Type': 'application/json' },
-        body: JSON.stringify(manifest)
+        body: JSON.stringify({ manifest: await sealJson(metadataCipher, manifest) })
     });
 
     if (!response.ok) {
@@ -140,20 +142,25 @@ async function uploadFiles(selectedFiles) {
     })
 
     try {
-        const { key } = await getCredentialsFromUrl()
+        const { fileKeys } = await getCredentialsFromUrl()
         const token = window.location.pathname.split('/').pop()
 
+        const claimResponse = await claimSession('receive', token)
+        if (!claimResponse.ok) {
+            throw new Error(`Failed to claim session: HTTP ${claimResponse.status}`)
+        }
+
         // Send manifest first so server knows total chunks
-        await sendManifest(token, selectedFiles);
+        const { file_ids: fileIds } = await sendManifest(token, selectedFiles, fileKeys);
 
         await runWithConcurrency(
-            selectedFiles.map((file, index) => ({ file, index, fileItem: fileItems[index] })),
-            async ({ file, fileItem }) => {
+            selectedFiles.map((file, index) => ({ file, fileId: fileIds[index], fileItem: fileItems[index] })),
+            async ({ file, fileId, fileItem }) => {
                 const relativePath = file.webkitRelativePath || file.name
                 
                 fileItem.classList.add('uploading')
                 try {
-                    await uploadFile(file, relativePath,token, key, fileItem)
+                    await uploadFile(file, relativePath, fileId, token, fileKeys, fileItem)
                     fileItem.classList.remove('uploading')
                     fileItem.classList.add('completed')
                 } catch (error) {
@@ -165,8 +172,7 @@ async function uploadFiles(selectedFiles) {
             MAX_CONCURRENT_FILES
         )
 
-        const clientId = getClientId()
-        await fetch(`/receive/${token}/complete?clientId=${clientId}`, { method: 'POST' })
+        await fetch(`/receive/${token}/complete`, { method: 'POST' })
 
         uploadBtn.textContent = 'Upload Complete!'
 
@@ -178,10 +184,12 @@ async function uploadFiles(selectedFiles) {
     }
 }
 
-async function uploadFile(file, relativePath, token, key, fileItem) {
-    // each file gets its own nonce
-    const fileNonce = crypto.getRandomValues(new Uint8Array(7));
-    const totalChunks = Math.ceil(file.size / CHUNK_SIZE)
+async function uploadFile(file, relativePath, fileId, token, fileKeys, fileItem) {
+    // each file gets its own nonce and key
+    const cipher = await deriveFileCipher(fileKeys, 'receive', relativePath)
+    const fileNonce = generateNonceBase(cipher)
+    // Empty files still upload one (empty) chunk, the server expects it
+    const totalChunks = Math.max(1, Math.ceil(file.size / CHUNK_SIZE))
 
     console.log(`Uploading: ${relativePath} (${totalChunks} chunks)`);
 
@@ -197,22 +205,14 @@ async function uploadFile(file, relativePath, token, key, fileItem) {
             const chunkData = await chunkBlob.arrayBuffer()
 
             // Encrypt chunk
-            const nonce = generateNonce(fileNonce, chunkIndex)
-            const encrypted = await crypto.subtle.encrypt(
-                { name: 'AES-GCM', iv: nonce },
-                key,
-                chunkData
-            )
-
-            // Create FormData with chunk and metadata
+            const last = chunkIndex === totalChunks - 1
+            const encrypted = await encryptChunk(cipher, fileNonce, chunkData, chunkIndex, last)
+
+            // Path and size went with the sealed manifest, only the id goes here
             const formData = new FormData()
             formData.append('chunk', new Blob([encrypted]))
-            formData.append('relativePath', relativePath)
-            formData.append('fileName', file.name)
+            formData.append('fileId', fileId)
             formData.append('chunkIndex', chunkIndex.toString())
-            formData.append('totalChunks', totalChunks.toString())
-            formData.append('fileSize', file.size.toString())
-            formData.append('clientId', getClientId())  // ← FIX: Add clientId to FormData
 
             if (chunkIndex === 0) {
                 const nonceBase64 = arrayBufferToBase64(fileNonce)
@@ -229,15 +229,14 @@ async function uploadFile(file, relativePath, token, key, fileItem) {
         MAX_CONCURRENT
     )    // Finalize (merge chunks)
 
-    await finalizeFile(token, relativePath);
+    await finalizeFile(token, fileId, relativePath);
 
     const progressText = fileItem.querySelector('.progress-text')
     if (progressText) progressText.textContent = 'Upload complete!'
 }
 
 async function uploadChunk(token, formData, chunkIndex, relativePath) {
-    const clientId = getClientId()
-    const url = `/receive/${token}/chunk?clientId=${clientId}`
+    const url = `/receive/${token}/chunk`
 
     return await retryWithExponentialBackoff(async () => {
         const response = await fetch(url, {
@@ -246,7 +245,7 @@ async function uploadChunk(token, formData, chunkIndex, relativePath) {
         })
 
         if (!response.ok) {
-            throw new Error(`HTTP ${response.status}`)
+            throw httpError(response)
         }
         
         // Log success (optional, can remove for production)
@@ -255,12 +254,11 @@ async function uploadChunk(token, formData, chunkIndex, relativePath) {
     }, 3, `chunk ${chunkIndex}`)
 }
 
-async function finalizeFile(token, relativePath) {
+async function finalizeFile(token, fileId, relativePath) {
     const formData = new FormData();
-    formData.append('relativePath', relativePath);
+    formData.append('fileId', fileId);
     
-    const clientId = getClientId()
-    const url = `/receive/${token}/finalize?clientId=${clientId}`
+    const url = `/receive/${token}/finalize`
     const response = await fetch(url, {
         method: 'POST',
         body: formData
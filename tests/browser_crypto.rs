@@ -0,0 +1,378 @@
+//! templates/shared.js against the Rust implementation, run under node
+//!
+//! Skipped with a note when node isn't installed.
+
+use archdrop::config::CHUNK_SIZE;
+use archdrop::crypto::code::TransferCode;
+use archdrop::crypto::kdf::{self, Direction};
+use archdrop::crypto::types::EncryptionKey;
+use archdrop::crypto::types::Nonce;
+use archdrop::crypto::{self, Cipher, CipherSuite};
+use archdrop::server::Session;
+use base64::{engine::general_purpose, Engine};
+use rand::RngCore;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+/// shared.js in a node process, see tests/js/shared_harness.js
+struct SharedJs {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl SharedJs {
+    fn start() -> Option<Self> {
+        let harness = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/js/shared_harness.js");
+        let mut child = match Command::new("node")
+            .arg(harness)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(e) => {
+                eprintln!("Skipping shared.js checks, node not available: {}", e);
+                return None;
+            }
+        };
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Some(Self {
+            child,
+            stdin,
+            stdout,
+        })
+    }
+
+    fn send(&mut self, value: Value) {
+        writeln!(self.stdin, "{}", value).unwrap();
+    }
+
+    fn receive(&mut self) -> Value {
+        let mut line = String::new();
+        self.stdout.read_line(&mut line).unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    fn call(&mut self, function: &str, args: Value) -> Result<Value, String> {
+        self.send(json!({ "call": function, "args": args }));
+        result(self.receive())
+    }
+}
+
+// The call's result, or its error message as Err
+fn result(reply: Value) -> Result<Value, String> {
+    match reply.get("error") {
+        Some(error) => Err(error.as_str().unwrap_or_default().to_string()),
+        None => Ok(reply["result"].clone()),
+    }
+}
+
+impl Drop for SharedJs {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+    }
+}
+
+// runPake with its requests answered by `session`
+fn run_pake(js: &mut SharedJs, session: &Session, passphrase: &str) -> Result<Value, String> {
+    let b64 = general_purpose::URL_SAFE_NO_PAD;
+    js.send(json!({
+        "call": "runPake",
+        "args": ["receive", session.token(), passphrase],
+    }));
+
+    loop {
+        let request = js.receive();
+        let Some(url) = request.get("fetch").and_then(Value::as_str) else {
+            return result(request);
+        };
+        let body = &request["body"];
+
+        let reply = if url.ends_with("/pake") {
+            let message = b64.decode(body["message"].as_str().unwrap()).unwrap();
+            let (exchange_id, response) = session.pake_respond(session.token(), &message).unwrap();
+            json!({
+                "status": 200,
+                "body": { "exchangeId": exchange_id, "message": b64.encode(response) },
+            })
+        } else if url.ends_with("/pake/confirm") {
+            let confirmation = b64.decode(body["confirmation"].as_str().unwrap()).unwrap();
+            match session.pake_confirm(
+                session.token(),
+                body["exchangeId"].as_str().unwrap(),
+                &confirmation,
+            ) {
+                Ok(wrapped) => json!({ "status": 200, "body": { "key": b64.encode(wrapped) } }),
+                Err(_) => json!({ "status": 403, "body": null }),
+            }
+        } else {
+            panic!("Unexpected request to {}", url);
+        };
+        js.send(reply);
+    }
+}
+
+// python-spake2's interop vectors, also pinned in src/crypto/pake.rs
+const SPAKE2_VECTORS: &str = "async (password, idA, idB, serverMessage) => {
+    const spake = await spake2Start(password, idA, idB,
+        2611694063369306139794446498317402240796898290761098242657700742213257926693n)
+    return [spake.message, await spake.finish(serverMessage)]
+}";
+
+#[test]
+fn js_spake2_matches_python_vectors() {
+    let Some(mut js) = SharedJs::start() else {
+        return;
+    };
+    let result = js
+        .call(
+            SPAKE2_VECTORS,
+            json!([
+                format!("hex:{}", hex::encode("password")),
+                format!("hex:{}", hex::encode("idA")),
+                format!("hex:{}", hex::encode("idB")),
+                "hex:42354e97b88406922b1df4bea1d7870f17aed3dba7c720b313edae315b00959309",
+            ]),
+        )
+        .unwrap();
+    assert_eq!(
+        result,
+        json!([
+            "416fc960df73c9cf8ed7198b0c9534e2e96a5984bfc5edc023fd24dacf371f2af9",
+            "712295de7219c675ddd31942184aa26e0a957cf216bc230d165b215047b520c1",
+        ])
+    );
+}
+
+#[test]
+fn js_pake_unlocks_rust_session() {
+    let Some(mut js) = SharedJs::start() else {
+        return;
+    };
+    let key = EncryptionKey::new();
+    let session =
+        Session::new_receive(PathBuf::from("."), key.clone(), 0).with_passphrase("guitar-orbit");
+
+    let unwrapped = run_pake(&mut js, &session, "guitar-orbit").unwrap();
+    assert_eq!(unwrapped, hex::encode(key.as_bytes()));
+}
+
+#[test]
+fn js_pake_wrong_passphrase() {
+    let Some(mut js) = SharedJs::start() else {
+        return;
+    };
+    let session = Session::new_receive(PathBuf::from("."), EncryptionKey::new(), 0)
+        .with_passphrase("guitar-orbit");
+
+    let error = run_pake(&mut js, &session, "guitar-orbot").unwrap_err();
+    assert!(error.starts_with("Wrong passphrase"), "{}", error);
+}
+
+#[test]
+fn js_normalizes_passphrases_like_rust() {
+    let Some(mut js) = SharedJs::start() else {
+        return;
+    };
+    let uuid = "5f0c6f7e-93a4-4c3e-9d8a-0b1e2c3d4e5f";
+    for (token, input) in [
+        ("7", "guitar-orbit"),
+        ("7", "Guitar Orbit"),
+        ("7", " 7 GUITAR--orbit\n"),
+        ("7", "07-guitar-orbit"),
+        ("7", "8-guitar-orbit"),
+        ("12", "12"),
+        (uuid, "Correct Horse"),
+        (uuid, " spaced out "),
+    ] {
+        let js_result = js
+            .call("normalizePassphrase", json!([input, token]))
+            .unwrap();
+        assert_eq!(
+            js_result,
+            TransferCode::normalize_passphrase(token, input),
+            "{:?} on {}",
+            input,
+            token
+        );
+    }
+}
+
+// Keys as deriveFileCipher and deriveMetadataCipher derive them, from a raw session key
+const HKDF_KEYS: &str = "async raw => ({
+    key: await crypto.subtle.importKey('raw', raw, 'HKDF', false, ['deriveBits']),
+})";
+
+#[test]
+fn js_derives_the_same_keys_as_rust() {
+    let Some(mut js) = SharedJs::start() else {
+        return;
+    };
+    let session_key = EncryptionKey::new();
+    let raw = format!("hex:{}", hex::encode(session_key.as_bytes()));
+    let file_key = format!(
+        "async (raw, direction, fileId) => deriveKey(await ({})(raw), FILE_KEY_LABEL, [direction, fileId])",
+        HKDF_KEYS
+    );
+    let metadata_key = format!(
+        "async (raw, direction) => deriveKey(await ({})(raw), METADATA_KEY_LABEL, [direction])",
+        HKDF_KEYS
+    );
+
+    // download.js passes the manifest index as a number
+    let send = js.call(&file_key, json!([raw, "send", 3])).unwrap();
+    let expected = kdf::file_key(&session_key, Direction::Send, "3");
+    assert_eq!(send, hex::encode(expected.as_bytes()));
+
+    // upload.js passes the relative path
+    let receive = js
+        .call(&file_key, json!([raw, "receive", "docs/a b.txt"]))
+        .unwrap();
+    let expected = kdf::file_key(&session_key, Direction::Receive, "docs/a b.txt");
+    assert_eq!(receive, hex::encode(expected.as_bytes()));
+
+    for (direction, name) in [(Direction::Send, "send"), (Direction::Receive, "receive")] {
+        let metadata = js.call(&metadata_key, json!([raw, name])).unwrap();
+        let expected = kdf::metadata_key(&session_key, direction);
+        assert_eq!(metadata, hex::encode(expected.as_bytes()));
+    }
+}
+
+#[test]
+fn js_chacha_matches_published_vectors() {
+    let Some(mut js) = SharedJs::start() else {
+        return;
+    };
+    let vectors: Value = serde_json::from_str(include_str!("vectors/aead.json")).unwrap();
+    let hex_arg =
+        |name: &str, field: &str| format!("hex:{}", vectors[name][field].as_str().unwrap());
+
+    for (name, seal, open) in [
+        ("chacha20-poly1305", "chachaSeal", "chachaOpen"),
+        ("xchacha20-poly1305", "xchachaSeal", "xchachaOpen"),
+    ] {
+        let (key, nonce, aad) = (
+            hex_arg(name, "key"),
+            hex_arg(name, "nonce"),
+            hex_arg(name, "aad"),
+        );
+        let sealed = js
+            .call(seal, json!([key, nonce, hex_arg(name, "plaintext"), aad]))
+            .unwrap();
+        assert_eq!(sealed, vectors[name]["ciphertext"], "{}", seal);
+
+        let opened = js
+            .call(open, json!([key, nonce, hex_arg(name, "ciphertext"), aad]))
+            .unwrap();
+        assert_eq!(opened, vectors[name]["plaintext"], "{}", open);
+
+        let error = js
+            .call(
+                open,
+                json!([key, nonce, hex_arg(name, "ciphertext"), "hex:00"]),
+            )
+            .unwrap_err();
+        assert_eq!(error, "Decryption failed");
+    }
+}
+
+// encryptChunk and decryptChunk under a raw file key
+const ENCRYPT_CHUNK: &str = "async (suite, key, nonceBase, data, counter, last) =>
+    encryptChunk(await suiteCipher(suite, key), nonceBase, data, counter, last)";
+const DECRYPT_CHUNK: &str = "async (suite, key, nonceBase, data, counter, last) =>
+    decryptChunk(await suiteCipher(suite, key), nonceBase, data, counter, last)";
+
+#[test]
+fn js_chunks_roundtrip_with_rust() {
+    let Some(mut js) = SharedJs::start() else {
+        return;
+    };
+    for suite in [CipherSuite::Aes256Gcm, CipherSuite::XChaCha20Poly1305] {
+        let key = EncryptionKey::new();
+        let cipher = Cipher::new(suite, &key);
+        let nonce = Nonce::new(suite);
+        let args = |data: &[u8], counter: u32, last: bool| {
+            json!([
+                suite.name(),
+                format!("hex:{}", hex::encode(key.as_bytes())),
+                format!("hex:{}", hex::encode(nonce.as_bytes())),
+                format!("hex:{}", hex::encode(data)),
+                counter,
+                last
+            ])
+        };
+
+        // Browser upload, Rust receive
+        let encrypted = js.call(ENCRYPT_CHUNK, args(b"upload", 3, true)).unwrap();
+        let encrypted = hex::decode(encrypted.as_str().unwrap()).unwrap();
+        let plaintext = crypto::decrypt_chunk_at_position(&cipher, &nonce, &encrypted, 3, true);
+        assert_eq!(plaintext.unwrap(), b"upload", "{}", suite);
+        assert!(crypto::decrypt_chunk_at_position(&cipher, &nonce, &encrypted, 3, false).is_err());
+
+        // Rust send, browser download
+        let encrypted =
+            crypto::encrypt_chunk_at_position(&cipher, &nonce, b"download", 5, false).unwrap();
+        let plaintext = js.call(DECRYPT_CHUNK, args(&encrypted, 5, false)).unwrap();
+        assert_eq!(plaintext, hex::encode(b"download"), "{}", suite);
+        assert!(js.call(DECRYPT_CHUNK, args(&encrypted, 4, false)).is_err());
+    }
+}
+
+// Full size chunks run Poly1305 and the block counter far past the vectors
+#[test]
+fn js_full_chunks_roundtrip_with_rust() {
+    let Some(mut js) = SharedJs::start() else {
+        return;
+    };
+    let mut file = vec![0u8; CHUNK_SIZE as usize + 1000];
+    rand::rngs::OsRng.fill_bytes(&mut file);
+    let chunks: Vec<&[u8]> = file.chunks(CHUNK_SIZE as usize).collect();
+
+    for suite in [CipherSuite::Aes256Gcm, CipherSuite::XChaCha20Poly1305] {
+        let key = EncryptionKey::new();
+        let cipher = Cipher::new(suite, &key);
+        let nonce = Nonce::new(suite);
+        let args = |data: &[u8], counter: usize| {
+            json!([
+                suite.name(),
+                format!("hex:{}", hex::encode(key.as_bytes())),
+                format!("hex:{}", hex::encode(nonce.as_bytes())),
+                format!("hex:{}", hex::encode(data)),
+                counter,
+                counter == chunks.len() - 1
+            ])
+        };
+
+        for (counter, chunk) in chunks.iter().enumerate() {
+            let last = counter == chunks.len() - 1;
+
+            // Browser upload, Rust receive
+            let encrypted = js.call(ENCRYPT_CHUNK, args(chunk, counter)).unwrap();
+            let encrypted = hex::decode(encrypted.as_str().unwrap()).unwrap();
+            let plaintext = crypto::decrypt_chunk_at_position(
+                &cipher,
+                &nonce,
+                &encrypted,
+                counter as u32,
+                last,
+            );
+            assert!(plaintext.unwrap() == *chunk, "{} chunk {}", suite, counter);
+
+            // Rust send, browser download
+            let encrypted =
+                crypto::encrypt_chunk_at_position(&cipher, &nonce, chunk, counter as u32, last)
+                    .unwrap();
+            let plaintext = js.call(DECRYPT_CHUNK, args(&encrypted, counter)).unwrap();
+            assert!(
+                plaintext == hex::encode(chunk),
+                "{} chunk {}",
+                suite,
+                counter
+            );
+        }
+    }
+}
@@ -0,0 +1,70 @@
+// Runs templates/shared.js under node for tests/browser_crypto.rs
+//
+// Reads one JSON call per line, {"call": "runPake", "args": [...]}, and
+// answers {"result": ...} or {"error": "..."}. Strings starting with "hex:"
+// become Uint8Arrays and Uint8Array results come back as hex. fetch() is
+// forwarded the same way, {"fetch": url, "body": {...}}, and the test
+// replies {"status": 200, "body": {...}}.
+
+const fs = require('fs')
+const path = require('path')
+const readline = require('readline')
+const vm = require('vm')
+
+const source = fs
+    .readFileSync(path.join(__dirname, '../../templates/shared.js'), 'utf8')
+    .replace('__CHUNK_SIZE__', String(1024 * 1024))
+// Page setup waits for DOMContentLoaded, which never fires here
+globalThis.document = { addEventListener() {} }
+vm.runInThisContext(source)
+
+const lines = readline.createInterface({ input: process.stdin })[Symbol.asyncIterator]()
+
+function send(value) {
+    process.stdout.write(JSON.stringify(value) + '\n')
+}
+
+async function receive() {
+    const { value, done } = await lines.next()
+    return done ? null : JSON.parse(value)
+}
+
+function decode(value) {
+    if (typeof value === 'string' && value.startsWith('hex:')) {
+        return Uint8Array.from(Buffer.from(value.substring(4), 'hex'))
+    }
+    return Array.isArray(value) ? value.map(decode) : value
+}
+
+function encode(value) {
+    if (value instanceof Uint8Array) {
+        return Buffer.from(value).toString('hex')
+    }
+    if (Array.isArray(value)) {
+        return value.map(encode)
+    }
+    return typeof value === 'bigint' ? value.toString(16) : value
+}
+
+globalThis.fetch = async (url, init) => {
+    send({ fetch: url, body: JSON.parse(init.body) })
+    const reply = await receive()
+    return {
+        ok: reply.status >= 200 && reply.status < 300,
+        status: reply.status,
+        json: async () => reply.body,
+    }
+}
+
+async function main() {
+    for (let request = await receive(); request; request = await receive()) {
+        try {
+            const fn = vm.runInThisContext(request.call)
+            send({ result: encode(await fn(...decode(request.args))) })
+        } catch (error) {
+            send({ error: error.message })
+        }
+    }
+}
+
+main()
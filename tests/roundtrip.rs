@@ -0,0 +1,158 @@
+//! End to end transfers through the test servers and the native clients
+
+use archdrop::client::{collect_files, DownloadClient, ShareLink, UploadClient};
+use archdrop::crypto::types::EncryptionKey;
+use archdrop::crypto::CipherSuite;
+use archdrop::server::{
+    start_receive_server_for_test, start_send_server_for_test, start_send_session_for_test,
+    LocalConfig, ServerMode, Session,
+};
+use archdrop::transfer::manifest::Manifest;
+use archdrop::tunnel::CloudflareTunnel;
+use std::path::Path;
+
+// Spans three chunks, the last one short
+const FILE_SIZE: usize = 2_500_000;
+
+fn write_source(dir: &Path) -> std::path::PathBuf {
+    let path = dir.join("data.bin");
+    let contents: Vec<u8> = (0..FILE_SIZE).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+async fn send_roundtrip(suite: CipherSuite) {
+    let dir = tempfile::tempdir().unwrap();
+    let source = write_source(dir.path());
+
+    let manifest = Manifest::new(vec![source.clone()], None, suite)
+        .await
+        .unwrap();
+    let key = EncryptionKey::new();
+    let (port, session) = start_send_server_for_test(
+        manifest,
+        key.clone(),
+        ServerMode::Local(LocalConfig::default()),
+    )
+    .await
+    .unwrap();
+
+    // The test server's certificate isn't pinned in the link
+    let link = ShareLink::new(
+        &format!("https://127.0.0.1:{}", port),
+        "send",
+        session.token(),
+        key,
+        suite,
+    )
+    .with_insecure(true);
+
+    let destination = dir.path().join("out");
+    let files = DownloadClient::new(link)
+        .unwrap()
+        .download_all(&destination)
+        .await
+        .unwrap();
+
+    assert_eq!(files.len(), 1);
+    assert_eq!(
+        std::fs::read(&files[0]).unwrap(),
+        std::fs::read(&source).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn send_roundtrip_aes() {
+    send_roundtrip(CipherSuite::Aes256Gcm).await;
+}
+
+#[tokio::test]
+async fn send_roundtrip_xchacha() {
+    send_roundtrip(CipherSuite::XChaCha20Poly1305).await;
+}
+
+#[tokio::test]
+async fn max_downloads_turns_away_the_second_client() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = write_source(dir.path());
+
+    let suite = CipherSuite::default();
+    let manifest = Manifest::new(vec![source], None, suite).await.unwrap();
+    let key = EncryptionKey::new();
+    let total_chunks = manifest.total_chunks();
+    let session =
+        Session::new_send(manifest, key.clone(), total_chunks).with_max_downloads(Some(1));
+    let (port, session) =
+        start_send_session_for_test(session, ServerMode::Local(LocalConfig::default()))
+            .await
+            .unwrap();
+    let link = || {
+        ShareLink::new(
+            &format!("https://127.0.0.1:{}", port),
+            "send",
+            session.token(),
+            key.clone(),
+            suite,
+        )
+        .with_insecure(true)
+    };
+
+    // The first client never reports completion, its claim is the download
+    let first = DownloadClient::new(link()).unwrap();
+    let manifest = first.claim().await.unwrap();
+    first.fetch_chunk(&manifest.files[0], 0).await.unwrap();
+
+    let error = DownloadClient::new(link())
+        .unwrap()
+        .claim()
+        .await
+        .err()
+        .expect("a second client claimed past the limit");
+    assert!(
+        error.to_string().contains("no longer available"),
+        "{:#}",
+        error
+    );
+
+    // The first client's download carries on
+    first.fetch_chunk(&manifest.files[0], 1).await.unwrap();
+}
+
+#[tokio::test]
+async fn receive_roundtrip() {
+    let dir = tempfile::tempdir().unwrap();
+    let source = write_source(dir.path());
+    let destination = dir.path().join("received");
+    std::fs::create_dir_all(&destination).unwrap();
+
+    // Tunnel mode serves plain HTTP, the tunnel itself isn't started
+    let key = EncryptionKey::new();
+    let (port, session) = start_receive_server_for_test(
+        destination.clone(),
+        key.clone(),
+        ServerMode::Tunnel(Box::new(CloudflareTunnel::new())),
+    )
+    .await
+    .unwrap();
+
+    let suite = CipherSuite::default();
+    let link = ShareLink::new(
+        &format!("http://127.0.0.1:{}", port),
+        "receive",
+        session.token(),
+        key,
+        suite,
+    );
+
+    let files = collect_files(std::slice::from_ref(&source)).unwrap();
+    UploadClient::new(link)
+        .unwrap()
+        .upload_all(&files)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        std::fs::read(destination.join("data.bin")).unwrap(),
+        std::fs::read(&source).unwrap()
+    );
+}